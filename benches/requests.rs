@@ -0,0 +1,41 @@
+//! Requests through `FinchersService`, from the routing to the serialized response.
+
+#[macro_use]
+extern crate criterion;
+extern crate finchers;
+extern crate futures;
+extern crate hyper;
+extern crate petstore;
+extern crate tokio_core;
+
+use criterion::Criterion;
+use finchers::responder::DefaultResponder;
+use finchers::service::FinchersService;
+use futures::{Future, Stream};
+use hyper::{Method, Request};
+use hyper::server::Service;
+use tokio_core::reactor::Core;
+use petstore::{api, fixtures};
+
+fn bench_requests(c: &mut Criterion) {
+    let store = fixtures::build(fixtures::generate(42, 10_000));
+    let service = FinchersService::new(api::endpoint(), store, DefaultResponder::default());
+
+    for &(name, uri) in &[
+        ("GET /pet/{id}", "/pet/4242"),
+        ("GET /pet/findByStatus", "/pet/findByStatus?status=pending"),
+    ] {
+        let service = service.clone();
+        c.bench_function(name, move |b| {
+            let mut core = Core::new().unwrap();
+            b.iter(|| {
+                let request = Request::new(Method::Get, uri.parse().unwrap());
+                let response = service.call(request).and_then(|response| response.body().concat2());
+                core.run(response).unwrap()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_requests);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+//! Operations of `Petstore` on stores of various sizes, populated with generated fixtures.
+
+#[macro_use]
+extern crate criterion;
+extern crate chrono;
+extern crate petstore;
+
+use std::collections::HashMap;
+use chrono::{Duration, Utc};
+use criterion::Criterion;
+use petstore::fixtures;
+use petstore::model::Pet;
+use petstore::petstore::Petstore;
+
+/// The numbers of pets in the stores; they also hold a user for every ten pets.
+const SIZES: &'static [usize] = &[1_000, 100_000, 1_000_000];
+
+fn stores() -> HashMap<usize, Petstore> {
+    SIZES
+        .iter()
+        .map(|&size| (size, fixtures::build(fixtures::generate(42, size))))
+        .collect()
+}
+
+fn new_pet() -> Pet {
+    let mut pet = fixtures::generate(7, 1).pets.remove(0);
+    pet.status = None;
+    pet
+}
+
+fn bench_store(c: &mut Criterion) {
+    let stores = stores();
+
+    // The pet added by the previous iteration is removed for good before each one, so that
+    // every iteration adds the pet with the ID `size` to a store of `size` pets.
+    let s = stores.clone();
+    let pet = new_pet();
+    c.bench_function_over_inputs(
+        "add_pet",
+        move |b, &&size| {
+            let store = &s[&size];
+            b.iter_with_setup(
+                || {
+                    if store.delete_pet(size as u64, None).is_ok() {
+                        store.purge_trash(Utc::now() + Duration::days(1)).unwrap();
+                    }
+                    pet.clone()
+                },
+                |pet| store.add_pet(pet).unwrap(),
+            )
+        },
+        SIZES,
+    );
+
+    let s = stores.clone();
+    c.bench_function_over_inputs(
+        "find_pets_by_tag",
+        move |b, &&size| b.iter(|| s[&size].find_pets_by_tag(vec!["friendly".into()], &Default::default()).unwrap()),
+        SIZES,
+    );
+
+    let s = stores.clone();
+    c.bench_function_over_inputs(
+        "get_inventory",
+        move |b, &&size| b.iter(|| s[&size].get_inventory().unwrap()),
+        SIZES,
+    );
+
+    // The last user, as users are looked up by scanning them.
+    let usernames: HashMap<_, _> = stores
+        .iter()
+        .map(|(&size, store)| (size, store.list_users().unwrap().pop().unwrap().username))
+        .collect();
+    let s = stores;
+    c.bench_function_over_inputs(
+        "get_user",
+        move |b, &&size| b.iter(|| s[&size].get_user(usernames[&size].clone()).unwrap()),
+        SIZES,
+    );
+}
+
+criterion_group!(benches, bench_store);
+criterion_main!(benches);
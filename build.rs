@@ -0,0 +1,36 @@
+//! Record the build information reported by `GET /version`.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    let sha = Command::new("git")
+        .args(&["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .and_then(|output| {
+            if output.status.success() {
+                String::from_utf8(output.stdout).ok()
+            } else {
+                None
+            }
+        })
+        .map(|sha| sha.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=PETSTORE_GIT_SHA={}", sha);
+
+    let mut features: Vec<_> = env::vars()
+        .filter_map(|(key, _)| {
+            if key.starts_with("CARGO_FEATURE_") {
+                Some(key["CARGO_FEATURE_".len()..].to_lowercase().replace('_', "-"))
+            } else {
+                None
+            }
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=PETSTORE_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
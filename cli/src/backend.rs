@@ -0,0 +1,370 @@
+//! The two ways of reaching a store: through a running server, or directly in its snapshot file.
+
+use std::path::PathBuf;
+use futures::Future;
+use petstore::Petstore;
+use petstore::bulk::{self, BulkFormat, RowParser, RowResult};
+use petstore::model::{Inventory, Order, Pet, PublicUser, Status, User};
+use petstore_client::{self, Client};
+use tokio_core::reactor::Core;
+use errors::*;
+
+/// The number of entities fetched by each request when listing them.
+const PAGE_SIZE: usize = 100;
+
+pub trait Backend {
+    fn add_pet(&mut self, pet: Pet) -> Result<u64>;
+    fn get_pet(&mut self, id: u64) -> Result<Option<Pet>>;
+    /// Find the pets with one of `statuses`, or all pets if empty.
+    fn find_pets(&mut self, statuses: &[Status]) -> Result<Vec<Pet>>;
+    fn delete_pet(&mut self, id: u64) -> Result<()>;
+
+    fn add_order(&mut self, order: Order) -> Result<u64>;
+    fn get_order(&mut self, id: u64) -> Result<Option<Order>>;
+    fn list_orders(&mut self) -> Result<Vec<Order>>;
+    fn delete_order(&mut self, id: u64) -> Result<bool>;
+
+    fn add_user(&mut self, user: User) -> Result<String>;
+    fn get_user(&mut self, username: &str) -> Result<Option<User>>;
+    fn list_users(&mut self) -> Result<Vec<PublicUser>>;
+    /// Delete a user, logging in as them with `password` if the backend requires their session.
+    fn delete_user(&mut self, username: &str, password: Option<&str>) -> Result<()>;
+
+    fn import_pets(&mut self, format: BulkFormat, data: Vec<u8>) -> Result<Vec<RowResult>>;
+    fn export_pets(&mut self, format: BulkFormat) -> Result<Vec<u8>>;
+    fn inventory(&mut self) -> Result<Inventory>;
+    /// Return the inconsistencies found in the store.
+    fn check(&mut self) -> Result<Vec<String>>;
+}
+
+/// A running server, reached over HTTP.
+pub struct Remote {
+    core: Core,
+    client: Client,
+}
+
+impl Remote {
+    pub fn new(url: &str, api_key: Option<&str>) -> Result<Self> {
+        let core = Core::new()?;
+        let mut client = Client::new(url, &core.handle());
+        if let Some(api_key) = api_key {
+            client = client.api_key(api_key);
+        }
+        Ok(Remote { core, client })
+    }
+
+    fn run<T, F>(&mut self, future: F) -> Result<T>
+    where
+        F: Future<Item = T, Error = petstore_client::Error>,
+    {
+        self.core.run(future).map_err(Into::into)
+    }
+
+    /// Run `future`, turning a `404 Not Found` into `None`.
+    fn run_opt<T, F>(&mut self, future: F) -> Result<Option<T>>
+    where
+        F: Future<Item = T, Error = petstore_client::Error>,
+    {
+        match self.core.run(future) {
+            Ok(item) => Ok(Some(item)),
+            Err(petstore_client::Error(petstore_client::ErrorKind::NotFound, _)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Backend for Remote {
+    fn add_pet(&mut self, pet: Pet) -> Result<u64> {
+        let future = self.client.add_pet(&pet);
+        self.run(future)
+    }
+
+    fn get_pet(&mut self, id: u64) -> Result<Option<Pet>> {
+        let future = self.client.get_pet(id);
+        self.run_opt(future)
+    }
+
+    fn find_pets(&mut self, statuses: &[Status]) -> Result<Vec<Pet>> {
+        if !statuses.is_empty() {
+            let future = self.client.find_pets_by_status(statuses);
+            return self.run(future);
+        }
+        let data = self.export_pets(BulkFormat::Ndjson)?;
+        let mut parser = RowParser::new(BulkFormat::Ndjson);
+        let mut rows = parser.push(&data);
+        rows.extend(parser.finish());
+        rows.into_iter()
+            .map(|(_, pet)| pet.map_err(|e| ErrorKind::InvalidResponse(e).into()))
+            .collect()
+    }
+
+    fn delete_pet(&mut self, id: u64) -> Result<()> {
+        let future = self.client.delete_pet(id);
+        self.run(future)
+    }
+
+    fn add_order(&mut self, order: Order) -> Result<u64> {
+        let future = self.client.place_order(&order);
+        self.run(future)
+    }
+
+    fn get_order(&mut self, id: u64) -> Result<Option<Order>> {
+        let future = self.client.get_order(id);
+        self.run_opt(future)
+    }
+
+    fn list_orders(&mut self) -> Result<Vec<Order>> {
+        let mut orders = vec![];
+        loop {
+            let future = self.client.list_orders(orders.len(), PAGE_SIZE);
+            let (total, page) = self.run(future)?;
+            let done = page.is_empty() || orders.len() + page.len() >= total;
+            orders.extend(page);
+            if done {
+                return Ok(orders);
+            }
+        }
+    }
+
+    fn delete_order(&mut self, id: u64) -> Result<bool> {
+        let future = self.client.delete_order(id);
+        self.run(future)
+    }
+
+    fn add_user(&mut self, user: User) -> Result<String> {
+        let future = self.client.create_user(&user);
+        self.run(future)
+    }
+
+    fn get_user(&mut self, username: &str) -> Result<Option<User>> {
+        let future = self.client.get_user(username);
+        self.run_opt(future)
+    }
+
+    fn list_users(&mut self) -> Result<Vec<PublicUser>> {
+        let mut users = vec![];
+        loop {
+            let future = self.client.list_users(users.len(), PAGE_SIZE);
+            let (total, page) = self.run(future)?;
+            let done = page.is_empty() || users.len() + page.len() >= total;
+            users.extend(page);
+            if done {
+                return Ok(users);
+            }
+        }
+    }
+
+    fn delete_user(&mut self, username: &str, password: Option<&str>) -> Result<()> {
+        let client = match password {
+            Some(password) => {
+                let future = self.client.login(username, password);
+                let token = self.run(future)?;
+                self.client.clone().session(token)
+            }
+            None => self.client.clone(),
+        };
+        let future = client.delete_user(username);
+        self.run(future)
+    }
+
+    fn import_pets(&mut self, format: BulkFormat, data: Vec<u8>) -> Result<Vec<RowResult>> {
+        let future = self.client.import_pets(format, data);
+        self.run(future)
+    }
+
+    fn export_pets(&mut self, format: BulkFormat) -> Result<Vec<u8>> {
+        let future = self.client.export_pets(format);
+        self.run(future)
+    }
+
+    fn inventory(&mut self) -> Result<Inventory> {
+        let future = self.client.get_inventory();
+        self.run(future)
+    }
+
+    fn check(&mut self) -> Result<Vec<String>> {
+        let future = self.client.check();
+        self.run(future)
+    }
+}
+
+/// A snapshot file, which is saved after every modification.
+///
+/// It must not be modified while a server is running on it, as the server would overwrite it.
+pub struct Local {
+    store: Petstore,
+    path: PathBuf,
+}
+
+impl Local {
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let store = Petstore::load_snapshot(&path)?;
+        Ok(Local { store, path })
+    }
+
+    fn save<T>(&self, result: T) -> Result<T> {
+        self.store.save_snapshot(&self.path)?;
+        Ok(result)
+    }
+}
+
+impl Backend for Local {
+    fn add_pet(&mut self, pet: Pet) -> Result<u64> {
+        let id = self.store.add_pet(pet)?;
+        self.save(id)
+    }
+
+    fn get_pet(&mut self, id: u64) -> Result<Option<Pet>> {
+        self.store.get_pet(id).map_err(Into::into)
+    }
+
+    fn find_pets(&mut self, statuses: &[Status]) -> Result<Vec<Pet>> {
+        if statuses.is_empty() {
+            Ok(self.store.export_pets()?.collect())
+        } else {
+            self.store
+                .get_pets_by_status(statuses.to_vec(), &Default::default())
+                .map_err(Into::into)
+        }
+    }
+
+    fn delete_pet(&mut self, id: u64) -> Result<()> {
+        self.store.delete_pet(id, None)?;
+        self.save(())
+    }
+
+    fn add_order(&mut self, order: Order) -> Result<u64> {
+        let id = self.store.add_order(order)?;
+        self.save(id)
+    }
+
+    fn get_order(&mut self, id: u64) -> Result<Option<Order>> {
+        self.store.find_order(id).map_err(Into::into)
+    }
+
+    fn list_orders(&mut self) -> Result<Vec<Order>> {
+        self.store.list_orders().map_err(Into::into)
+    }
+
+    fn delete_order(&mut self, id: u64) -> Result<bool> {
+        let deleted = self.store.delete_order(id, None)?;
+        self.save(deleted)
+    }
+
+    fn add_user(&mut self, user: User) -> Result<String> {
+        let username = self.store.add_user(user)?;
+        self.save(username)
+    }
+
+    fn get_user(&mut self, username: &str) -> Result<Option<User>> {
+        self.store.get_user(username.to_owned()).map_err(Into::into)
+    }
+
+    fn list_users(&mut self) -> Result<Vec<PublicUser>> {
+        let users = self.store.list_users()?;
+        Ok(users.into_iter().map(Into::into).collect())
+    }
+
+    fn delete_user(&mut self, username: &str, _password: Option<&str>) -> Result<()> {
+        self.store.delete_user(username.to_owned(), None)?;
+        self.save(())
+    }
+
+    fn import_pets(&mut self, format: BulkFormat, data: Vec<u8>) -> Result<Vec<RowResult>> {
+        let mut parser = RowParser::new(format);
+        let mut results = self.store.import_pets(parser.push(&data));
+        results.extend(self.store.import_pets(parser.finish()));
+        self.save(results)
+    }
+
+    fn export_pets(&mut self, format: BulkFormat) -> Result<Vec<u8>> {
+        let mut data = bulk::header(format).unwrap_or_default();
+        for pet in self.store.export_pets()? {
+            data.extend(bulk::encode_row(format, &pet));
+        }
+        Ok(data)
+    }
+
+    fn inventory(&mut self) -> Result<Inventory> {
+        self.store.get_inventory().map_err(Into::into)
+    }
+
+    fn check(&mut self) -> Result<Vec<String>> {
+        self.store.check_consistency().map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use petstore::config::Config;
+    use petstore::server::Server;
+    use super::*;
+
+    fn user(name: &str) -> User {
+        User {
+            id: None,
+            username: name.into(),
+            first_name: None,
+            last_name: None,
+            email: None,
+            password: "secret".into(),
+            phone: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        }
+    }
+
+    /// Go through the operations which every backend supports.
+    fn exercise(backend: &mut Backend) {
+        backend.add_user(user("alice")).unwrap();
+        backend.add_user(user("bob")).unwrap();
+        let names: Vec<_> = backend
+            .list_users()
+            .unwrap()
+            .into_iter()
+            .map(|user| user.username)
+            .collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+
+        let data = b"{\"name\":\"doggie\",\"photo_urls\":[]}\n{}\n".to_vec();
+        let results = backend.import_pets(BulkFormat::Ndjson, data).unwrap();
+        assert!(results[0].error.is_none() && results[1].error.is_some());
+        assert_eq!(backend.find_pets(&[]).unwrap().len(), 1);
+
+        backend.delete_user("bob", Some("secret")).unwrap();
+        assert!(backend.get_user("bob").unwrap().is_none());
+        assert_eq!(backend.check().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_local() {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+        let path = env::temp_dir().join(format!("petstore-cli-{}.json", nanos));
+        exercise(&mut Local::open(path.clone()).unwrap());
+        // Every modification was saved.
+        assert_eq!(Local::open(path.clone()).unwrap().list_users().unwrap().len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_remote() {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut core = Core::new().unwrap();
+            let mut config = Config::default();
+            config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+            let server = Server::bind(config, Petstore::new(), &core.handle()).unwrap();
+            tx.send(server.local_addrs().unwrap()[0]).unwrap();
+            core.run(server.run()).unwrap();
+        });
+        let addr = rx.recv().unwrap();
+        exercise(&mut Remote::new(&format!("http://{}", addr), None).unwrap());
+    }
+}
@@ -0,0 +1,422 @@
+//! An administration tool for the petstore, working either through a running server
+//! or directly on the snapshot file of a stopped one.
+
+extern crate clap;
+#[macro_use]
+extern crate error_chain;
+extern crate futures;
+extern crate petstore;
+extern crate petstore_client;
+extern crate serde;
+extern crate serde_json;
+extern crate tokio_core;
+
+mod backend;
+mod output;
+
+mod errors {
+    error_chain! {
+        errors {
+            InvalidArgument(name: &'static str, value: String) {
+                display("invalid value for `{}': `{}'", name, value)
+            }
+
+            InvalidResponse(msg: String) {
+                display("invalid response: {}", msg)
+            }
+
+            NotFound(what: String) {
+                display("{} not found", what)
+            }
+
+            Inconsistent(count: usize) {
+                display("found {} inconsistencies", count)
+            }
+
+            ImportFailed(count: usize) {
+                display("{} rows could not be imported", count)
+            }
+        }
+
+        links {
+            Client(::petstore_client::Error, ::petstore_client::ErrorKind);
+            Store(::petstore::petstore::PetstoreError, ::petstore::petstore::PetstoreErrorKind);
+        }
+
+        foreign_links {
+            Io(::std::io::Error);
+        }
+    }
+}
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+use std::str::FromStr;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use petstore::bulk::BulkFormat;
+use petstore::model::{Category, Order, Pet, PublicUser, Status, Tag, User};
+use backend::{Backend, Local, Remote};
+use errors::*;
+use output::{print_list, print_one, print_table, Format};
+
+fn main() {
+    let matches = app().get_matches();
+    if let Err(e) = run(&matches) {
+        eprintln!("error: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+        process::exit(1);
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let id = || Arg::with_name("id").required(true);
+    let username = || Arg::with_name("username").required(true);
+    let format = || {
+        Arg::with_name("format")
+            .long("format")
+            .value_name("FORMAT")
+            .possible_values(&["ndjson", "csv"])
+            .help("Bulk format, guessed from the file extension if omitted")
+    };
+
+    App::new("petstore-cli")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("server")
+                .long("server")
+                .value_name("URL")
+                .env("PETSTORE_URL")
+                .default_value("http://localhost:4000")
+                .help("URL of a running server"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .value_name("FILE")
+                .help("Snapshot file to modify directly, instead of going through a server"),
+        )
+        .arg(
+            Arg::with_name("api-key")
+                .long("api-key")
+                .value_name("KEY")
+                .env("PETSTORE_API_KEY")
+                .help("API key sent to the server"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .value_name("FORMAT")
+                .possible_values(&["table", "json"])
+                .default_value("table")
+                .help("Output format"),
+        )
+        .subcommand(
+            SubCommand::with_name("pet")
+                .about("Manage pets")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .arg(Arg::with_name("name").long("name").takes_value(true).required(true))
+                        .arg(Arg::with_name("status").long("status").takes_value(true))
+                        .arg(Arg::with_name("category").long("category").takes_value(true))
+                        .arg(Arg::with_name("tag").long("tag").takes_value(true).multiple(true).number_of_values(1))
+                        .arg(
+                            Arg::with_name("photo-url")
+                                .long("photo-url")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List all pets, or those with one of the given statuses")
+                        .arg(
+                            Arg::with_name("status")
+                                .long("status")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("get").arg(id()))
+                .subcommand(SubCommand::with_name("delete").arg(id())),
+        )
+        .subcommand(
+            SubCommand::with_name("order")
+                .about("Manage orders")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .arg(Arg::with_name("pet-id").long("pet-id").takes_value(true).required(true))
+                        .arg(Arg::with_name("quantity").long("quantity").takes_value(true).default_value("1"))
+                        .arg(Arg::with_name("ship-date").long("ship-date").takes_value(true)),
+                )
+                .subcommand(SubCommand::with_name("list"))
+                .subcommand(SubCommand::with_name("get").arg(id()))
+                .subcommand(SubCommand::with_name("delete").arg(id())),
+        )
+        .subcommand(
+            SubCommand::with_name("user")
+                .about("Manage users")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .arg(Arg::with_name("username").long("username").takes_value(true).required(true))
+                        .arg(Arg::with_name("password").long("password").takes_value(true).required(true))
+                        .arg(Arg::with_name("first-name").long("first-name").takes_value(true))
+                        .arg(Arg::with_name("last-name").long("last-name").takes_value(true))
+                        .arg(Arg::with_name("email").long("email").takes_value(true))
+                        .arg(Arg::with_name("phone").long("phone").takes_value(true)),
+                )
+                .subcommand(SubCommand::with_name("list"))
+                .subcommand(SubCommand::with_name("get").arg(username()))
+                .subcommand(
+                    SubCommand::with_name("delete").arg(username()).arg(
+                        Arg::with_name("password")
+                            .long("password")
+                            .takes_value(true)
+                            .help("Password of the user, whose session a server requires"),
+                    ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Import pets from a bulk file, or `-' for the standard input")
+                .arg(Arg::with_name("file").required(true))
+                .arg(format()),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export all pets to the standard output")
+                .arg(format().default_value("ndjson")),
+        )
+        .subcommand(SubCommand::with_name("inventory").about("Count the pets by status"))
+        .subcommand(SubCommand::with_name("check").about("Check the consistency of the store"))
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &'static str) -> Result<Option<T>> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ErrorKind::InvalidArgument(name, value.into()).into()),
+        None => Ok(None),
+    }
+}
+
+fn parse_all<T: FromStr>(matches: &ArgMatches, name: &'static str) -> Result<Vec<T>> {
+    matches
+        .values_of(name)
+        .map(|values| values.collect())
+        .unwrap_or_else(Vec::new)
+        .into_iter()
+        .map(|value: &str| {
+            value
+                .parse()
+                .map_err(|_| ErrorKind::InvalidArgument(name, value.into()).into())
+        })
+        .collect()
+}
+
+fn string(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches.value_of(name).map(ToOwned::to_owned)
+}
+
+fn bulk_format(matches: &ArgMatches, path: &str) -> BulkFormat {
+    match matches.value_of("format") {
+        Some("csv") => BulkFormat::Csv,
+        Some(_) => BulkFormat::Ndjson,
+        None if path.ends_with(".csv") => BulkFormat::Csv,
+        None => BulkFormat::Ndjson,
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<()> {
+    let mut backend: Box<Backend> = match matches.value_of("store") {
+        Some(path) => Box::new(Local::open(path.into())?),
+        None => Box::new(Remote::new(
+            matches.value_of("server").expect("has a default value"),
+            matches.value_of("api-key"),
+        )?),
+    };
+    let format: Format = parse(matches, "output")?.expect("has a default value");
+
+    match matches.subcommand() {
+        ("pet", Some(matches)) => pet(&mut *backend, format, matches),
+        ("order", Some(matches)) => order(&mut *backend, format, matches),
+        ("user", Some(matches)) => user(&mut *backend, format, matches),
+        ("import", Some(matches)) => {
+            let path = matches.value_of("file").expect("is required");
+            let mut data = vec![];
+            if path == "-" {
+                io::stdin().read_to_end(&mut data)?;
+            } else {
+                File::open(path)?.read_to_end(&mut data)?;
+            }
+            let results = backend.import_pets(bulk_format(matches, path), data)?;
+            print_list(format, &results);
+            let failed = results.iter().filter(|result| result.error.is_some()).count();
+            if failed > 0 {
+                bail!(ErrorKind::ImportFailed(failed));
+            }
+            Ok(())
+        }
+        ("export", Some(matches)) => {
+            let data = backend.export_pets(bulk_format(matches, ""))?;
+            io::stdout().write_all(&data).map_err(Into::into)
+        }
+        ("inventory", Some(_)) => {
+            let inventory = backend.inventory()?;
+            match format {
+                Format::Table => print_table(
+                    &["STATUS", "COUNT"],
+                    &[
+                        vec!["available".into(), inventory.available.to_string()],
+                        vec!["pending".into(), inventory.pending.to_string()],
+                        vec!["adopted".into(), inventory.adopted.to_string()],
+                    ],
+                ),
+                Format::Json => output::print_json(&inventory),
+            }
+            Ok(())
+        }
+        ("check", Some(_)) => {
+            let problems = backend.check()?;
+            match format {
+                Format::Table => for problem in &problems {
+                    println!("{}", problem);
+                },
+                Format::Json => output::print_json(&problems),
+            }
+            if !problems.is_empty() {
+                bail!(ErrorKind::Inconsistent(problems.len()));
+            }
+            Ok(())
+        }
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+fn pet(backend: &mut Backend, format: Format, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        ("add", Some(matches)) => {
+            let pet = Pet {
+                id: None,
+                name: string(matches, "name").expect("is required"),
+                photo_urls: parse_all(matches, "photo-url")?,
+                category: string(matches, "category").map(|name| Category { id: None, name }),
+                tags: matches.values_of("tag").map(|tags| {
+                    tags.map(|name| Tag {
+                        id: None,
+                        name: name.into(),
+                    }).collect()
+                }),
+                status: parse(matches, "status")?,
+                created_at: None,
+                updated_at: None,
+                deleted_at: None,
+                version: 0,
+            };
+            let id = backend.add_pet(pet)?;
+            let pet = backend.get_pet(id)?.ok_or_else(|| ErrorKind::NotFound(format!("pet {}", id)))?;
+            print_one(format, &pet);
+        }
+        ("list", Some(matches)) => {
+            let statuses: Vec<Status> = parse_all(matches, "status")?;
+            print_list(format, &backend.find_pets(&statuses)?);
+        }
+        ("get", Some(matches)) => {
+            let id = parse(matches, "id")?.expect("is required");
+            let pet = backend.get_pet(id)?.ok_or_else(|| ErrorKind::NotFound(format!("pet {}", id)))?;
+            print_one(format, &pet);
+        }
+        ("delete", Some(matches)) => backend.delete_pet(parse(matches, "id")?.expect("is required"))?,
+        _ => unreachable!("a subcommand is required"),
+    }
+    Ok(())
+}
+
+fn order(backend: &mut Backend, format: Format, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        ("add", Some(matches)) => {
+            let order = Order {
+                id: None,
+                pet_id: parse(matches, "pet-id")?,
+                quantity: parse(matches, "quantity")?,
+                ship_date: parse(matches, "ship-date")?,
+                status: None,
+                complete: Some(false),
+                username: None,
+                created_at: None,
+                updated_at: None,
+                version: 0,
+            };
+            let id = backend.add_order(order)?;
+            let order = backend
+                .get_order(id)?
+                .ok_or_else(|| ErrorKind::NotFound(format!("order {}", id)))?;
+            print_one(format, &order);
+        }
+        ("list", Some(_)) => print_list(format, &backend.list_orders()?),
+        ("get", Some(matches)) => {
+            let id = parse(matches, "id")?.expect("is required");
+            let order = backend
+                .get_order(id)?
+                .ok_or_else(|| ErrorKind::NotFound(format!("order {}", id)))?;
+            print_one(format, &order);
+        }
+        ("delete", Some(matches)) => {
+            let id = parse(matches, "id")?.expect("is required");
+            if !backend.delete_order(id)? {
+                bail!(ErrorKind::NotFound(format!("order {}", id)));
+            }
+        }
+        _ => unreachable!("a subcommand is required"),
+    }
+    Ok(())
+}
+
+fn user(backend: &mut Backend, format: Format, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        ("add", Some(matches)) => {
+            let user = User {
+                id: None,
+                username: string(matches, "username").expect("is required"),
+                first_name: string(matches, "first-name"),
+                last_name: string(matches, "last-name"),
+                email: string(matches, "email"),
+                password: string(matches, "password").expect("is required"),
+                phone: string(matches, "phone"),
+                created_at: None,
+                updated_at: None,
+                deleted_at: None,
+                version: 0,
+            };
+            let username = backend.add_user(user)?;
+            let user = backend
+                .get_user(&username)?
+                .ok_or_else(|| ErrorKind::NotFound(format!("user {}", username)))?;
+            print_one(format, &PublicUser::from(user));
+        }
+        ("list", Some(_)) => print_list(format, &backend.list_users()?),
+        ("get", Some(matches)) => {
+            let username = matches.value_of("username").expect("is required");
+            let user = backend
+                .get_user(username)?
+                .ok_or_else(|| ErrorKind::NotFound(format!("user {}", username)))?;
+            print_one(format, &PublicUser::from(user));
+        }
+        ("delete", Some(matches)) => backend.delete_user(
+            matches.value_of("username").expect("is required"),
+            matches.value_of("password"),
+        )?,
+        _ => unreachable!("a subcommand is required"),
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Printing of the results, as aligned tables for humans or as JSON for scripts.
+
+use std::str::FromStr;
+use serde::Serialize;
+use serde_json;
+use petstore::bulk::RowResult;
+use petstore::model::{Order, Pet, PublicUser};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An item which is printed as a row of a table.
+pub trait Row {
+    fn header() -> Vec<&'static str>;
+    fn cells(&self) -> Vec<String>;
+}
+
+fn opt<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+impl Row for Pet {
+    fn header() -> Vec<&'static str> {
+        vec!["ID", "NAME", "STATUS", "CATEGORY", "TAGS"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        let tags = self.tags
+            .as_ref()
+            .map(|tags| tags.iter().map(|t| &t.name[..]).collect::<Vec<_>>().join(","))
+            .unwrap_or_default();
+        vec![
+            opt(&self.id),
+            self.name.clone(),
+            opt(&self.status),
+            self.category.as_ref().map(|c| c.name.clone()).unwrap_or_default(),
+            tags,
+        ]
+    }
+}
+
+impl Row for Order {
+    fn header() -> Vec<&'static str> {
+        vec!["ID", "PET", "QUANTITY", "STATUS", "SHIP DATE", "COMPLETE"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            opt(&self.id),
+            opt(&self.pet_id),
+            opt(&self.quantity),
+            opt(&self.status),
+            opt(&self.ship_date),
+            opt(&self.complete),
+        ]
+    }
+}
+
+impl Row for PublicUser {
+    fn header() -> Vec<&'static str> {
+        vec!["ID", "USERNAME", "FIRST NAME", "LAST NAME", "EMAIL", "PHONE"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![
+            opt(&self.id),
+            self.username.clone(),
+            opt(&self.first_name),
+            opt(&self.last_name),
+            opt(&self.email),
+            opt(&self.phone),
+        ]
+    }
+}
+
+impl Row for RowResult {
+    fn header() -> Vec<&'static str> {
+        vec!["ROW", "ID", "ERROR"]
+    }
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.row.to_string(), opt(&self.id), opt(&self.error)]
+    }
+}
+
+/// Print cells as a table whose columns are padded to the widest cell.
+pub fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:1$}", cell, width))
+            .collect();
+        println!("{}", line.join("  ").trim_right());
+    };
+    print_row(header.to_vec());
+    for row in rows {
+        print_row(row.iter().map(|cell| &cell[..]).collect());
+    }
+}
+
+pub fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("the results are always serializable")
+    );
+}
+
+pub fn print_list<T: Row + Serialize>(format: Format, items: &[T]) {
+    match format {
+        Format::Table => print_table(&T::header(), &items.iter().map(Row::cells).collect::<Vec<_>>()),
+        Format::Json => print_json(&items),
+    }
+}
+
+pub fn print_one<T: Row + Serialize>(format: Format, item: &T) {
+    match format {
+        Format::Table => print_table(&T::header(), &[item.cells()]),
+        Format::Json => print_json(item),
+    }
+}
@@ -0,0 +1,83 @@
+use hyper::{self, StatusCode};
+use serde_json;
+use serde_urlencoded;
+
+error_chain! {
+    errors {
+        /// The request was malformed (400), e.g. an invalid body or wrong credentials.
+        BadRequest(msg: String) {
+            display("bad request: {}", msg)
+        }
+
+        /// A valid API key, or the session of the user, is required (401).
+        Unauthorized {
+            display("unauthorized")
+        }
+
+        /// The session of another user was sent (403).
+        Forbidden(msg: String) {
+            display("forbidden: {}", msg)
+        }
+
+        /// The entity or route does not exist (404).
+        NotFound {
+            display("not found")
+        }
+
+        /// The entity was modified since it was read (412).
+        PreconditionFailed(msg: String) {
+            display("precondition failed: {}", msg)
+        }
+
+        /// The server requires modifications to carry `If-Match` (428).
+        PreconditionRequired(msg: String) {
+            display("precondition required: {}", msg)
+        }
+
+        /// The body or the number of items exceeds a limit of the server (413).
+        PayloadTooLarge(msg: String) {
+            display("payload too large: {}", msg)
+        }
+
+        /// The patch cannot be applied (422).
+        InvalidPatch(msg: String) {
+            display("invalid patch: {}", msg)
+        }
+
+        /// The rate limit is exhausted. The request can be retried after the given number of seconds (429).
+        TooManyRequests(retry_after: Option<u64>) {
+            display("too many requests")
+        }
+
+        /// Any other unsuccessful status, along with the body of the response.
+        Status(status: StatusCode, msg: String) {
+            display("unexpected status {}: {}", status, msg)
+        }
+    }
+
+    foreign_links {
+        Hyper(hyper::Error);
+        Uri(hyper::error::UriError);
+        Json(serde_json::Error);
+        Form(serde_urlencoded::ser::Error);
+    }
+}
+
+impl ErrorKind {
+    /// Map an unsuccessful response to an error, as the server maps its errors to responses.
+    pub fn from_response(status: StatusCode, retry_after: Option<u64>, body: &[u8]) -> Self {
+        let msg = String::from_utf8_lossy(body).into_owned();
+        match status {
+            StatusCode::BadRequest => ErrorKind::BadRequest(msg),
+            StatusCode::Unauthorized => ErrorKind::Unauthorized,
+            StatusCode::Forbidden => ErrorKind::Forbidden(msg),
+            StatusCode::NotFound => ErrorKind::NotFound,
+            StatusCode::PreconditionFailed => ErrorKind::PreconditionFailed(msg),
+            StatusCode::PreconditionRequired => ErrorKind::PreconditionRequired(msg),
+            StatusCode::PayloadTooLarge => ErrorKind::PayloadTooLarge(msg),
+            StatusCode::UnprocessableEntity => ErrorKind::InvalidPatch(msg),
+            StatusCode::TooManyRequests => ErrorKind::TooManyRequests(retry_after),
+            status => ErrorKind::Status(status, msg),
+        }
+    }
+}
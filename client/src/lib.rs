@@ -0,0 +1,369 @@
+//! A typed client of the petstore API, built on the model types of the server.
+//!
+//! Every operation returns a future to be run on the event loop of the `Handle` given
+//! to `Client::new`. Entities read from the server carry their version, which is sent
+//! back in `If-Match` when they are updated.
+
+#[macro_use]
+extern crate error_chain;
+extern crate futures;
+extern crate hyper;
+extern crate petstore;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_urlencoded;
+extern crate tokio_core;
+extern crate url;
+
+pub mod error;
+
+pub use error::{Error, ErrorKind, Result};
+pub use petstore::bulk::{BulkFormat, RowResult};
+pub use petstore::model;
+pub use petstore::patch::{Patch, PatchOperation};
+
+use std::str;
+use futures::{future, Future, Stream};
+use hyper::{Chunk, Delete, Get, Headers, Method, Post, Put, Request};
+use hyper::client::HttpConnector;
+use hyper::header::{Authorization, Bearer, ContentLength, ContentType, ETag, EntityTag, IfMatch, RetryAfter};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio_core::reactor::Handle;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use model::{Inventory, Order, Pet, PublicUser, Status, Trash, User};
+
+pub type ClientFuture<T> = Box<Future<Item = T, Error = Error>>;
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: hyper::Client<HttpConnector>,
+    base_url: String,
+    api_key: Option<String>,
+    session: Option<String>,
+}
+
+fn segment(s: &str) -> String {
+    utf8_percent_encode(s, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+/// The total number of items of a page, or `default` if the server did not tell it.
+fn total_count(headers: &Headers, default: usize) -> usize {
+    headers
+        .get_raw("X-Total-Count")
+        .and_then(|raw| raw.one())
+        .and_then(|value| str::from_utf8(value).ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn version(headers: &Headers) -> u64 {
+    headers
+        .get::<ETag>()
+        .and_then(|tag| tag.tag().parse().ok())
+        .unwrap_or(0)
+}
+
+impl Client {
+    /// Create a client of the server at `base_url`, e.g. `http://localhost:4000`.
+    pub fn new(base_url: &str, handle: &Handle) -> Self {
+        Client {
+            http: hyper::Client::new(handle),
+            base_url: base_url.trim_right_matches('/').to_owned(),
+            api_key: None,
+            session: None,
+        }
+    }
+
+    /// Send `key` in the `api_key` header of every request.
+    pub fn api_key<S: Into<String>>(mut self, key: S) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Act as the user who opened the session `token`, which is required to modify their account
+    /// or list their orders.
+    pub fn session<S: Into<String>>(mut self, token: S) -> Self {
+        self.session = Some(token.into());
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<Request> {
+        let mut req = Request::new(method, format!("{}{}", self.base_url, path).parse()?);
+        if let Some(ref key) = self.api_key {
+            req.headers_mut().set_raw("api_key", key.clone());
+        }
+        if let Some(ref token) = self.session {
+            req.headers_mut().set(Authorization(Bearer { token: token.clone() }));
+        }
+        Ok(req)
+    }
+
+    /// A request applying `patch`, conditioned on `version` if known.
+    fn patch_request(&self, path: &str, patch: &Patch, version: u64) -> Result<Request> {
+        let (mut req, content_type) = match *patch {
+            Patch::Merge(ref value) => (
+                self.json_request(Method::Patch, path, value, version)?,
+                "application/merge-patch+json",
+            ),
+            Patch::Json(ref operations) => (
+                self.json_request(Method::Patch, path, operations, version)?,
+                "application/json-patch+json",
+            ),
+        };
+        req.headers_mut().set_raw("Content-Type", content_type);
+        Ok(req)
+    }
+
+    fn json_request<T: Serialize>(&self, method: Method, path: &str, body: &T, version: u64) -> Result<Request> {
+        let body = serde_json::to_vec(body)?;
+        let mut req = self.request(method, path)?;
+        req.headers_mut().set(ContentType::json());
+        req.headers_mut().set(ContentLength(body.len() as u64));
+        if version != 0 {
+            req.headers_mut()
+                .set(IfMatch::Items(vec![EntityTag::strong(version.to_string())]));
+        }
+        req.set_body(body);
+        Ok(req)
+    }
+
+    /// Send a request, and return the headers and body of a successful response.
+    fn send(&self, req: Result<Request>) -> ClientFuture<(Headers, Chunk)> {
+        let req = match req {
+            Ok(req) => req,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        Box::new(self.http.request(req).from_err().and_then(|response| {
+            let (status, headers) = (response.status(), response.headers().clone());
+            response.body().concat2().from_err().and_then(move |body| {
+                if status.is_success() {
+                    return Ok((headers, body));
+                }
+                let retry_after = match headers.get::<RetryAfter>() {
+                    Some(&RetryAfter::Delay(delay)) => Some(delay.as_secs()),
+                    _ => None,
+                };
+                Err(ErrorKind::from_response(status, retry_after, &body).into())
+            })
+        }))
+    }
+
+    fn send_json<T: DeserializeOwned + 'static>(&self, req: Result<Request>) -> ClientFuture<T> {
+        Box::new(
+            self.send(req)
+                .and_then(|(_, body)| serde_json::from_slice(&body).map_err(Into::into)),
+        )
+    }
+
+    fn send_empty(&self, req: Result<Request>) -> ClientFuture<()> {
+        Box::new(self.send(req).map(|_| ()))
+    }
+}
+
+/// Pets.
+impl Client {
+    pub fn get_pet(&self, id: u64) -> ClientFuture<Pet> {
+        let req = self.request(Get, &format!("/pet/{}", id));
+        Box::new(self.send(req).and_then(|(headers, body)| {
+            let mut pet: Pet = serde_json::from_slice(&body)?;
+            pet.version = version(&headers);
+            Ok(pet)
+        }))
+    }
+
+    /// Add a pet, and return its ID.
+    pub fn add_pet(&self, pet: &Pet) -> ClientFuture<u64> {
+        self.send_json(self.json_request(Post, "/pet", pet, 0))
+    }
+
+    /// Replace a pet, conditioned on its version if known.
+    pub fn update_pet(&self, pet: &Pet) -> ClientFuture<Pet> {
+        self.send_json(self.json_request(Put, "/pet", pet, pet.version))
+    }
+
+    /// Apply a JSON Merge Patch or a JSON Patch to a pet, conditioned on `version` if not 0.
+    pub fn patch_pet(&self, id: u64, patch: &Patch, version: u64) -> ClientFuture<Pet> {
+        self.send_json(self.patch_request(&format!("/pet/{}", id), patch, version))
+    }
+
+    pub fn update_pet_with_form(&self, id: u64, name: Option<&str>, status: Option<Status>) -> ClientFuture<Pet> {
+        let mut form = vec![];
+        if let Some(name) = name {
+            form.push(("name", name.to_owned()));
+        }
+        if let Some(status) = status {
+            form.push(("status", status.to_string()));
+        }
+        let req = serde_urlencoded::to_string(&form)
+            .map_err(Into::into)
+            .and_then(|body| {
+                let mut req = self.request(Post, &format!("/pet/{}", id))?;
+                req.headers_mut().set(ContentType::form_url_encoded());
+                req.headers_mut().set(ContentLength(body.len() as u64));
+                req.set_body(body);
+                Ok(req)
+            });
+        self.send_json(req)
+    }
+
+    pub fn delete_pet(&self, id: u64) -> ClientFuture<()> {
+        self.send_empty(self.request(Delete, &format!("/pet/{}", id)))
+    }
+
+    /// Bring a deleted pet back from the trash.
+    pub fn restore_pet(&self, id: u64) -> ClientFuture<Pet> {
+        self.send_json(self.request(Post, &format!("/pet/{}/restore", id)))
+    }
+
+    /// A page of the deleted pets and users which have not been purged yet, the pets first,
+    /// along with the total number of them.
+    pub fn trash(&self, offset: usize, limit: usize) -> ClientFuture<(usize, Trash)> {
+        let req = self.request(Get, &format!("/trash?offset={}&limit={}", offset, limit));
+        Box::new(self.send(req).and_then(|(headers, body)| {
+            let trash: Trash = serde_json::from_slice(&body)?;
+            Ok((total_count(&headers, trash.pets.len() + trash.users.len()), trash))
+        }))
+    }
+
+    pub fn find_pets_by_status(&self, statuses: &[Status]) -> ClientFuture<Vec<Pet>> {
+        let statuses: Vec<_> = statuses.iter().map(|s| s.to_string()).collect();
+        let query = serde_urlencoded::to_string(&[("status", statuses.join(","))]);
+        self.send_json(query.map_err(Into::into).and_then(|query| {
+            self.request(Get, &format!("/pet/findByStatus?{}", query))
+        }))
+    }
+
+    /// Import pets from a bulk payload, and return the outcome of each row.
+    pub fn import_pets(&self, format: BulkFormat, data: Vec<u8>) -> ClientFuture<Vec<RowResult>> {
+        let req = self.request(Post, "/pet/bulk").map(|mut req| {
+            req.headers_mut()
+                .set_raw("Content-Type", format.content_type());
+            req.headers_mut().set(ContentLength(data.len() as u64));
+            req.set_body(data);
+            req
+        });
+        self.send_json(req)
+    }
+
+    /// Export all pets as a bulk payload.
+    pub fn export_pets(&self, format: BulkFormat) -> ClientFuture<Vec<u8>> {
+        let format = match format {
+            BulkFormat::Ndjson => "ndjson",
+            BulkFormat::Csv => "csv",
+        };
+        let req = self.request(Get, &format!("/pet/export?format={}", format));
+        Box::new(self.send(req).map(|(_, body)| body.to_vec()))
+    }
+
+    pub fn find_pets_by_tags(&self, tags: &[&str]) -> ClientFuture<Vec<Pet>> {
+        let query = serde_urlencoded::to_string(&[("tags", tags.join(","))]);
+        self.send_json(query.map_err(Into::into).and_then(|query| {
+            self.request(Get, &format!("/pet/findByTags?{}", query))
+        }))
+    }
+}
+
+/// Orders.
+impl Client {
+    pub fn get_inventory(&self) -> ClientFuture<Inventory> {
+        self.send_json(self.request(Get, "/store/inventory"))
+    }
+
+    /// Place an order, and return its ID.
+    pub fn place_order(&self, order: &Order) -> ClientFuture<u64> {
+        self.send_json(self.json_request(Post, "/store/order", order, 0))
+    }
+
+    pub fn get_order(&self, id: u64) -> ClientFuture<Order> {
+        let req = self.request(Get, &format!("/store/order/{}", id));
+        Box::new(self.send(req).and_then(|(headers, body)| {
+            let mut order: Order = serde_json::from_slice(&body)?;
+            order.version = version(&headers);
+            Ok(order)
+        }))
+    }
+
+    /// List a page of the orders in the order of their IDs, along with the total number of orders.
+    pub fn list_orders(&self, offset: usize, limit: usize) -> ClientFuture<(usize, Vec<Order>)> {
+        let req = self.request(Get, &format!("/store/order?offset={}&limit={}", offset, limit));
+        Box::new(self.send(req).and_then(|(headers, body)| {
+            let orders: Vec<Order> = serde_json::from_slice(&body)?;
+            Ok((total_count(&headers, orders.len()), orders))
+        }))
+    }
+
+    /// Delete an order, and return whether it existed.
+    pub fn delete_order(&self, id: u64) -> ClientFuture<bool> {
+        self.send_json(self.request(Delete, &format!("/store/order/{}", id)))
+    }
+
+    /// Look for the inconsistencies of the store, e.g. orders of missing pets.
+    pub fn check(&self) -> ClientFuture<Vec<String>> {
+        self.send_json(self.request(Get, "/store/check"))
+    }
+}
+
+/// Users.
+impl Client {
+    /// Create a user, and return its username.
+    pub fn create_user(&self, user: &User) -> ClientFuture<String> {
+        self.send_json(self.json_request(Post, "/user", user, 0))
+    }
+
+    pub fn create_users_with_list(&self, users: &[User]) -> ClientFuture<Vec<String>> {
+        self.send_json(self.json_request(Post, "/user/createWithList", &users, 0))
+    }
+
+    /// List a page of the users in the order of their IDs, along with the total number of users.
+    pub fn list_users(&self, offset: usize, limit: usize) -> ClientFuture<(usize, Vec<PublicUser>)> {
+        let req = self.request(Get, &format!("/user?offset={}&limit={}", offset, limit));
+        Box::new(self.send(req).and_then(|(headers, body)| {
+            let users: Vec<PublicUser> = serde_json::from_slice(&body)?;
+            Ok((total_count(&headers, users.len()), users))
+        }))
+    }
+
+    pub fn get_user(&self, username: &str) -> ClientFuture<User> {
+        let req = self.request(Get, &format!("/user/{}", segment(username)));
+        Box::new(self.send(req).and_then(|(headers, body)| {
+            let mut user: User = serde_json::from_slice(&body)?;
+            user.version = version(&headers);
+            Ok(user)
+        }))
+    }
+
+    /// Replace a user, conditioned on its version if known. This requires their `session`.
+    pub fn update_user(&self, user: &User) -> ClientFuture<User> {
+        self.send_json(self.json_request(Put, "/user", user, user.version))
+    }
+
+    /// Apply a JSON Merge Patch or a JSON Patch to a user, conditioned on `version` if not 0.
+    /// This requires their `session`.
+    pub fn patch_user(&self, username: &str, patch: &Patch, version: u64) -> ClientFuture<User> {
+        self.send_json(self.patch_request(&format!("/user/{}", segment(username)), patch, version))
+    }
+
+    /// Move a user to the trash. This requires their `session`.
+    pub fn delete_user(&self, username: &str) -> ClientFuture<()> {
+        self.send_empty(self.request(Delete, &format!("/user/{}", segment(username))))
+    }
+
+    /// Log in, and return the token of the session.
+    pub fn login(&self, username: &str, password: &str) -> ClientFuture<String> {
+        let query = serde_urlencoded::to_string(&[("username", username), ("password", password)]);
+        self.send_json(query.map_err(Into::into).and_then(|query| {
+            self.request(Get, &format!("/user/login?{}", query))
+        }))
+    }
+
+    pub fn logout(&self, token: &str) -> ClientFuture<()> {
+        let req = self.request(Get, "/user/logout").map(|mut req| {
+            req.headers_mut().set(Authorization(Bearer {
+                token: token.to_owned(),
+            }));
+            req
+        });
+        self.send_empty(req)
+    }
+}
@@ -0,0 +1,161 @@
+extern crate futures;
+extern crate petstore;
+extern crate petstore_client;
+extern crate serde_json;
+extern crate tokio_core;
+
+use futures::Future;
+use petstore::Petstore;
+use petstore::config::Config;
+use petstore::model::Status;
+use petstore::server::Server;
+use petstore_client::{Client, ErrorKind, Patch, PatchOperation};
+use petstore_client::model::{Order, Pet, User};
+use tokio_core::reactor::Core;
+
+/// Start a server on an ephemeral port, and create a client of it.
+fn start(core: &Core) -> Client {
+    let mut config = Config::default();
+    config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+    let server = Server::bind(config, Petstore::new(), &core.handle()).unwrap();
+    let addr = server.local_addrs().unwrap()[0];
+    core.handle().spawn(server.run().map_err(|e| panic!("{}", e)));
+    Client::new(&format!("http://{}", addr), &core.handle())
+}
+
+fn pet(name: &str, status: Status) -> Pet {
+    Pet {
+        id: None,
+        name: name.into(),
+        photo_urls: vec![],
+        category: None,
+        tags: None,
+        status: Some(status),
+        created_at: None,
+        updated_at: None,
+        deleted_at: None,
+        version: 0,
+    }
+}
+
+#[test]
+fn test_pets() {
+    let mut core = Core::new().unwrap();
+    let client = start(&core);
+
+    let id = core.run(client.add_pet(&pet("doggie", Status::Available))).unwrap();
+    core.run(client.add_pet(&pet("kitty", Status::Pending))).unwrap();
+
+    let mut doggie = core.run(client.get_pet(id)).unwrap();
+    assert_eq!(doggie.name, "doggie");
+    assert_eq!(doggie.version, 1);
+
+    doggie.status = Some(Status::Adopted);
+    core.run(client.update_pet(&doggie)).unwrap();
+    match core.run(client.update_pet(&doggie)) {
+        Err(e) => match *e.kind() {
+            ErrorKind::PreconditionFailed(..) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(..) => panic!("a stale version must be rejected"),
+    }
+
+    let patch = Patch::Json(vec![PatchOperation::Replace {
+        path: "/name".into(),
+        value: "doge".into(),
+    }]);
+    let patched = core.run(client.patch_pet(id, &patch, 0)).unwrap();
+    assert_eq!(patched.name, "doge");
+
+    let pending = core.run(client.find_pets_by_status(&[Status::Pending])).unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].name, "kitty");
+
+    core.run(client.delete_pet(id)).unwrap();
+    match core.run(client.get_pet(id)) {
+        Err(e) => match *e.kind() {
+            ErrorKind::NotFound => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(..) => panic!("the pet must be deleted"),
+    }
+
+    let (total, trash) = core.run(client.trash(0, 10)).unwrap();
+    assert_eq!(total, 1);
+    assert_eq!(trash.pets.iter().map(|pet| pet.id).collect::<Vec<_>>(), vec![Some(id)]);
+    let restored = core.run(client.restore_pet(id)).unwrap();
+    assert_eq!(restored.name, "doge");
+    assert!(core.run(client.trash(0, 10)).unwrap().1.pets.is_empty());
+}
+
+#[test]
+fn test_orders_and_users() {
+    let mut core = Core::new().unwrap();
+    let client = start(&core);
+
+    let order = Order {
+        id: None,
+        pet_id: Some(0),
+        quantity: Some(1),
+        ship_date: None,
+        status: None,
+        complete: Some(false),
+        username: None,
+        created_at: None,
+        updated_at: None,
+        version: 0,
+    };
+    let id = core.run(client.place_order(&order)).unwrap();
+    assert_eq!(core.run(client.get_order(id)).unwrap().pet_id, Some(0));
+    let (total, orders) = core.run(client.list_orders(0, 10)).unwrap();
+    assert_eq!((total, orders[0].id), (1, Some(id)));
+    assert_eq!(core.run(client.delete_order(id)).unwrap(), true);
+
+    let users: Vec<_> = ["alice", "bob"]
+        .iter()
+        .map(|name| User {
+            id: None,
+            username: name.to_string(),
+            first_name: None,
+            last_name: None,
+            email: None,
+            password: "secret".into(),
+            phone: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        })
+        .collect();
+    let usernames = core.run(client.create_users_with_list(&users)).unwrap();
+    assert_eq!(usernames, vec!["alice", "bob"]);
+
+    let token = core.run(client.login("alice", "secret")).unwrap();
+    match core.run(client.delete_user("alice")) {
+        Err(e) => match *e.kind() {
+            ErrorKind::Unauthorized => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(..) => panic!("deleting a user must require their session"),
+    }
+    let alice = client.clone().session(token.clone());
+    match core.run(alice.delete_user("bob")) {
+        Err(e) => match *e.kind() {
+            ErrorKind::Forbidden(..) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(..) => panic!("a user must not delete another one"),
+    }
+    let patch = Patch::Merge(serde_json::from_str(r#"{"first_name":"Alice"}"#).unwrap());
+    let patched = core.run(alice.patch_user("alice", &patch, 0)).unwrap();
+    assert_eq!(patched.first_name, Some("Alice".into()));
+    core.run(client.logout(&token)).unwrap();
+    assert!(core.run(alice.patch_user("alice", &patch, 0)).is_err());
+    match core.run(client.login("alice", "wrong")) {
+        Err(e) => match *e.kind() {
+            ErrorKind::BadRequest(..) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(..) => panic!("a wrong password must be rejected"),
+    }
+}
@@ -0,0 +1,34 @@
+//! Feeds arbitrary bulk import payloads through `RowParser`, split into chunks of arbitrary size.
+//!
+//! The first byte selects the format and the second one the size of the chunks.
+//!
+//!     cargo +nightly fuzz run bulk_rows -- -max_len=65536 -rss_limit_mb=256
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate petstore;
+
+use petstore::bulk::{BulkFormat, RowParser};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let format = if data[0] % 2 == 0 {
+        BulkFormat::Ndjson
+    } else {
+        BulkFormat::Csv
+    };
+    let chunk_size = data[1] as usize + 1;
+
+    let mut parser = RowParser::new(format);
+    let mut rows = vec![];
+    for chunk in data[2..].chunks(chunk_size) {
+        rows.extend(parser.push(chunk).into_iter().map(|(row, _)| row));
+    }
+    rows.extend(parser.finish().map(|(row, _)| row));
+
+    // Every row is reported once, in order, whatever the chunks are.
+    assert!(rows.windows(2).all(|w| w[0] < w[1]), "rows out of order: {:?}", rows);
+});
@@ -0,0 +1,62 @@
+//! Feeds arbitrary requests through `api::endpoint()` and the handler of a fresh store.
+//!
+//! The input is read as `METHOD URI`, followed by `Name: value` header lines, an empty line
+//! and the body. Errors must be answered with a 4xx rather than a panic or a 5xx.
+//!
+//!     cargo +nightly fuzz run endpoint -- -max_len=4096 -rss_limit_mb=256
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate finchers;
+extern crate petstore;
+
+use std::str;
+use finchers::Handler;
+use finchers::http::{HttpRequest, IntoResponse};
+use finchers::test::EndpointTestExt;
+use petstore::api;
+use petstore::petstore::Petstore;
+
+fuzz_target!(|data: &[u8]| {
+    let split = data.windows(2).position(|w| w == b"\n\n").unwrap_or(data.len());
+    let head = match str::from_utf8(&data[..split]) {
+        Ok(head) => head,
+        Err(..) => return,
+    };
+    let body = data.get(split + 2..).unwrap_or(&[]).to_vec();
+
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or("").splitn(2, ' ');
+    let mut builder = HttpRequest::builder();
+    builder
+        .method(request_line.next().unwrap_or(""))
+        .uri(request_line.next().unwrap_or(""));
+    for line in lines {
+        if let Some(i) = line.find(':') {
+            builder.header(&line[..i], line[i + 1..].trim());
+        }
+    }
+    let request = match builder.body(body.into()) {
+        Ok(request) => request,
+        Err(..) => return,
+    };
+
+    // A new store for each input, so that the memory used does not grow across runs.
+    let store = Petstore::new().max_list_length(100);
+    let response = match api::endpoint().run(request) {
+        None => return,
+        Some(Err(e)) => e.into_response(),
+        Some(Ok(request)) => match store.call(request) {
+            Ok(Some(response)) => response.into_response(),
+            Ok(None) => return,
+            Err(e) => e.into_response(),
+        },
+    };
+    let status = response.status();
+    assert!(
+        status.is_success() || status.is_client_error(),
+        "unexpected status {}",
+        status
+    );
+});
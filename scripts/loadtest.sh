@@ -0,0 +1,39 @@
+#!/bin/sh
+# Run a load test against a release build of the server, populated with seeded fixtures.
+#
+# Requires wrk (https://github.com/wg/wrk). The parameters can be overridden through the
+# environment, e.g. `DURATION=60s CONNECTIONS=256 scripts/loadtest.sh`.
+
+set -eu
+
+PORT=${PORT:-4100}
+SEED=${SEED:-42}
+SEED_COUNT=${SEED_COUNT:-10000}
+DURATION=${DURATION:-30s}
+THREADS=${THREADS:-4}
+CONNECTIONS=${CONNECTIONS:-64}
+
+cd "$(dirname "$0")/.."
+cargo build --release --bin petstore
+
+./target/release/petstore \
+    --listen "127.0.0.1:$PORT" \
+    --seed "$SEED" \
+    --seed-count "$SEED_COUNT" \
+    --log-level warn &
+SERVER=$!
+trap 'kill $SERVER' EXIT
+
+for _ in $(seq 50); do
+    if curl -sf "http://127.0.0.1:$PORT/healthz" > /dev/null; then
+        break
+    fi
+    sleep 0.1
+done
+
+echo "seed=$SEED seed_count=$SEED_COUNT duration=$DURATION threads=$THREADS connections=$CONNECTIONS"
+for path in "/pet/$((SEED_COUNT / 2))" "/pet/findByStatus?status=pending" "/store/inventory"; do
+    echo
+    echo "== GET $path"
+    wrk --latency -d "$DURATION" -t "$THREADS" -c "$CONNECTIONS" "http://127.0.0.1:$PORT$path"
+done
@@ -1,15 +1,55 @@
-use finchers::http::header;
+use std::cell::RefCell;
+use chrono::{DateTime, Utc};
+use finchers::Endpoint;
+use finchers::http::header::{self, ContentType, EntityTag, IfMatch};
+use finchers::request::{FromBody, Request};
+use futures::{stream, Future, Sink};
+use futures::sync::mpsc::SendError;
+use hyper::{self, Body, Chunk};
 use serde::Serialize;
-use serde_json;
+use serde_json::{self, Value};
+use tokio_core::reactor::Handle;
+use error::EndpointError;
+use model::{OrderQuery, OrderSort, OrderStatus, TimestampQuery, TimestampSort, Window};
+use patch::{Patch, PatchOperation};
+use petstore::{PetstoreError, PetstoreErrorKind, Precondition};
 
 pub use finchers::http::{IntoResponse, Response as HyperResponse, StatusCode};
 
+/// Serialize `content` as the body of the response, which is a 500 if it cannot be serialized.
 pub fn json_response<T: Serialize>(content: &T) -> HyperResponse {
-    let body = serde_json::to_vec(&content).unwrap();
-    HyperResponse::new()
-        .with_header(header::ContentType::json())
-        .with_header(header::ContentLength(body.len() as u64))
-        .with_body(body)
+    match serde_json::to_vec(&content) {
+        Ok(body) => HyperResponse::new()
+            .with_header(header::ContentType::json())
+            .with_header(header::ContentLength(body.len() as u64))
+            .with_body(body),
+        Err(e) => {
+            error!("failed to serialize the response: {}", e);
+            let body = "failed to serialize the response";
+            HyperResponse::new()
+                .with_status(StatusCode::InternalServerError)
+                .with_header(header::ContentType::plaintext())
+                .with_header(header::ContentLength(body.len() as u64))
+                .with_body(body)
+        }
+    }
+}
+
+/// A `201 Created` response whose body is `content`, unless it cannot be serialized.
+pub fn created<T: Serialize>(content: &T) -> HyperResponse {
+    let response = json_response(content);
+    if response.status() == StatusCode::Ok {
+        response.with_status(StatusCode::Created)
+    } else {
+        response
+    }
+}
+
+/// A page of a listing, along with the total number of items in `X-Total-Count`.
+pub fn page_response<T: Serialize>(total: usize, items: &[T]) -> HyperResponse {
+    let mut response = json_response(&items);
+    response.headers_mut().set_raw("X-Total-Count", total.to_string());
+    response
 }
 
 pub fn no_content() -> HyperResponse {
@@ -17,3 +57,207 @@ pub fn no_content() -> HyperResponse {
         .with_status(StatusCode::NoContent)
         .with_header(header::ContentLength(0))
 }
+
+thread_local!(static HANDLE: RefCell<Option<Handle>> = RefCell::new(None));
+
+/// Register the event loop which drives streaming response bodies on the current thread.
+pub fn set_handle(handle: &Handle) {
+    HANDLE.with(|h| *h.borrow_mut() = Some(handle.clone()));
+}
+
+/// Create a response body which pulls `chunks` only as fast as the client reads them.
+///
+/// Without a registered event loop (e.g. in tests), the chunks are collected upfront.
+pub fn streaming_body<I>(chunks: I) -> Body
+where
+    I: Iterator<Item = Vec<u8>> + 'static,
+{
+    match HANDLE.with(|h| h.borrow().clone()) {
+        Some(handle) => {
+            let (tx, body) = Body::pair();
+            let chunks = chunks.map(|chunk| Ok(Chunk::from(chunk)));
+            let chunks = stream::iter_ok::<_, SendError<Result<Chunk, hyper::Error>>>(chunks);
+            handle.spawn(tx.send_all(chunks).then(|_| Ok(())));
+            body
+        }
+        None => chunks.flat_map(|c| c).collect::<Vec<u8>>().into(),
+    }
+}
+
+pub fn etag(version: u64) -> header::ETag {
+    header::ETag(EntityTag::strong(version.to_string()))
+}
+
+/// Create an endpoint which extracts the versions requested by `If-Match`, if any.
+///
+/// Weak or non-numeric entity tags never match the version of an entity. The tag of a compressed
+/// representation carries the name of its encoding after a dash, which is ignored.
+pub fn if_match() -> impl Endpoint<Item = Option<Precondition>, Error = EndpointError> + Clone + 'static {
+    use finchers::endpoint::prelude::*;
+    use finchers::endpoint::header::header_opt;
+
+    header_opt().map(|if_match: Option<IfMatch>| {
+        if_match.map(|if_match| match if_match {
+            IfMatch::Any => Precondition::Any,
+            IfMatch::Items(tags) => Precondition::Versions(
+                tags.iter()
+                    .filter(|tag| !tag.weak)
+                    .filter_map(|tag| tag.tag().split('-').next().and_then(|version| version.parse().ok()))
+                    .collect(),
+            ),
+        })
+    })
+}
+
+/// Create an endpoint which extracts the token of `Authorization: Bearer`, if any.
+pub fn bearer_token() -> impl Endpoint<Item = Option<String>, Error = EndpointError> + Clone + 'static {
+    use finchers::endpoint::prelude::*;
+    use finchers::endpoint::header::header_opt;
+    use finchers::http::header::{Authorization, Bearer};
+
+    header_opt().map(|auth: Option<Authorization<Bearer>>| auth.map(|auth| auth.0.token))
+}
+
+/// The query string of the listings of orders.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub struct OrderQueryParam {
+    pub status: Option<OrderStatus>,
+    pub pet_id: Option<u64>,
+    pub complete: Option<bool>,
+    pub ship_date_from: Option<DateTime<Utc>>,
+    pub ship_date_to: Option<DateTime<Utc>>,
+    pub created_at_from: Option<DateTime<Utc>>,
+    pub created_at_to: Option<DateTime<Utc>>,
+    pub updated_at_from: Option<DateTime<Utc>>,
+    pub updated_at_to: Option<DateTime<Utc>>,
+    pub sort: Option<OrderSort>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl OrderQueryParam {
+    pub fn into_query(self, username: Option<String>) -> OrderQuery {
+        OrderQuery {
+            status: self.status,
+            pet_id: self.pet_id,
+            complete: self.complete,
+            username,
+            ship_date: Window {
+                from: self.ship_date_from,
+                to: self.ship_date_to,
+            },
+            created_at: Window {
+                from: self.created_at_from,
+                to: self.created_at_to,
+            },
+            updated_at: Window {
+                from: self.updated_at_from,
+                to: self.updated_at_to,
+            },
+            sort: self.sort.unwrap_or_default(),
+            offset: self.offset.unwrap_or(0),
+            limit: self.limit,
+        }
+    }
+}
+
+/// The query string of the listings of pets and users, along with their own parameters.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub struct TimestampQueryParam {
+    pub created_at_from: Option<DateTime<Utc>>,
+    pub created_at_to: Option<DateTime<Utc>>,
+    pub updated_at_from: Option<DateTime<Utc>>,
+    pub updated_at_to: Option<DateTime<Utc>>,
+    pub sort: Option<TimestampSort>,
+}
+
+impl TimestampQueryParam {
+    pub fn into_query(self) -> TimestampQuery {
+        TimestampQuery {
+            created_at: Window {
+                from: self.created_at_from,
+                to: self.created_at_to,
+            },
+            updated_at: Window {
+                from: self.updated_at_from,
+                to: self.updated_at_to,
+            },
+            sort: self.sort.unwrap_or_default(),
+        }
+    }
+}
+
+fn has_media_type(req: &Request, media_type: &str) -> bool {
+    req.header()
+        .map_or(false, |&ContentType(ref mime)| mime.as_ref().starts_with(media_type))
+}
+
+/// A request body of type `application/merge-patch+json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergePatch(pub Value);
+
+impl FromBody for MergePatch {
+    type Error = serde_json::Error;
+
+    fn validate(req: &Request) -> bool {
+        has_media_type(req, "application/merge-patch+json")
+    }
+
+    fn from_body(body: Vec<u8>) -> Result<Self, Self::Error> {
+        serde_json::from_slice(&body).map(MergePatch)
+    }
+}
+
+/// A request body of type `application/json-patch+json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPatch(pub Vec<PatchOperation>);
+
+impl FromBody for JsonPatch {
+    type Error = serde_json::Error;
+
+    fn validate(req: &Request) -> bool {
+        has_media_type(req, "application/json-patch+json")
+    }
+
+    fn from_body(body: Vec<u8>) -> Result<Self, Self::Error> {
+        serde_json::from_slice(&body).map(JsonPatch)
+    }
+}
+
+/// A request body in neither of the patch formats, so that a PATCH is answered with
+/// `415 Unsupported Media Type` rather than as if its route did not exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedPatch;
+
+impl UnsupportedPatch {
+    pub fn into_error(self) -> PetstoreError {
+        PetstoreErrorKind::UnsupportedMediaType(
+            "a patch is either application/merge-patch+json or application/json-patch+json".into(),
+        ).into()
+    }
+}
+
+impl FromBody for UnsupportedPatch {
+    /// Never returned, as the body is ignored.
+    type Error = serde_json::Error;
+
+    fn validate(req: &Request) -> bool {
+        !has_media_type(req, "application/merge-patch+json") && !has_media_type(req, "application/json-patch+json")
+    }
+
+    fn from_body(_: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(UnsupportedPatch)
+    }
+}
+
+impl From<MergePatch> for Patch {
+    fn from(MergePatch(patch): MergePatch) -> Self {
+        Patch::Merge(patch)
+    }
+}
+
+impl From<JsonPatch> for Patch {
+    fn from(JsonPatch(operations): JsonPatch) -> Self {
+        Patch::Json(operations)
+    }
+}
@@ -1,9 +1,12 @@
 pub mod common;
 pub mod pet;
+pub mod routes;
 pub mod store;
+pub mod trash;
 pub mod user;
 
 use finchers::{Endpoint, Handler};
+use context;
 use error::Error;
 use petstore::Petstore;
 
@@ -11,27 +14,48 @@ use petstore::Petstore;
 pub enum Request {
     Pet(pet::Request),
     Store(store::Request),
+    Trash(trash::Request),
     User(user::Request),
 }
 
+/// A request which is named after its route in logs and metrics.
+pub trait RouteName {
+    /// The name of the route, e.g. `pet::GetPet`.
+    fn route(&self) -> &'static str;
+}
+
+impl RouteName for Request {
+    fn route(&self) -> &'static str {
+        match *self {
+            Request::Pet(ref pet) => pet.route(),
+            Request::Store(ref store) => store.route(),
+            Request::Trash(ref trash) => trash.route(),
+            Request::User(ref user) => user.route(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Response {
     Pet(pet::Response),
     Store(store::Response),
+    Trash(trash::Response),
     User(user::Response),
 }
 
 mod imp {
     use api::common::*;
+    use context;
 
     impl IntoResponse for super::Response {
         fn into_response(self) -> HyperResponse {
             use super::Response::*;
-            match self {
+            context::span("serialize", || match self {
                 Pet(pet) => pet.into_response(),
                 Store(store) => store.into_response(),
+                Trash(trash) => trash.into_response(),
                 User(user) => user.into_response(),
-            }
+            })
         }
     }
 }
@@ -40,6 +64,7 @@ pub fn endpoint() -> impl Endpoint<Item = Request, Error = Error> + Clone + 'sta
     choice![
         pet::endpoint().from_ok_err(),
         store::endpoint().from_ok_err(),
+        trash::endpoint().from_ok_err(),
         user::endpoint().from_ok_err(),
     ]
 }
@@ -51,10 +76,12 @@ impl Handler<Request> for Petstore {
 
     fn call(&self, request: Request) -> Self::Result {
         use self::Request::*;
-        match request {
+        context::with_current(|context| context.set_route(request.route()));
+        context::span("store", || match request {
             Pet(pet) => self.call(pet).map(|r| r.map(Response::Pet)),
             Store(store) => self.call(store).map(|r| r.map(Response::Store)),
+            Trash(trash) => self.call(trash).map(|r| r.map(Response::Trash)),
             User(user) => self.call(user).map(|r| r.map(Response::User)),
-        }.map_err(Into::into)
+        }).map_err(Into::into)
     }
 }
@@ -1,7 +1,11 @@
 use finchers::{Endpoint, Handler};
-use model::{Pet, Status};
+use api::RouteName;
+use api::common::UnsupportedPatch;
+use bulk::{self, BulkFormat};
+use model::{Pet, Status, TimestampQuery};
 use error::EndpointError;
-use petstore::{Petstore, PetstoreError};
+use patch::Patch;
+use petstore::{PetExport, Petstore, PetstoreError, Precondition};
 use self::Request::*;
 use self::Response::*;
 
@@ -11,11 +15,32 @@ use self::Response::*;
 pub enum Request {
     GetPet(u64),
     AddPet(Pet),
-    UpdatePet(Pet),
-    DeletePet(u64),
-    FindPetsByStatuses(Vec<Status>),
-    FindPetsByTags(Vec<String>),
-    UpdatePetViaForm(u64, Option<String>, Option<Status>),
+    UpdatePet(Pet, Option<Precondition>),
+    PatchPet(u64, Patch, Option<Precondition>),
+    UnsupportedPatchPet(u64, UnsupportedPatch),
+    DeletePet(u64, Option<Precondition>),
+    RestorePet(u64),
+    FindPetsByStatuses(Vec<Status>, TimestampQuery),
+    FindPetsByTags(Vec<String>, TimestampQuery),
+    UpdatePetViaForm(u64, Option<String>, Option<Status>, Option<Precondition>),
+    ExportPets(BulkFormat),
+}
+
+impl RouteName for Request {
+    fn route(&self) -> &'static str {
+        match *self {
+            GetPet(..) => "pet::GetPet",
+            AddPet(..) => "pet::AddPet",
+            UpdatePet(..) => "pet::UpdatePet",
+            PatchPet(..) | UnsupportedPatchPet(..) => "pet::PatchPet",
+            DeletePet(..) => "pet::DeletePet",
+            RestorePet(..) => "pet::RestorePet",
+            FindPetsByStatuses(..) => "pet::FindPetsByStatuses",
+            FindPetsByTags(..) => "pet::FindPetsByTags",
+            UpdatePetViaForm(..) => "pet::UpdatePetViaForm",
+            ExportPets(..) => "pet::ExportPets",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -24,19 +49,27 @@ pub enum Response {
     PetCreated(u64),
     Pets(Vec<Pet>),
     PetDeleted,
+    PetsExported(BulkFormat, PetExport),
 }
 
 mod imp {
     use super::*;
     use api::common::*;
+    use finchers::http::header;
 
     impl IntoResponse for Response {
         fn into_response(self) -> HyperResponse {
             match self {
-                ThePet(pet) => json_response(&pet),
-                PetCreated(id) => json_response(&id).with_status(StatusCode::Created),
+                ThePet(pet) => json_response(&pet).with_header(etag(pet.version)),
+                PetCreated(id) => created(&id),
                 Pets(id) => json_response(&id),
                 PetDeleted => no_content(),
+                PetsExported(format, pets) => {
+                    let rows = pets.map(move |pet| bulk::encode_row(format, &pet));
+                    HyperResponse::new()
+                        .with_header(header::ContentType(format.content_type().parse().unwrap()))
+                        .with_body(streaming_body(bulk::header(format).into_iter().chain(rows)))
+                }
             }
         }
     }
@@ -46,6 +79,8 @@ pub fn endpoint() -> impl Endpoint<Item = Request, Error = EndpointError> + Clon
     use finchers::endpoint::prelude::*;
     use finchers_json::json_body;
     use finchers_urlencoded::serde::{from_csv, queries_req, Form};
+    use finchers_urlencoded::serde::queries_opt;
+    use api::common::{if_match, JsonPatch, MergePatch, TimestampQueryParam};
 
     #[derive(Debug, PartialEq, Deserialize)]
     pub struct FindPetsByStatusesParam {
@@ -63,19 +98,38 @@ pub fn endpoint() -> impl Endpoint<Item = Request, Error = EndpointError> + Clon
         pub status: Option<Status>,
     }
 
+    #[derive(Debug, PartialEq, Deserialize)]
+    pub struct ExportPetsParam {
+        pub format: BulkFormat,
+    }
+
     endpoint("pet").with(choice![
+        get("export")
+            .with(queries_req().from_err())
+            .map(|ExportPetsParam { format }| ExportPets(format)),
         get(path()).map(GetPet),
+        post((path(), "restore")).map(|(id, _): (u64, ())| RestorePet(id)),
         post(json_body().from_err()).map(AddPet),
-        put(json_body().from_err()).map(UpdatePet),
-        delete(path()).map(DeletePet),
+        put((json_body().from_err(), if_match())).map(|(pet, precondition)| UpdatePet(pet, precondition)),
+        patch((path(), body().from_err(), if_match()))
+            .map(|(id, MergePatch(patch), precondition)| PatchPet(id, Patch::Merge(patch), precondition)),
+        patch((path(), body().from_err(), if_match()))
+            .map(|(id, JsonPatch(operations), precondition)| PatchPet(id, Patch::Json(operations), precondition)),
+        patch((path(), body().from_err())).map(|(id, patch)| UnsupportedPatchPet(id, patch)),
+        delete((path(), if_match())).map(|(id, precondition)| DeletePet(id, precondition)),
         get("findByStatus")
-            .with(queries_req().from_err())
-            .map(|FindPetsByStatusesParam { status }| FindPetsByStatuses(status)),
+            .with((queries_req().from_err(), queries_opt().from_err()))
+            .map(|(FindPetsByStatusesParam { status }, param): (_, Option<TimestampQueryParam>)| {
+                FindPetsByStatuses(status, param.unwrap_or_default().into_query())
+            }),
         get("findByTags")
-            .with(queries_req().from_err())
-            .map(|FindPetsByTagsParam { tags }| FindPetsByTags(tags)),
-        post((path(), body().from_err()))
-            .map(|(id, Form(UpdatePetParam { name, status }))| UpdatePetViaForm(id, name, status))
+            .with((queries_req().from_err(), queries_opt().from_err()))
+            .map(|(FindPetsByTagsParam { tags }, param): (_, Option<TimestampQueryParam>)| {
+                FindPetsByTags(tags, param.unwrap_or_default().into_query())
+            }),
+        post((path(), body().from_err(), if_match())).map(
+            |(id, Form(UpdatePetParam { name, status }), precondition)| UpdatePetViaForm(id, name, status, precondition)
+        )
     ])
 }
 
@@ -88,12 +142,19 @@ impl Handler<Request> for Petstore {
         match request {
             GetPet(id) => self.get_pet(id).map(|p| p.map(ThePet)),
             AddPet(pet) => self.add_pet(pet).map(|id| Some(PetCreated(id))),
-            UpdatePet(pet) => self.update_pet(pet).map(|pet| Some(ThePet(pet))),
-            DeletePet(id) => self.delete_pet(id).map(|_| Some(PetDeleted)),
-            FindPetsByStatuses(status) => self.get_pets_by_status(status).map(|pets| Some(Pets(pets))),
-            FindPetsByTags(tags) => self.find_pets_by_tag(tags).map(|pets| Some(Pets(pets))),
-            UpdatePetViaForm(id, name, status) => self.update_pet_name_status(id, name, status)
+            UpdatePet(pet, precondition) => self.update_pet(pet, precondition)
                 .map(|pet| Some(ThePet(pet))),
+            PatchPet(id, patch, precondition) => self.patch_pet(id, &patch, precondition)
+                .map(|p| p.map(ThePet)),
+            UnsupportedPatchPet(_, patch) => Err(patch.into_error()),
+            DeletePet(id, precondition) => self.delete_pet(id, precondition).map(|_| Some(PetDeleted)),
+            RestorePet(id) => self.restore_pet(id).map(|pet| Some(ThePet(pet))),
+            FindPetsByStatuses(status, query) => self.get_pets_by_status(status, &query).map(|pets| Some(Pets(pets))),
+            FindPetsByTags(tags, query) => self.find_pets_by_tag(tags, &query).map(|pets| Some(Pets(pets))),
+            UpdatePetViaForm(id, name, status, precondition) => self
+                .update_pet_name_status(id, name, status, precondition)
+                .map(|pet| Some(ThePet(pet))),
+            ExportPets(format) => self.export_pets().map(|pets| Some(PetsExported(format, pets))),
         }
     }
 }
@@ -103,6 +164,8 @@ mod tests {
     use super::*;
     use finchers::http::HttpRequest;
     use finchers::test::EndpointTestExt;
+    use api::common::{IntoResponse, StatusCode};
+    use model::{TimestampSort, Window};
     use model::Status::*;
 
     #[test]
@@ -123,7 +186,7 @@ mod tests {
             .unwrap();
         assert_eq!(
             endpoint().run(request).map(|r| r.unwrap()),
-            Some(FindPetsByStatuses(vec![Available, Adopted]))
+            Some(FindPetsByStatuses(vec![Available, Adopted], Default::default()))
         );
     }
 
@@ -134,7 +197,23 @@ mod tests {
             .unwrap();
         assert_eq!(
             endpoint().run(request).map(|r| r.unwrap()),
-            Some(FindPetsByTags(vec!["cat".into(), "cute".into()])),
+            Some(FindPetsByTags(vec!["cat".into(), "cute".into()], Default::default())),
+        );
+
+        let request = HttpRequest::get("/pet/findByTags?tags=cat&created_at_from=2018-01-01T00:00:00Z&sort=-created_at")
+            .body(Default::default())
+            .unwrap();
+        let query = TimestampQuery {
+            created_at: Window {
+                from: Some("2018-01-01T00:00:00Z".parse().unwrap()),
+                to: None,
+            },
+            updated_at: Window::default(),
+            sort: TimestampSort::CreatedAtDesc,
+        };
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(FindPetsByTags(vec!["cat".into()], query)),
         );
     }
 
@@ -145,7 +224,64 @@ mod tests {
             .unwrap();
         assert_eq!(
             endpoint().run(request).map(|r| r.unwrap()),
-            Some(UpdatePetViaForm(42, Some("Alice".into()), Some(Available),))
+            Some(UpdatePetViaForm(42, Some("Alice".into()), Some(Available), None))
+        );
+    }
+
+    #[test]
+    fn test_restore_pet() {
+        let request = HttpRequest::post("/pet/42/restore")
+            .body(Default::default())
+            .unwrap();
+        assert_eq!(endpoint().run(request).map(|r| r.unwrap()), Some(RestorePet(42)));
+    }
+
+    #[test]
+    fn test_export_pets() {
+        let request = HttpRequest::get("/pet/export?format=csv")
+            .body(Default::default())
+            .unwrap();
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(ExportPets(BulkFormat::Csv)),
+        );
+    }
+
+    #[test]
+    fn test_patch_pet() {
+        let request = HttpRequest::patch("/pet/42")
+            .header("Content-Type", "application/merge-patch+json")
+            .body(r#"{"name":"Alice"}"#.into())
+            .unwrap();
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(PatchPet(42, Patch::Merge(json!({"name": "Alice"})), None)),
+        );
+    }
+
+    #[test]
+    fn test_patch_pet_unsupported_media_type() {
+        let request = HttpRequest::patch("/pet/42")
+            .header("Content-Type", "application/json")
+            .body(r#"{"name":"Alice"}"#.into())
+            .unwrap();
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(UnsupportedPatchPet(42, UnsupportedPatch)),
+        );
+        let error = Petstore::new().call(UnsupportedPatchPet(42, UnsupportedPatch)).unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn test_delete_pet_if_match() {
+        let request = HttpRequest::delete("/pet/42")
+            .header("If-Match", "\"3\", W/\"4\", \"5-gzip\"")
+            .body(Default::default())
+            .unwrap();
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(DeletePet(42, Some(Precondition::Versions(vec![3, 5])))),
         );
     }
 }
@@ -0,0 +1,138 @@
+//! The table of the routes served by `api::endpoint`, for the code which has to know
+//! them without matching a request, e.g. to answer `OPTIONS`.
+//!
+//! It has to be kept in sync with the endpoints of each module, which `test_routes_match_endpoints`
+//! checks.
+
+use hyper::Method;
+use hyper::Method::*;
+
+/// A route, whose path segments are either literal or a parameter:
+/// `{id}` matches a numeric identifier and `{name}` any segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub method: Method,
+    pub path: &'static str,
+}
+
+pub fn routes() -> Vec<Route> {
+    vec![
+        (Post, "/pet/bulk"),
+        (Get, "/pet/export"),
+        (Get, "/pet/{id}"),
+        (Post, "/pet"),
+        (Put, "/pet"),
+        (Patch, "/pet/{id}"),
+        (Delete, "/pet/{id}"),
+        (Post, "/pet/{id}/restore"),
+        (Get, "/pet/findByStatus"),
+        (Get, "/pet/findByTags"),
+        (Post, "/pet/{id}"),
+        (Get, "/store/inventory"),
+        (Get, "/store/check"),
+        (Get, "/store/reports/adoptions"),
+        (Post, "/store/order"),
+        (Get, "/store/order"),
+        (Delete, "/store/order/{id}"),
+        (Get, "/store/order/{id}"),
+        (Get, "/user/login"),
+        (Get, "/user/logout"),
+        (Get, "/user/{name}/orders"),
+        (Get, "/user/{name}"),
+        (Delete, "/user/{name}"),
+        (Patch, "/user/{name}"),
+        (Put, "/user/{name}"),
+        (Get, "/user"),
+        (Post, "/user"),
+        (Put, "/user"),
+        (Post, "/user/createWithList"),
+        (Post, "/user/createWithArray"),
+        (Get, "/trash"),
+    ].into_iter()
+        .map(|(method, path)| Route { method, path })
+        .collect()
+}
+
+impl Route {
+    pub fn matches(&self, path: &str) -> bool {
+        let mut segments = path.split('/').filter(|s| !s.is_empty());
+        let mut patterns = self.path.split('/').filter(|s| !s.is_empty());
+        loop {
+            match (patterns.next(), segments.next()) {
+                (None, None) => return true,
+                (Some("{id}"), Some(segment)) if segment.parse::<u64>().is_ok() => {}
+                (Some("{name}"), Some(_)) => {}
+                (Some(pattern), Some(segment)) if pattern == segment => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// The methods of the routes matching `path`, which is not served at all if it is empty.
+pub fn allowed_methods(path: &str) -> Vec<Method> {
+    let mut methods: Vec<Method> = vec![];
+    for route in routes() {
+        if route.matches(path) && !methods.contains(&route.method) {
+            methods.push(route.method);
+        }
+    }
+    methods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use finchers::http::HttpRequest;
+    use finchers::test::EndpointTestExt;
+    use api;
+
+    /// A path matched by `route`, with the query string its endpoint requires.
+    fn sample(route: &Route) -> String {
+        let path = route.path.replace("{id}", "42").replace("{name}", "alice");
+        let query = match route.path {
+            "/pet/export" => "?format=csv",
+            "/pet/findByStatus" => "?status=available",
+            "/pet/findByTags" => "?tags=cat",
+            "/user/login" => "?username=alice&password=secret",
+            _ => "",
+        };
+        path + query
+    }
+
+    #[test]
+    fn test_routes_match_endpoints() {
+        let methods = vec![Get, Post, Put, Patch, Delete];
+        for route in routes() {
+            // Served by the `BulkImport` middleware rather than by an endpoint.
+            if route.path == "/pet/bulk" {
+                continue;
+            }
+            let uri = sample(&route);
+            let path = uri.split('?').next().unwrap().to_owned();
+            for method in &methods {
+                let request = HttpRequest::builder()
+                    .method(method.as_ref())
+                    .uri(&uri[..])
+                    .body(Default::default())
+                    .unwrap();
+                let served = api::endpoint().run(request).is_some();
+                assert_eq!(
+                    served,
+                    allowed_methods(&path).contains(method),
+                    "{} {} is served by an endpoint but not in the route table, or the other way around",
+                    method,
+                    uri
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        assert_eq!(allowed_methods("/pet"), vec![Post, Put]);
+        assert_eq!(allowed_methods("/pet/42"), vec![Get, Patch, Delete, Post]);
+        assert_eq!(allowed_methods("/pet/findByStatus"), vec![Get]);
+        assert_eq!(allowed_methods("/store/order/foo"), vec![]);
+    }
+}
@@ -1,24 +1,47 @@
+use std::collections::BTreeMap;
 use finchers::{Endpoint, Handler};
+use api::RouteName;
 use error::EndpointError;
-use model::{Inventory, Order};
-use petstore::{Petstore, PetstoreError};
+use model::{AdoptionCount, Granularity, Inventory, InventoryGrouping, Order, OrderQuery, Window};
+use petstore::{Petstore, PetstoreError, Precondition};
 use self::Request::*;
 use self::Response::*;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Request {
-    GetInventory,
-    AddOrder(Order),
-    DeleteOrder(u64),
+    GetInventory(Option<InventoryGrouping>, Window),
+    ReportAdoptions(Window, Granularity),
+    AddOrder(Order, Option<String>),
+    DeleteOrder(u64, Option<Precondition>),
     FindOrder(u64),
+    ListOrders(OrderQuery),
+    CheckConsistency,
+}
+
+impl RouteName for Request {
+    fn route(&self) -> &'static str {
+        match *self {
+            GetInventory(..) => "store::GetInventory",
+            ReportAdoptions(..) => "store::ReportAdoptions",
+            AddOrder(..) => "store::AddOrder",
+            DeleteOrder(..) => "store::DeleteOrder",
+            FindOrder(..) => "store::FindOrder",
+            ListOrders(..) => "store::ListOrders",
+            CheckConsistency => "store::CheckConsistency",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Response {
     TheInventory(Inventory),
+    InventoryBreakdown(BTreeMap<String, Inventory>),
+    Adoptions(Vec<AdoptionCount>),
     TheOrder(Order),
     OrderCreated(u64),
     OrderDeleted(bool),
+    Orders(usize, Vec<Order>),
+    Problems(Vec<String>),
 }
 
 mod imp {
@@ -29,25 +52,61 @@ mod imp {
         fn into_response(self) -> HyperResponse {
             match self {
                 TheInventory(inventory) => json_response(&inventory),
-                TheOrder(order) => json_response(&order),
-                OrderCreated(id) => json_response(&id).with_status(StatusCode::Created),
+                InventoryBreakdown(inventories) => json_response(&inventories),
+                Adoptions(series) => json_response(&series),
+                TheOrder(order) => json_response(&order).with_header(etag(order.version)),
+                OrderCreated(id) => created(&id),
                 OrderDeleted(deleted) => json_response(&deleted),
+                Orders(total, orders) => page_response(total, &orders),
+                Problems(problems) => json_response(&problems),
             }
         }
     }
 }
 
 pub fn endpoint() -> impl Endpoint<Item = Request, Error = EndpointError> + Clone + 'static {
+    use chrono::{DateTime, Utc};
     use finchers::endpoint::prelude::*;
-    use finchers::endpoint::ok;
     use finchers_json::json_body;
+    use finchers_urlencoded::serde::queries_opt;
+    use api::common::{bearer_token, if_match, OrderQueryParam};
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    pub struct InventoryParam {
+        pub by: Option<InventoryGrouping>,
+        pub from: Option<DateTime<Utc>>,
+        pub to: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    pub struct ReportParam {
+        pub from: Option<DateTime<Utc>>,
+        pub to: Option<DateTime<Utc>>,
+        pub granularity: Option<Granularity>,
+    }
 
     endpoint("store").with(choice![
-        get("inventory").with(ok(GetInventory)),
+        get("inventory")
+            .with(queries_opt().from_err())
+            .map(|param: Option<InventoryParam>| {
+                let InventoryParam { by, from, to } = param.unwrap_or_default();
+                GetInventory(by, Window { from, to })
+            }),
+        endpoint("reports").with(
+            get("adoptions")
+                .with(queries_opt().from_err())
+                .map(|param: Option<ReportParam>| {
+                    let ReportParam { from, to, granularity } = param.unwrap_or_default();
+                    ReportAdoptions(Window { from, to }, granularity.unwrap_or(Granularity::Day))
+                })
+        ),
+        get("check").map(|_| CheckConsistency),
         endpoint("order").with(choice![
-            post(json_body().from_err()).map(AddOrder),
-            delete(path()).map(DeleteOrder),
+            post((json_body().from_err(), bearer_token())).map(|(order, token)| AddOrder(order, token)),
+            delete((path(), if_match())).map(|(id, precondition)| DeleteOrder(id, precondition)),
             get(path()).map(FindOrder),
+            get(queries_opt().from_err())
+                .map(|param: Option<OrderQueryParam>| ListOrders(param.unwrap_or_default().into_query(None))),
         ]),
     ])
 }
@@ -59,11 +118,89 @@ impl Handler<Request> for Petstore {
 
     fn call(&self, request: Request) -> Self::Result {
         match request {
-            GetInventory => self.get_inventory().map(|i| Some(TheInventory(i))),
-            AddOrder(order) => self.add_order(order).map(|id| Some(OrderCreated(id))),
-            DeleteOrder(id) => self.delete_order(id)
+            GetInventory(None, window) => self.get_inventory_within(&window).map(|i| Some(TheInventory(i))),
+            GetInventory(Some(grouping), window) => self.get_inventory_by(grouping, &window)
+                .map(|i| Some(InventoryBreakdown(i))),
+            ReportAdoptions(window, granularity) => self.report_adoptions(&window, granularity)
+                .map(|series| Some(Adoptions(series))),
+            AddOrder(mut order, token) => {
+                // The user is only known from the session, whatever the order claims.
+                order.username = match token {
+                    Some(token) => Some(self.session_user(&token)?),
+                    None => None,
+                };
+                self.add_order(order).map(|id| Some(OrderCreated(id)))
+            }
+            DeleteOrder(id, precondition) => self.delete_order(id, precondition)
                 .map(|deleted| Some(OrderDeleted(deleted))),
-            FindOrder(id) => self.find_order(id).map(|o| o.map(TheOrder)),
+            FindOrder(id) => self.find_order(id).map(|o| o.map(|order| TheOrder(anonymous(order)))),
+            ListOrders(query) => self.find_orders(&query)
+                .map(|(total, orders)| Some(Orders(total, orders.into_iter().map(anonymous).collect()))),
+            CheckConsistency => self.check_consistency().map(|problems| Some(Problems(problems))),
         }
     }
 }
+
+/// An order as shown to anyone, without the user who placed it, which only the routes
+/// authorized by the session of that user show.
+fn anonymous(order: Order) -> Order {
+    Order {
+        username: None,
+        ..order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use model::{OrderSort, OrderStatus};
+    use finchers::http::HttpRequest;
+    use finchers::test::EndpointTestExt;
+
+    #[test]
+    fn test_get_inventory() {
+        let request = HttpRequest::get("/store/inventory").body(Default::default()).unwrap();
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(GetInventory(None, Window::default()))
+        );
+
+        let request = HttpRequest::get("/store/inventory?by=tag&from=2018-01-01T00:00:00Z")
+            .body(Default::default())
+            .unwrap();
+        let window = Window {
+            from: Some(Utc.ymd(2018, 1, 1).and_hms(0, 0, 0)),
+            to: None,
+        };
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(GetInventory(Some(InventoryGrouping::Tag), window))
+        );
+    }
+
+    #[test]
+    fn test_list_orders() {
+        let request = HttpRequest::get("/store/order?status=placed&sort=-ship_date&limit=10")
+            .body(Default::default())
+            .unwrap();
+        let query = OrderQuery {
+            status: Some(OrderStatus::Placed),
+            sort: OrderSort::ShipDateDesc,
+            limit: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(endpoint().run(request).map(|r| r.unwrap()), Some(ListOrders(query)));
+    }
+
+    #[test]
+    fn test_report_adoptions() {
+        let request = HttpRequest::get("/store/reports/adoptions?granularity=month")
+            .body(Default::default())
+            .unwrap();
+        assert_eq!(
+            endpoint().run(request).map(|r| r.unwrap()),
+            Some(ReportAdoptions(Window::default(), Granularity::Month))
+        );
+    }
+}
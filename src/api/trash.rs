@@ -0,0 +1,90 @@
+use finchers::{Endpoint, Handler};
+use api::RouteName;
+use error::EndpointError;
+use model::Trash;
+use petstore::{Petstore, PetstoreError};
+use self::Request::*;
+use self::Response::*;
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    GetTrash(usize, Option<usize>),
+}
+
+impl RouteName for Request {
+    fn route(&self) -> &'static str {
+        match *self {
+            GetTrash(..) => "trash::GetTrash",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Response {
+    TheTrash(usize, Trash),
+}
+
+mod imp {
+    use super::*;
+    use api::common::*;
+
+    impl IntoResponse for Response {
+        fn into_response(self) -> HyperResponse {
+            match self {
+                TheTrash(total, trash) => {
+                    let mut response = json_response(&trash);
+                    response.headers_mut().set_raw("X-Total-Count", total.to_string());
+                    response
+                }
+            }
+        }
+    }
+}
+
+pub fn endpoint() -> impl Endpoint<Item = Request, Error = EndpointError> + Clone + 'static {
+    use finchers::endpoint::prelude::*;
+    use finchers_urlencoded::serde::queries_opt;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    pub struct GetTrashParam {
+        pub offset: Option<usize>,
+        pub limit: Option<usize>,
+    }
+
+    get(("trash", queries_opt().from_err())).map(|(_, param): ((), Option<GetTrashParam>)| {
+        let GetTrashParam { offset, limit } = param.unwrap_or_default();
+        GetTrash(offset.unwrap_or(0), limit)
+    })
+}
+
+impl Handler<Request> for Petstore {
+    type Item = Response;
+    type Error = PetstoreError;
+    type Result = Result<Option<Self::Item>, Self::Error>;
+
+    fn call(&self, request: Request) -> Self::Result {
+        match request {
+            GetTrash(offset, limit) => self.trash(offset, limit).map(|(total, trash)| Some(TheTrash(total, trash))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use finchers::http::HttpRequest;
+    use finchers::test::EndpointTestExt;
+
+    #[test]
+    fn test_get_trash() {
+        let request = HttpRequest::get("/trash")
+            .body(Default::default())
+            .unwrap();
+        assert_eq!(endpoint().run(request).map(|r| r.unwrap()), Some(GetTrash(0, None)));
+
+        let request = HttpRequest::get("/trash?offset=10&limit=5")
+            .body(Default::default())
+            .unwrap();
+        assert_eq!(endpoint().run(request).map(|r| r.unwrap()), Some(GetTrash(10, Some(5))));
+    }
+}
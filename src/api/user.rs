@@ -1,16 +1,26 @@
 use finchers::{Endpoint, Handler};
 
+use api::RouteName;
+use api::common::UnsupportedPatch;
 use error::EndpointError;
-use model::User;
-use petstore::{Petstore, PetstoreError};
+use model::{Order, OrderQuery, PublicUser, TimestampQuery, User};
+use patch::Patch;
+use petstore::{Petstore, PetstoreError, PetstoreErrorKind, Precondition, Session};
 
+/// The requests on a user's own account carry the token of their session last.
 #[derive(Debug, PartialEq)]
 pub enum Request {
     AddUser(User),
     AddUsersViaList(Vec<User>),
-    DeleteUser(String),
+    DeleteUser(String, Option<Precondition>, Option<String>),
     GetUser(String),
-    UpdateUser(User),
+    UpdateUser(User, Option<Precondition>, Option<String>),
+    PatchUser(String, Patch, Option<Precondition>, Option<String>),
+    UnsupportedPatchUser(String, UnsupportedPatch),
+    LoginUser(String, String),
+    LogoutUser(Option<String>),
+    ListUserOrders(String, OrderQuery, Option<String>),
+    ListUsers(TimestampQuery, usize, Option<usize>),
 }
 
 #[derive(Debug)]
@@ -19,11 +29,32 @@ pub enum Response {
     UsersCreated(Vec<String>),
     TheUser(User),
     UserDeleted,
+    LoggedIn(Session),
+    LoggedOut,
+    UserOrders(usize, Vec<Order>),
+    Users(usize, Vec<PublicUser>),
 }
 
 use self::Request::*;
 use self::Response::*;
 
+impl RouteName for Request {
+    fn route(&self) -> &'static str {
+        match *self {
+            AddUser(..) => "user::AddUser",
+            AddUsersViaList(..) => "user::AddUsersViaList",
+            DeleteUser(..) => "user::DeleteUser",
+            GetUser(..) => "user::GetUser",
+            UpdateUser(..) => "user::UpdateUser",
+            PatchUser(..) | UnsupportedPatchUser(..) => "user::PatchUser",
+            LoginUser(..) => "user::LoginUser",
+            LogoutUser(..) => "user::LogoutUser",
+            ListUserOrders(..) => "user::ListUserOrders",
+            ListUsers(..) => "user::ListUsers",
+        }
+    }
+}
+
 mod imp {
     use super::*;
     use api::common::*;
@@ -31,10 +62,20 @@ mod imp {
     impl IntoResponse for Response {
         fn into_response(self) -> HyperResponse {
             match self {
-                UserCreated(username) => json_response(&username).with_status(StatusCode::Created),
-                UsersCreated(usernames) => json_response(&usernames).with_status(StatusCode::Created),
-                TheUser(user) => json_response(&user),
+                UserCreated(username) => created(&username),
+                UsersCreated(usernames) => created(&usernames),
+                TheUser(user) => json_response(&user).with_header(etag(user.version)),
                 UserDeleted => no_content(),
+                LoggedIn(session) => {
+                    let mut response = json_response(&session.token);
+                    response
+                        .headers_mut()
+                        .set_raw("X-Expires-After", session.expires_at.to_rfc3339());
+                    response
+                }
+                LoggedOut => no_content(),
+                UserOrders(total, orders) => page_response(total, &orders),
+                Users(total, users) => page_response(total, &users),
             }
         }
     }
@@ -43,18 +84,64 @@ mod imp {
 pub fn endpoint() -> impl Endpoint<Item = Request, Error = EndpointError> + Clone + 'static {
     use finchers::endpoint::prelude::*;
     use finchers_json::json_body;
+    use finchers_urlencoded::serde::{queries_opt, queries_req};
+    use api::common::{bearer_token, if_match, JsonPatch, MergePatch, OrderQueryParam, TimestampQueryParam};
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    pub struct LoginParam {
+        pub username: String,
+        pub password: String,
+    }
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    pub struct ListUsersParam {
+        pub offset: Option<usize>,
+        pub limit: Option<usize>,
+    }
 
     endpoint("user").with(choice![
+        get("login")
+            .with(queries_req().from_err())
+            .map(|LoginParam { username, password }| LoginUser(username, password)),
+        get("logout").with(bearer_token()).map(LogoutUser),
+        get((path(), "orders", queries_opt().from_err(), bearer_token())).map(
+            |(name, _, param, token): (String, (), Option<OrderQueryParam>, Option<String>)| {
+                let query = param.unwrap_or_default().into_query(Some(name.clone()));
+                ListUserOrders(name, query, token)
+            }
+        ),
         get(path()).map(GetUser),
-        delete(path()).map(DeleteUser),
+        delete((path(), if_match(), bearer_token()))
+            .map(|(name, precondition, token)| DeleteUser(name, precondition, token)),
+        patch((path(), body().from_err(), if_match(), bearer_token())).map(
+            |(name, MergePatch(patch), precondition, token)| PatchUser(name, Patch::Merge(patch), precondition, token)
+        ),
+        patch((path(), body().from_err(), if_match(), bearer_token())).map(
+            |(name, JsonPatch(operations), precondition, token)| {
+                PatchUser(name, Patch::Json(operations), precondition, token)
+            }
+        ),
+        patch((path(), body().from_err())).map(|(name, patch)| UnsupportedPatchUser(name, patch)),
         post(json_body().from_err()).map(AddUser),
-        put(json_body().from_err()).map(UpdateUser),
+        put((path(), json_body().from_err(), if_match(), bearer_token())).map(
+            |(name, user, precondition, token): (String, User, _, _)| {
+                UpdateUser(User { username: name, ..user }, precondition, token)
+            }
+        ),
+        put((json_body().from_err(), if_match(), bearer_token()))
+            .map(|(user, precondition, token)| UpdateUser(user, precondition, token)),
         post("createWithList")
             .with(json_body().from_err())
             .map(AddUsersViaList),
         post("createWithArray")
             .with(json_body().from_err())
             .map(AddUsersViaList),
+        get((queries_opt().from_err(), queries_opt().from_err())).map(
+            |(param, query): (Option<ListUsersParam>, Option<TimestampQueryParam>)| {
+                let ListUsersParam { offset, limit } = param.unwrap_or_default();
+                ListUsers(query.unwrap_or_default().into_query(), offset.unwrap_or(0), limit)
+            }
+        ),
     ])
 }
 
@@ -67,9 +154,36 @@ impl Handler<Request> for Petstore {
         match request {
             AddUser(new_user) => self.add_user(new_user).map(|u| Some(UserCreated(u))),
             AddUsersViaList(users) => self.add_users(users).map(|u| Some(UsersCreated(u))),
-            DeleteUser(name) => self.delete_user(name).map(|_| Some(UserDeleted)),
+            DeleteUser(name, precondition, token) => {
+                self.authorize(token.as_ref().map(String::as_str), &name)?;
+                self.delete_user(name, precondition).map(|_| Some(UserDeleted))
+            }
             GetUser(name) => self.get_user(name).map(|u| u.map(TheUser)),
-            UpdateUser(user) => self.update_user(user).map(|user| Some(TheUser(user))),
+            UpdateUser(user, precondition, token) => {
+                self.authorize(token.as_ref().map(String::as_str), &user.username)?;
+                self.update_user(user, precondition).map(|user| Some(TheUser(user)))
+            }
+            PatchUser(name, patch, precondition, token) => {
+                self.authorize(token.as_ref().map(String::as_str), &name)?;
+                self.patch_user(name, &patch, precondition).map(|u| u.map(TheUser))
+            }
+            UnsupportedPatchUser(_, patch) => Err(patch.into_error()),
+            LoginUser(username, password) => self.login(username, password).map(|s| Some(LoggedIn(s))),
+            LogoutUser(token) => {
+                if let Some(token) = token {
+                    self.logout(&token)?;
+                }
+                Ok(Some(LoggedOut))
+            }
+            ListUserOrders(name, query, token) => {
+                self.authorize(token.as_ref().map(String::as_str), &name)?;
+                if self.get_user(name)?.is_none() {
+                    bail!(PetstoreErrorKind::MissingUser("This user doesn't exist".into()));
+                }
+                self.find_orders(&query).map(|(total, orders)| Some(UserOrders(total, orders)))
+            }
+            ListUsers(query, offset, limit) => self.find_users(&query, offset, limit)
+                .map(|(total, users)| Some(Users(total, users))),
         }
     }
 }
@@ -0,0 +1,342 @@
+//! Row-oriented encodings of pets used by bulk import and export.
+
+use std::io::Read;
+use csv;
+use serde_json;
+use model::{Category, Pet, Status, Tag};
+
+/// The separator of multi-valued CSV columns (`tags` and `photo_urls`).
+const CSV_SEPARATOR: char = '|';
+const CSV_HEADER: &'static [&'static str] = &["id", "name", "category", "tags", "status", "photo_urls"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkFormat {
+    Ndjson,
+    Csv,
+}
+
+impl BulkFormat {
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            BulkFormat::Ndjson => "application/x-ndjson",
+            BulkFormat::Csv => "text/csv",
+        }
+    }
+}
+
+/// The outcome of importing a single row. Rows are numbered from 1, not counting the CSV header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowResult {
+    pub row: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CsvPet {
+    id: Option<u64>,
+    name: String,
+    category: Option<String>,
+    tags: Option<String>,
+    status: Option<Status>,
+    photo_urls: Option<String>,
+}
+
+impl From<CsvPet> for Pet {
+    fn from(row: CsvPet) -> Self {
+        let split = |s: String| -> Vec<String> {
+            s.split(CSV_SEPARATOR)
+                .filter(|s| !s.is_empty())
+                .map(ToOwned::to_owned)
+                .collect()
+        };
+        Pet {
+            id: row.id,
+            name: row.name,
+            photo_urls: row.photo_urls.map(&split).unwrap_or_default(),
+            category: row.category.and_then(|name| {
+                if name.is_empty() {
+                    None
+                } else {
+                    Some(Category { id: None, name })
+                }
+            }),
+            tags: row.tags.map(|tags| {
+                split(tags)
+                    .into_iter()
+                    .map(|name| Tag { id: None, name })
+                    .collect()
+            }),
+            status: row.status,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        }
+    }
+}
+
+/// Parses a bulk payload as its chunks arrive, without buffering more than the current row.
+///
+/// Rows are numbered from 1, not counting the CSV header. Blank lines are skipped. A row longer
+/// than the maximum size is reported as an error, and the rest of it is skipped.
+#[derive(Debug)]
+pub struct RowParser {
+    format: BulkFormat,
+    buf: Vec<u8>,
+    /// The number of bytes of `buf` already scanned for the end of a row.
+    scanned: usize,
+    in_quotes: bool,
+    /// Whether the bytes up to the next newline belong to a row already reported as too long.
+    skipping: bool,
+    max_row_size: Option<usize>,
+    header: Option<Vec<u8>>,
+    row: usize,
+}
+
+impl RowParser {
+    pub fn new(format: BulkFormat) -> Self {
+        RowParser {
+            format,
+            buf: vec![],
+            scanned: 0,
+            in_quotes: false,
+            skipping: false,
+            max_row_size: None,
+            header: None,
+            row: 0,
+        }
+    }
+
+    /// Set the maximum size of a row, including its newline.
+    pub fn max_row_size(mut self, max: usize) -> Self {
+        self.max_row_size = Some(max);
+        self
+    }
+
+    fn is_too_long(&self, len: usize) -> bool {
+        self.max_row_size.map_or(false, |max| len > max)
+    }
+
+    /// Report the current row as too long.
+    fn too_long(&mut self) -> (usize, Result<Pet, String>) {
+        self.row += 1;
+        let max = self.max_row_size.unwrap_or_default();
+        (self.row, Err(format!("the row is longer than {} bytes", max)))
+    }
+
+    /// Parse the rows completed by `chunk`.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<(usize, Result<Pet, String>)> {
+        self.buf.extend_from_slice(chunk);
+        let mut rows = vec![];
+        let mut start = 0;
+        for i in self.scanned..self.buf.len() {
+            match self.buf[i] {
+                b'\n' if self.skipping => {
+                    self.skipping = false;
+                    start = i + 1;
+                }
+                _ if self.skipping => {}
+                // Newlines within a quoted CSV field do not end the row.
+                b'"' if self.format == BulkFormat::Csv => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => {
+                    if self.is_too_long(i + 1 - start) {
+                        rows.push(self.too_long());
+                    } else {
+                        let line = self.buf[start..i + 1].to_vec();
+                        rows.extend(self.parse_line(&line));
+                    }
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        if self.skipping {
+            start = self.buf.len();
+        } else if self.is_too_long(self.buf.len() - start) {
+            rows.push(self.too_long());
+            self.skipping = true;
+            self.in_quotes = false;
+            start = self.buf.len();
+        }
+        self.buf.drain(..start);
+        self.scanned = self.buf.len();
+        rows
+    }
+
+    /// Parse the last row, which may lack a trailing newline.
+    pub fn finish(mut self) -> Option<(usize, Result<Pet, String>)> {
+        if self.skipping {
+            return None;
+        }
+        let line = ::std::mem::replace(&mut self.buf, vec![]);
+        if self.in_quotes && !line.iter().all(|&b| (b as char).is_whitespace()) {
+            self.row += 1;
+            return Some((self.row, Err("the row has an unterminated quoted field".into())));
+        }
+        self.parse_line(&line)
+    }
+
+    fn parse_line(&mut self, line: &[u8]) -> Option<(usize, Result<Pet, String>)> {
+        if line.iter().all(|&b| (b as char).is_whitespace()) {
+            return None;
+        }
+        let pet = match self.format {
+            BulkFormat::Ndjson => serde_json::from_slice(line).map_err(|e| e.to_string()),
+            BulkFormat::Csv if self.header.is_none() => {
+                self.header = Some(line.to_vec());
+                return None;
+            }
+            BulkFormat::Csv => {
+                let header = self.header.as_ref().map_or(&[][..], |header| &header[..]);
+                let mut reader = csv::Reader::from_reader(header.chain(line));
+                let mut records = reader.deserialize();
+                let pet = records
+                    .next()
+                    .expect("a non-blank line is a record")
+                    .map(|row: CsvPet| row.into())
+                    .map_err(|e| e.to_string());
+                // The quotes of a field not starting with one are literal, whereas they were
+                // counted to find the end of the row.
+                match records.next() {
+                    Some(_) => Err("the row holds several records, one of its quotes is unbalanced".into()),
+                    None => pet,
+                }
+            }
+        };
+        self.row += 1;
+        Some((self.row, pet))
+    }
+}
+
+/// The header written before any row, if the format has one.
+pub fn header(format: BulkFormat) -> Option<Vec<u8>> {
+    match format {
+        BulkFormat::Ndjson => None,
+        BulkFormat::Csv => Some(csv_line(CSV_HEADER)),
+    }
+}
+
+/// Encode a pet as a single, newline-terminated row.
+pub fn encode_row(format: BulkFormat, pet: &Pet) -> Vec<u8> {
+    match format {
+        BulkFormat::Ndjson => {
+            let mut line = serde_json::to_vec(pet).expect("a pet is always serializable");
+            line.push(b'\n');
+            line
+        }
+        BulkFormat::Csv => {
+            let join = |items: Vec<&str>| items.join(&CSV_SEPARATOR.to_string());
+            let id = pet.id.map(|id| id.to_string()).unwrap_or_default();
+            let category = pet.category.as_ref().map_or("", |c| &c.name);
+            let tags = pet.tags
+                .as_ref()
+                .map(|tags| join(tags.iter().map(|t| &t.name[..]).collect()))
+                .unwrap_or_default();
+            let status = pet.status.map(|s| s.to_string()).unwrap_or_default();
+            let photo_urls = join(pet.photo_urls.iter().map(|u| &u[..]).collect());
+            csv_line(&[&id, &pet.name, category, &tags, &status, &photo_urls])
+        }
+    }
+}
+
+fn csv_line(fields: &[&str]) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record(fields)
+        .expect("writing to a Vec never fails");
+    writer.into_inner().expect("writing to a Vec never fails")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::Status::*;
+
+    const ROW: &'static [u8] = b"{\"name\":\"doggie\",\"photo_urls\":[]}\n";
+
+    #[test]
+    fn test_csv_round_trip() {
+        let pet = Pet {
+            id: None,
+            name: "Alice, the cat".into(),
+            photo_urls: vec!["http://example.com/a.png".into()],
+            category: Some(Category {
+                id: None,
+                name: "cats".into(),
+            }),
+            tags: Some(vec![
+                Tag {
+                    id: None,
+                    name: "cute".into(),
+                },
+                Tag {
+                    id: None,
+                    name: "small".into(),
+                },
+            ]),
+            status: Some(Available),
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        };
+        let mut data = header(BulkFormat::Csv).unwrap();
+        data.extend(encode_row(BulkFormat::Csv, &pet));
+        data.extend(b"oops,,,,unknown,\n".iter());
+
+        // Feed the payload in small chunks, which split rows and quoted fields.
+        let mut parser = RowParser::new(BulkFormat::Csv);
+        let mut rows = vec![];
+        for chunk in data.chunks(7) {
+            rows.extend(parser.push(chunk));
+        }
+        rows.extend(parser.finish());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], (1, Ok(pet)));
+        assert_eq!(rows[1].0, 2);
+        assert!(rows[1].1.is_err());
+    }
+
+    fn parse(parser: RowParser, data: &[u8]) -> Vec<(usize, Result<Pet, String>)> {
+        let mut parser = parser;
+        let mut rows = vec![];
+        for chunk in data.chunks(5) {
+            rows.extend(parser.push(chunk));
+        }
+        rows.extend(parser.finish());
+        rows
+    }
+
+    #[test]
+    fn test_csv_unbalanced_quote() {
+        let mut data = header(BulkFormat::Csv).unwrap();
+        data.extend(b"a,,,,available,\nb\"c,,,,available,\nd,,,,available,\ne,\"f,,,available,\n".iter());
+        data.extend(b"g,\"h,,,available,\n".iter());
+        let rows = parse(RowParser::new(BulkFormat::Csv), &data);
+        let names: Vec<_> = rows.iter()
+            .map(|&(row, ref pet)| (row, pet.as_ref().map(|pet| pet.name.clone()).ok()))
+            .collect();
+        // The quote of `b"c` is literal for a CSV reader, so the lines up to the next quote are
+        // rejected as a whole, and so is the last row, whose quoted field is never closed.
+        assert_eq!(names, vec![(1, Some("a".into())), (2, None), (3, None)]);
+    }
+
+    #[test]
+    fn test_row_too_long() {
+        let long = format!("{{\"name\":\"{}\",\"photo_urls\":[]}}\n", "x".repeat(100));
+        let data = [ROW, long.as_bytes(), ROW, long.as_bytes()].concat();
+        let rows = parse(RowParser::new(BulkFormat::Ndjson).max_row_size(64), &data);
+        let results: Vec<_> = rows.iter().map(|&(row, ref pet)| (row, pet.is_ok())).collect();
+        assert_eq!(results, vec![(1, true), (2, false), (3, true), (4, false)]);
+        assert!(rows[1].1.as_ref().unwrap_err().contains("longer than 64 bytes"));
+
+        // Without a newline, the rest of the payload is skipped as the rest of the row.
+        let data = [ROW, long.trim_right().as_bytes()].concat();
+        let rows = parse(RowParser::new(BulkFormat::Ndjson).max_row_size(64), &data);
+        assert_eq!(rows.len(), 2);
+    }
+}
@@ -0,0 +1,764 @@
+//! Configuration of the server.
+//!
+//! Each setting is resolved from the following sources, where later ones take precedence:
+//!
+//! 1. the built-in defaults,
+//! 2. the TOML file given by `--config` (or `PETSTORE_CONFIG`),
+//! 3. the `PETSTORE_*` environment variables,
+//! 4. the command-line flags.
+//!
+//! Multi-valued settings are comma-separated when given through an environment variable,
+//! and their flags may be repeated.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use clap::{App, Arg};
+use hyper::Method;
+use log::LevelFilter;
+use toml;
+use self::ConfigErrorKind::*;
+
+/// What `to_redacted_toml` prints in place of a secret.
+const REDACTED: &'static str = "<redacted>";
+
+error_chain! {
+    types {
+        ConfigError, ConfigErrorKind, ResultExt, ConfigResult;
+    }
+
+    errors {
+        InvalidValue(key: String, value: String) {
+            display("invalid value for `{}': `{}'", key, value)
+        }
+
+        Inconsistent(msg: String) {
+            display("inconsistent configuration: {}", msg)
+        }
+
+        UnknownSetting(key: String) {
+            display("unknown setting `{}'", key)
+        }
+    }
+
+    foreign_links {
+        Args(::clap::Error);
+        Io(io::Error);
+        Toml(toml::de::Error);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub store: StoreConfig,
+    pub log: LogConfig,
+    pub tls: TlsConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub cors: CorsConfig,
+    pub compression: CompressionConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Vec<SocketAddr>,
+    pub pipeline: bool,
+    /// Seconds to wait for in-flight requests when shutting down.
+    pub shutdown_timeout: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: vec!["0.0.0.0:4000".parse().unwrap()],
+            pipeline: true,
+            shutdown_timeout: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    /// Everything is lost when the server stops.
+    Memory,
+    /// The store is loaded from and periodically saved to the JSON file named by the DSN.
+    Snapshot,
+}
+
+impl FromStr for StoreBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(StoreBackend::Memory),
+            "snapshot" => Ok(StoreBackend::Snapshot),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    pub backend: StoreBackend,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dsn: Option<String>,
+    /// Seconds between two snapshots.
+    pub snapshot_interval: u64,
+    pub require_if_match: bool,
+    /// Seed of the fixtures generated into an empty store.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Number of pets generated when seeding.
+    pub seed_count: usize,
+    /// Seconds for which deleted pets and users are kept in the trash.
+    pub trash_retention: u64,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            backend: StoreBackend::Memory,
+            dsn: None,
+            snapshot_interval: 60,
+            require_if_match: false,
+            seed: None,
+            seed_count: 1000,
+            trash_retention: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Logfmt,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "logfmt" => Ok(LogFormat::Logfmt),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".into(),
+            format: LogFormat::Logfmt,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn level_filter(&self) -> LevelFilter {
+        self.level.parse().expect("validated on load")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    /// PEM file of the CAs whose client certificates are accepted. Clients must present one if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.cert.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// The keys accepted in the `api_key` header. Authentication is disabled if empty.
+    pub api_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The maximum size of a request body in bytes, and of a single row of a bulk import.
+    pub max_body_size: u64,
+    /// The maximum size in bytes of a bulk import, which is not buffered.
+    pub max_bulk_body_size: u64,
+    /// The maximum number of items listed at once, which is also the default page size of the
+    /// listings of users, orders and the trash. It bounds the periods of the adoption report and the
+    /// users created by a single request too.
+    pub max_list_length: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_body_size: 16 * 1024 * 1024,
+            max_bulk_body_size: 1024 * 1024 * 1024,
+            max_list_length: 1000,
+        }
+    }
+}
+
+/// Quotas of requests per client, for each group of routes. A quota of 0 disables the limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Seconds over which a quota is refilled.
+    pub window: u64,
+    pub pet: u32,
+    pub store: u32,
+    pub user: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            window: 60,
+            pet: 0,
+            store: 0,
+            user: 0,
+        }
+    }
+}
+
+/// Cross-origin requests from browsers. CORS is disabled if no origin is allowed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// The origins allowed to call the API, or `*` for any.
+    pub allowed_origins: Vec<String>,
+    /// The methods allowed in cross-origin requests. Each route allows its own methods if empty.
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// Seconds for which the browsers may cache the answer to a preflight request.
+    pub max_age: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec![],
+            allowed_methods: vec![],
+            allowed_headers: vec![
+                "Content-Type".into(),
+                "api_key".into(),
+                "Authorization".into(),
+                "If-Match".into(),
+                "X-Request-Id".into(),
+            ],
+            allow_credentials: false,
+            max_age: 3600,
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_origins.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Whether responses are compressed. Compressed request bodies are accepted regardless.
+    pub enabled: bool,
+    /// The size in bytes below which responses are sent uncompressed.
+    pub min_size: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_size: 1024,
+        }
+    }
+}
+
+/// A setting which can be overridden individually.
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    flag: &'static str,
+    multiple: bool,
+    help: &'static str,
+}
+
+const SETTINGS: &'static [Setting] = &[
+    Setting {
+        key: "server.listen",
+        env: "PETSTORE_LISTEN",
+        flag: "listen",
+        multiple: true,
+        help: "Address to listen on",
+    },
+    Setting {
+        key: "server.pipeline",
+        env: "PETSTORE_PIPELINE",
+        flag: "pipeline",
+        multiple: false,
+        help: "Whether to enable HTTP/1.1 pipelining",
+    },
+    Setting {
+        key: "server.shutdown_timeout",
+        env: "PETSTORE_SHUTDOWN_TIMEOUT",
+        flag: "shutdown-timeout",
+        multiple: false,
+        help: "Seconds to wait for in-flight requests when shutting down",
+    },
+    Setting {
+        key: "store.backend",
+        env: "PETSTORE_STORE_BACKEND",
+        flag: "store-backend",
+        multiple: false,
+        help: "Store backend, either `memory` or `snapshot`",
+    },
+    Setting {
+        key: "store.dsn",
+        env: "PETSTORE_STORE_DSN",
+        flag: "store-dsn",
+        multiple: false,
+        help: "Location of the store, e.g. the snapshot file",
+    },
+    Setting {
+        key: "store.snapshot_interval",
+        env: "PETSTORE_SNAPSHOT_INTERVAL",
+        flag: "snapshot-interval",
+        multiple: false,
+        help: "Seconds between two snapshots",
+    },
+    Setting {
+        key: "store.require_if_match",
+        env: "PETSTORE_REQUIRE_IF_MATCH",
+        flag: "require-if-match",
+        multiple: false,
+        help: "Whether modifications must carry If-Match",
+    },
+    Setting {
+        key: "store.seed",
+        env: "PETSTORE_SEED",
+        flag: "seed",
+        multiple: false,
+        help: "Populate an empty store with fake data generated from this seed",
+    },
+    Setting {
+        key: "store.seed_count",
+        env: "PETSTORE_SEED_COUNT",
+        flag: "seed-count",
+        multiple: false,
+        help: "Number of pets generated by --seed",
+    },
+    Setting {
+        key: "store.trash_retention",
+        env: "PETSTORE_TRASH_RETENTION",
+        flag: "trash-retention",
+        multiple: false,
+        help: "Seconds for which deleted pets and users can be restored",
+    },
+    Setting {
+        key: "log.level",
+        env: "PETSTORE_LOG_LEVEL",
+        flag: "log-level",
+        multiple: false,
+        help: "One of `off`, `error`, `warn`, `info`, `debug` and `trace`",
+    },
+    Setting {
+        key: "log.format",
+        env: "PETSTORE_LOG_FORMAT",
+        flag: "log-format",
+        multiple: false,
+        help: "Either `logfmt` or `json`",
+    },
+    Setting {
+        key: "tls.cert",
+        env: "PETSTORE_TLS_CERT",
+        flag: "tls-cert",
+        multiple: false,
+        help: "PEM file of the certificate chain",
+    },
+    Setting {
+        key: "tls.key",
+        env: "PETSTORE_TLS_KEY",
+        flag: "tls-key",
+        multiple: false,
+        help: "PEM file of the private key",
+    },
+    Setting {
+        key: "tls.client_ca",
+        env: "PETSTORE_TLS_CLIENT_CA",
+        flag: "tls-client-ca",
+        multiple: false,
+        help: "PEM file of the CAs of client certificates",
+    },
+    Setting {
+        key: "auth.api_keys",
+        env: "PETSTORE_API_KEYS",
+        flag: "api-key",
+        multiple: true,
+        help: "API key accepted in the `api_key` header",
+    },
+    Setting {
+        key: "limits.max_body_size",
+        env: "PETSTORE_MAX_BODY_SIZE",
+        flag: "max-body-size",
+        multiple: false,
+        help: "Maximum size of a request body, or of a row of a bulk import, in bytes",
+    },
+    Setting {
+        key: "limits.max_bulk_body_size",
+        env: "PETSTORE_MAX_BULK_BODY_SIZE",
+        flag: "max-bulk-body-size",
+        multiple: false,
+        help: "Maximum size of a bulk import in bytes",
+    },
+    Setting {
+        key: "limits.max_list_length",
+        env: "PETSTORE_MAX_LIST_LENGTH",
+        flag: "max-list-length",
+        multiple: false,
+        help: "Maximum number of items listed, report periods or users created by a single request",
+    },
+    Setting {
+        key: "rate_limit.window",
+        env: "PETSTORE_RATE_LIMIT_WINDOW",
+        flag: "rate-limit-window",
+        multiple: false,
+        help: "Seconds over which the rate limit quotas are refilled",
+    },
+    Setting {
+        key: "rate_limit.pet",
+        env: "PETSTORE_RATE_LIMIT_PET",
+        flag: "rate-limit-pet",
+        multiple: false,
+        help: "Requests to /pet and /trash allowed per client and window, 0 for no limit",
+    },
+    Setting {
+        key: "rate_limit.store",
+        env: "PETSTORE_RATE_LIMIT_STORE",
+        flag: "rate-limit-store",
+        multiple: false,
+        help: "Requests to /store allowed per client and window, 0 for no limit",
+    },
+    Setting {
+        key: "rate_limit.user",
+        env: "PETSTORE_RATE_LIMIT_USER",
+        flag: "rate-limit-user",
+        multiple: false,
+        help: "Requests to /user allowed per client and window, 0 for no limit",
+    },
+    Setting {
+        key: "cors.allowed_origins",
+        env: "PETSTORE_CORS_ALLOWED_ORIGINS",
+        flag: "cors-allowed-origin",
+        multiple: true,
+        help: "Origin allowed to make cross-origin requests, or `*` for any",
+    },
+    Setting {
+        key: "cors.allowed_methods",
+        env: "PETSTORE_CORS_ALLOWED_METHODS",
+        flag: "cors-allowed-method",
+        multiple: true,
+        help: "Method allowed in cross-origin requests",
+    },
+    Setting {
+        key: "cors.allowed_headers",
+        env: "PETSTORE_CORS_ALLOWED_HEADERS",
+        flag: "cors-allowed-header",
+        multiple: true,
+        help: "Request header allowed in cross-origin requests",
+    },
+    Setting {
+        key: "cors.allow_credentials",
+        env: "PETSTORE_CORS_ALLOW_CREDENTIALS",
+        flag: "cors-allow-credentials",
+        multiple: false,
+        help: "Whether cross-origin requests may carry credentials",
+    },
+    Setting {
+        key: "cors.max_age",
+        env: "PETSTORE_CORS_MAX_AGE",
+        flag: "cors-max-age",
+        multiple: false,
+        help: "Seconds for which preflight responses may be cached",
+    },
+    Setting {
+        key: "compression.enabled",
+        env: "PETSTORE_COMPRESSION",
+        flag: "compression",
+        multiple: false,
+        help: "Whether to compress responses with gzip, brotli or zstd",
+    },
+    Setting {
+        key: "compression.min_size",
+        env: "PETSTORE_COMPRESSION_MIN_SIZE",
+        flag: "compression-min-size",
+        multiple: false,
+        help: "Size in bytes below which responses are not compressed",
+    },
+];
+
+/// The result of parsing the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub config: Config,
+    /// Dump the effective configuration instead of starting the server.
+    pub print_config: bool,
+}
+
+/// Resolve the configuration from the command line `args` and the environment `vars`.
+pub fn load<I, T, V>(args: I, vars: V) -> ConfigResult<Options>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+    V: IntoIterator<Item = (String, String)>,
+{
+    let vars: Vec<_> = vars.into_iter().collect();
+    let var = |name: &str| {
+        vars.iter()
+            .find(|&&(ref k, _)| k == name)
+            .map(|&(_, ref v)| v.clone())
+    };
+
+    let mut app = App::new("petstore")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML configuration file (PETSTORE_CONFIG)"),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Print the effective configuration, API keys redacted, and exit"),
+        );
+    for setting in SETTINGS {
+        app = app.arg(
+            Arg::with_name(setting.flag)
+                .long(setting.flag)
+                .value_name("VALUE")
+                .multiple(setting.multiple)
+                .number_of_values(1)
+                .help(setting.help),
+        );
+    }
+    let matches = app.get_matches_from_safe(args)?;
+
+    let mut config = match matches
+        .value_of("config")
+        .map(ToOwned::to_owned)
+        .or_else(|| var("PETSTORE_CONFIG"))
+    {
+        Some(path) => Config::from_file(&path).chain_err(|| format!("failed to load `{}'", path))?,
+        None => Config::default(),
+    };
+    for setting in SETTINGS {
+        if let Some(value) = var(setting.env) {
+            config.set(setting.key, &value)?;
+        }
+    }
+    for setting in SETTINGS {
+        if let Some(values) = matches.values_of(setting.flag) {
+            config.set(setting.key, &values.collect::<Vec<_>>().join(","))?;
+        }
+    }
+    config.validate()?;
+
+    Ok(Options {
+        config,
+        print_config: matches.is_present("print-config"),
+    })
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> ConfigResult<Self> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+        toml::from_str(&content).map_err(Into::into)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("the configuration is always representable in TOML")
+    }
+
+    /// The configuration in TOML, with the API keys masked, so that it can be shown.
+    pub fn to_redacted_toml(&self) -> String {
+        let mut config = self.clone();
+        for key in &mut config.auth.api_keys {
+            *key = REDACTED.to_owned();
+        }
+        config.to_toml()
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> ConfigResult<()> {
+        fn parse<T: FromStr>(key: &str, value: &str) -> ConfigResult<T> {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| InvalidValue(key.into(), value.into()).into())
+        }
+        fn parse_list<T: FromStr>(key: &str, value: &str) -> ConfigResult<Vec<T>> {
+            value
+                .split(',')
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| parse(key, v))
+                .collect()
+        }
+
+        match key {
+            "server.listen" => self.server.listen = parse_list(key, value)?,
+            "server.pipeline" => self.server.pipeline = parse(key, value)?,
+            "server.shutdown_timeout" => self.server.shutdown_timeout = parse(key, value)?,
+            "store.backend" => self.store.backend = parse(key, value)?,
+            "store.dsn" => self.store.dsn = Some(value.into()),
+            "store.snapshot_interval" => self.store.snapshot_interval = parse(key, value)?,
+            "store.require_if_match" => self.store.require_if_match = parse(key, value)?,
+            "store.seed" => self.store.seed = Some(parse(key, value)?),
+            "store.seed_count" => self.store.seed_count = parse(key, value)?,
+            "store.trash_retention" => self.store.trash_retention = parse(key, value)?,
+            "log.level" => self.log.level = value.into(),
+            "log.format" => self.log.format = parse(key, value)?,
+            "tls.cert" => self.tls.cert = Some(value.into()),
+            "tls.key" => self.tls.key = Some(value.into()),
+            "tls.client_ca" => self.tls.client_ca = Some(value.into()),
+            "auth.api_keys" => self.auth.api_keys = parse_list(key, value)?,
+            "limits.max_body_size" => self.limits.max_body_size = parse(key, value)?,
+            "limits.max_bulk_body_size" => self.limits.max_bulk_body_size = parse(key, value)?,
+            "limits.max_list_length" => self.limits.max_list_length = parse(key, value)?,
+            "rate_limit.window" => self.rate_limit.window = parse(key, value)?,
+            "rate_limit.pet" => self.rate_limit.pet = parse(key, value)?,
+            "rate_limit.store" => self.rate_limit.store = parse(key, value)?,
+            "rate_limit.user" => self.rate_limit.user = parse(key, value)?,
+            "cors.allowed_origins" => self.cors.allowed_origins = parse_list(key, value)?,
+            "cors.allowed_methods" => self.cors.allowed_methods = parse_list(key, value)?,
+            "cors.allowed_headers" => self.cors.allowed_headers = parse_list(key, value)?,
+            "cors.allow_credentials" => self.cors.allow_credentials = parse(key, value)?,
+            "cors.max_age" => self.cors.max_age = parse(key, value)?,
+            "compression.enabled" => self.compression.enabled = parse(key, value)?,
+            "compression.min_size" => self.compression.min_size = parse(key, value)?,
+            key => bail!(UnknownSetting(key.into())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> ConfigResult<()> {
+        if self.server.listen.is_empty() {
+            bail!(Inconsistent("at least one listen address is required".into()));
+        }
+        if self.store.backend == StoreBackend::Snapshot && self.store.dsn.is_none() {
+            bail!(Inconsistent("the snapshot backend requires a DSN".into()));
+        }
+        if self.store.snapshot_interval == 0 {
+            bail!(InvalidValue("store.snapshot_interval".into(), "0".into()));
+        }
+        if self.rate_limit.window == 0 {
+            bail!(InvalidValue("rate_limit.window".into(), "0".into()));
+        }
+        if self.log.level.parse::<LevelFilter>().is_err() {
+            bail!(InvalidValue("log.level".into(), self.log.level.clone()));
+        }
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            bail!(Inconsistent("both a TLS certificate and key are required".into()));
+        }
+        if self.tls.client_ca.is_some() && !self.tls.is_enabled() {
+            bail!(Inconsistent("client certificates require TLS".into()));
+        }
+        if let Some(method) = self.cors.allowed_methods.iter().find(|m| m.parse::<Method>().is_err()) {
+            bail!(InvalidValue("cors.allowed_methods".into(), method.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_flags_override_environment() {
+        let options = load(
+            vec!["petstore", "--listen", "127.0.0.1:8080", "--listen", "[::1]:8080"],
+            vars(&[
+                ("PETSTORE_LISTEN", "127.0.0.1:9090"),
+                ("PETSTORE_LOG_LEVEL", "debug"),
+            ]),
+        ).unwrap();
+        assert_eq!(
+            options.config.server.listen,
+            vec![
+                "127.0.0.1:8080".parse().unwrap(),
+                "[::1]:8080".parse().unwrap(),
+            ]
+        );
+        assert_eq!(options.config.log.level, "debug");
+        assert!(!options.print_config);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(load(vec!["petstore", "--store-backend", "snapshot"], vec![]).is_err());
+        assert!(load(vec!["petstore"], vars(&[("PETSTORE_PIPELINE", "maybe")])).is_err());
+    }
+
+    #[test]
+    fn test_every_setting_can_be_set() {
+        for setting in SETTINGS {
+            // The value may be invalid for the setting, but the setting must be known.
+            if let Err(ConfigError(UnknownSetting(key), _)) = Config::default().set(setting.key, "1") {
+                panic!("`{}' is not handled by `Config::set'", key);
+            }
+        }
+        assert!(Config::default().set("store.nothing", "1").is_err());
+    }
+
+    #[test]
+    fn test_print_config_round_trip() {
+        let config = Config::default();
+        assert_eq!(toml::from_str::<Config>(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn test_print_config_hides_api_keys() {
+        let mut config = Config::default();
+        config.set("auth.api_keys", "s3cr3t,t0k3n").unwrap();
+        let printed = config.to_redacted_toml();
+        assert!(!printed.contains("s3cr3t") && !printed.contains("t0k3n"));
+        let printed = toml::from_str::<Config>(&printed).unwrap();
+        assert_eq!(printed.auth.api_keys, vec![REDACTED, REDACTED]);
+    }
+}
@@ -0,0 +1,172 @@
+//! The request being handled, made available to the code handling it on the same thread.
+
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use futures::{Future, Poll};
+
+#[derive(Debug)]
+pub struct RequestContext {
+    id: String,
+    route: Cell<Option<&'static str>>,
+    client: RefCell<Option<String>>,
+    spans: RefCell<Vec<(&'static str, Duration)>>,
+}
+
+impl RequestContext {
+    pub fn new(id: String) -> Self {
+        RequestContext {
+            id,
+            route: Cell::new(None),
+            client: RefCell::new(None),
+            spans: RefCell::new(vec![]),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The name of the matched route, e.g. `pet::GetPet`, once the request is routed.
+    pub fn route(&self) -> Option<&'static str> {
+        self.route.get()
+    }
+
+    pub fn set_route(&self, route: &'static str) {
+        self.route.set(Some(route));
+    }
+
+    /// The API key the client was authenticated with, if any.
+    pub fn client(&self) -> Option<String> {
+        self.client.borrow().clone()
+    }
+
+    pub fn set_client(&self, key: String) {
+        *self.client.borrow_mut() = Some(key);
+    }
+
+    /// The spans which have completed so far, with their durations.
+    pub fn spans(&self) -> Vec<(&'static str, Duration)> {
+        self.spans.borrow().clone()
+    }
+}
+
+thread_local!(static CURRENT: RefCell<Option<Rc<RequestContext>>> = RefCell::new(None));
+
+/// Run `f` while `context` is the current one.
+pub fn scope<F, R>(context: &Rc<RequestContext>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = CURRENT.with(|current| mem::replace(&mut *current.borrow_mut(), Some(context.clone())));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Call `f` with the context of the request being handled, if any.
+pub fn with_current<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&RequestContext) -> R,
+{
+    CURRENT.with(|current| current.borrow().as_ref().map(|context| f(context)))
+}
+
+/// Run `f` as a span called `name`, recording its duration on the current request.
+pub fn span<F, R>(name: &'static str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    with_current(|context| context.spans.borrow_mut().push((name, elapsed)));
+    result
+}
+
+thread_local!(static PEER: Cell<Option<SocketAddr>> = Cell::new(None));
+
+/// Run `f` on behalf of the connection from `addr`.
+pub fn peer_scope<F, R>(addr: SocketAddr, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = PEER.with(|peer| peer.replace(Some(addr)));
+    let result = f();
+    PEER.with(|peer| peer.set(previous));
+    result
+}
+
+/// The address of the client whose connection is being served, if any.
+pub fn peer_addr() -> Option<SocketAddr> {
+    PEER.with(|peer| peer.get())
+}
+
+/// A future which makes a request context current while it is polled.
+#[derive(Debug)]
+pub struct WithContext<F> {
+    inner: F,
+    context: Rc<RequestContext>,
+}
+
+impl<F> WithContext<F> {
+    pub fn new(inner: F, context: Rc<RequestContext>) -> Self {
+        WithContext { inner, context }
+    }
+}
+
+impl<F: Future> Future for WithContext<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = &mut self.inner;
+        scope(&self.context, || inner.poll())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async};
+
+    fn current_id() -> Option<String> {
+        with_current(|context| context.id().to_owned())
+    }
+
+    #[test]
+    fn test_scopes_are_nested() {
+        let outer = Rc::new(RequestContext::new("outer".into()));
+        let inner = Rc::new(RequestContext::new("inner".into()));
+        assert_eq!(current_id(), None);
+        scope(&outer, || {
+            scope(&inner, || assert_eq!(current_id(), Some("inner".into())));
+            assert_eq!(current_id(), Some("outer".into()));
+        });
+        assert_eq!(current_id(), None);
+
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(peer_scope(addr, peer_addr), Some(addr));
+        assert_eq!(peer_addr(), None);
+    }
+
+    #[test]
+    fn test_with_context() {
+        let context = Rc::new(RequestContext::new("42".into()));
+        let mut future = WithContext::new(
+            future::lazy(|| {
+                with_current(|context| context.set_route("pet::GetPet"));
+                span("store", || ());
+                Ok::<_, ()>(current_id())
+            }),
+            context.clone(),
+        );
+        assert_eq!(future.poll(), Ok(Async::Ready(Some("42".into()))));
+        assert_eq!(context.route(), Some("pet::GetPet"));
+        let spans: Vec<_> = context.spans().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(spans, vec!["store"]);
+        assert_eq!(current_id(), None);
+    }
+}
@@ -1,6 +1,6 @@
 use std::error::Error as StdError;
 use finchers::http::{header, IntoResponse, Response, StatusCode};
-use petstore::PetstoreError;
+use petstore::{PetstoreError, PetstoreErrorKind};
 
 #[derive(Debug, From)]
 pub enum Error {
@@ -12,9 +12,7 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         match self {
             Error::Endpoint(e) => e.into_response(),
-            Error::Petstore(e) => Response::new()
-                .with_status(StatusCode::InternalServerError)
-                .with_body(e.to_string()),
+            Error::Petstore(e) => e.into_response(),
         }
     }
 }
@@ -41,9 +39,23 @@ impl IntoResponse for EndpointError {
 
 impl IntoResponse for PetstoreError {
     fn into_response(self) -> Response {
+        let status = match *self.kind() {
+            PetstoreErrorKind::InvalidInput(..) | PetstoreErrorKind::MissingIdentifier(..) => StatusCode::BadRequest,
+            PetstoreErrorKind::MissingPet(..) | PetstoreErrorKind::MissingUser(..) => StatusCode::NotFound,
+            PetstoreErrorKind::RedundantUserName(..) => StatusCode::Conflict,
+            PetstoreErrorKind::PreconditionFailed(..) => StatusCode::PreconditionFailed,
+            PetstoreErrorKind::PreconditionRequired(..) => StatusCode::PreconditionRequired,
+            PetstoreErrorKind::InvalidPatch(..) => StatusCode::UnprocessableEntity,
+            PetstoreErrorKind::UnsupportedMediaType(..) => StatusCode::UnsupportedMediaType,
+            PetstoreErrorKind::TooManyItems(..) => StatusCode::PayloadTooLarge,
+            PetstoreErrorKind::InvalidCredentials(..) => StatusCode::BadRequest,
+            PetstoreErrorKind::InvalidSession(..) => StatusCode::Unauthorized,
+            PetstoreErrorKind::Forbidden(..) => StatusCode::Forbidden,
+            _ => StatusCode::InternalServerError,
+        };
         let body = self.to_string();
         Response::new()
-            .with_status(StatusCode::InternalServerError)
+            .with_status(status)
             .with_header(header::ContentType::plaintext())
             .with_header(header::ContentLength(body.len() as u64))
             .with_body(body)
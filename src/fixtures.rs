@@ -0,0 +1,251 @@
+//! Deterministic generation of plausible pets, users and orders, to populate stores
+//! for demos, load tests and tests.
+
+use chrono::{Duration, TimeZone, Utc};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use model::{Category, Order, OrderStatus, Pet, Status, Tag, User};
+use petstore::{Petstore, PetstoreResult};
+
+const PET_NAMES: &'static [&'static str] = &[
+    "Bella", "Max", "Luna", "Charlie", "Lucy", "Cooper", "Daisy", "Milo", "Coco", "Rocky", "Nala", "Oliver", "Kiki",
+    "Simba", "Pepper", "Ziggy", "Biscuit", "Mango", "Shadow", "Willow", "Peanut", "Ginger", "Toby", "Sunny",
+];
+
+/// The categories, with the tags which make sense for each of them.
+const CATEGORIES: &'static [(&'static str, &'static [&'static str])] = &[
+    ("dogs", &["friendly", "trained", "large", "small", "puppy", "senior", "vaccinated"]),
+    ("cats", &["friendly", "indoor", "outdoor", "kitten", "senior", "vaccinated", "hypoallergenic"]),
+    ("birds", &["talking", "colorful", "singing", "young"]),
+    ("fish", &["freshwater", "saltwater", "colorful", "schooling"]),
+    ("reptiles", &["heat-lamp", "docile", "young", "large"]),
+];
+
+const FIRST_NAMES: &'static [&'static str] = &[
+    "Alice", "Bob", "Carol", "Dave", "Erin", "Frank", "Grace", "Heidi", "Ivan", "Judy", "Mallory", "Niaj", "Olivia",
+    "Peggy", "Rupert", "Sybil", "Trent", "Victor", "Walter", "Yusuke",
+];
+
+const LAST_NAMES: &'static [&'static str] = &[
+    "Smith", "Johnson", "Brown", "Garcia", "Miller", "Davis", "Martinez", "Lopez", "Wilson", "Anderson", "Sasaki",
+    "Tanaka", "Nguyen", "Kim", "Müller", "Rossi",
+];
+
+/// Generated entities, which are consistent with each other once loaded into a store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixtures {
+    pub pets: Vec<Pet>,
+    pub users: Vec<User>,
+    /// Orders whose `pet_id` is the index of their pet in `pets`, as the pets have no ID yet.
+    pub orders: Vec<Order>,
+}
+
+/// Pick an item by weight. The weights must not be all zero.
+fn weighted<R: Rng, T: Copy>(rng: &mut R, items: &[(T, u32)]) -> T {
+    let total: u32 = items.iter().map(|&(_, weight)| weight).sum();
+    let mut n = rng.gen_range(0, total);
+    for &(item, weight) in items {
+        if n < weight {
+            return item;
+        }
+        n -= weight;
+    }
+    unreachable!()
+}
+
+/// Generate `count` pets, along with a user for every ten pets and orders for some of the
+/// available pets. The same `seed` always yields the same fixtures.
+pub fn generate(seed: u64, count: usize) -> Fixtures {
+    let mut rng = XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9E37_79B9, 0x243F_6A88]);
+
+    let start = Utc.ymd(2018, 1, 1).and_hms(0, 0, 0);
+    let mut pets: Vec<Pet> = (0..count)
+        .map(|i| {
+            let &(category, tags) = rng.choose(CATEGORIES).unwrap();
+            let tag_count = rng.gen_range(0, 4);
+            let mut pet_tags: Vec<Tag> = vec![];
+            for _ in 0..tag_count {
+                let name = rng.choose(tags).unwrap().to_string();
+                if pet_tags.iter().all(|tag| tag.name != name) {
+                    pet_tags.push(Tag { id: None, name });
+                }
+            }
+            let status = weighted(
+                &mut rng,
+                &[(Status::Available, 60), (Status::Pending, 25), (Status::Adopted, 15)],
+            );
+            let created_at = Some(start + Duration::hours(rng.gen_range(0, 24 * 365)));
+            Pet {
+                id: None,
+                name: rng.choose(PET_NAMES).unwrap().to_string(),
+                photo_urls: vec![format!("https://images.example.com/{}/{}.jpg", category, i)],
+                category: Some(Category {
+                    id: None,
+                    name: category.into(),
+                }),
+                tags: Some(pet_tags),
+                status: Some(status),
+                created_at,
+                updated_at: created_at,
+                deleted_at: None,
+                version: 0,
+            }
+        })
+        .collect();
+
+    let users: Vec<User> = (0..(count / 10).max(1))
+        .map(|i| {
+            let first = rng.choose(FIRST_NAMES).unwrap();
+            let last = rng.choose(LAST_NAMES).unwrap();
+            User {
+                id: None,
+                // The index keeps the usernames unique.
+                username: format!("{}.{}{}", first.to_lowercase(), last.to_lowercase(), i),
+                first_name: Some(first.to_string()),
+                last_name: Some(last.to_string()),
+                email: Some(format!("{}.{}{}@example.com", first.to_lowercase(), last.to_lowercase(), i)),
+                password: format!("{:016x}", rng.gen::<u64>()),
+                phone: Some(format!("+1-555-{:04}", rng.gen_range(0, 10000))),
+                created_at: Some(start),
+                updated_at: Some(start),
+                deleted_at: None,
+                version: 0,
+            }
+        })
+        .collect();
+
+    // Only available pets are ordered, at most once each, and they are no longer available afterwards.
+    // Each order is placed by one of the users.
+    let mut orders = vec![];
+    for (index, pet) in pets.iter_mut().enumerate() {
+        if pet.status != Some(Status::Available) || !rng.gen_weighted_bool(3) {
+            continue;
+        }
+        let status = weighted(
+            &mut rng,
+            &[
+                (OrderStatus::Placed, 50),
+                (OrderStatus::Approved, 30),
+                (OrderStatus::Delivered, 20),
+            ],
+        );
+        pet.status = Some(if status == OrderStatus::Delivered {
+            Status::Adopted
+        } else {
+            Status::Pending
+        });
+        let created_at = pet.created_at.unwrap() + Duration::hours(rng.gen_range(1, 24 * 30));
+        let ship_date = created_at + Duration::hours(rng.gen_range(1, 24 * 7));
+        orders.push(Order {
+            id: None,
+            pet_id: Some(index as u64),
+            quantity: Some(1),
+            ship_date: Some(ship_date),
+            status: Some(status),
+            complete: Some(status == OrderStatus::Delivered),
+            username: rng.choose(&users).map(|user| user.username.clone()),
+            created_at: Some(created_at),
+            updated_at: Some(created_at),
+            version: 0,
+        });
+    }
+
+    Fixtures { pets, users, orders }
+}
+
+/// Add the fixtures to `store`.
+pub fn load(store: &Petstore, fixtures: Fixtures) -> PetstoreResult<()> {
+    let ids = fixtures
+        .pets
+        .into_iter()
+        .map(|pet| store.add_pet(pet))
+        .collect::<PetstoreResult<Vec<_>>>()?;
+    store.add_users(fixtures.users)?;
+    for mut order in fixtures.orders {
+        order.pet_id = order.pet_id.map(|index| ids[index as usize]);
+        store.add_order(order)?;
+    }
+    Ok(())
+}
+
+/// Create a store holding the fixtures, with the IDs the API would assign them in order.
+///
+/// Unlike `load`, this takes linear time, which matters for large stores.
+pub fn build(fixtures: Fixtures) -> Petstore {
+    fn assign<T, F: FnMut(&mut T, u64)>(entities: Vec<T>, mut set: F) -> Vec<T> {
+        entities
+            .into_iter()
+            .zip(0..)
+            .map(|(mut entity, id)| {
+                set(&mut entity, id);
+                entity
+            })
+            .collect()
+    }
+    let pets = assign(fixtures.pets, |pet, id| {
+        pet.id = Some(id);
+        pet.version = 1;
+    });
+    let orders = assign(fixtures.orders, |order, id| {
+        order.id = Some(id);
+        order.version = 1;
+    });
+    let users = assign(fixtures.users, |user, id| {
+        user.id = Some(id);
+        user.version = 1;
+    });
+    Petstore::from_entities(pets, orders, users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let fixtures = generate(42, 200);
+        assert_eq!(fixtures, generate(42, 200));
+        assert_ne!(fixtures, generate(43, 200));
+        assert_eq!(fixtures.pets.len(), 200);
+        assert_eq!(fixtures.users.len(), 20);
+        for order in &fixtures.orders {
+            let pet = &fixtures.pets[order.pet_id.unwrap() as usize];
+            assert!(pet.status != Some(Status::Available));
+        }
+
+        let store = Petstore::new();
+        load(&store, fixtures.clone()).unwrap();
+        assert_eq!(store.check_consistency().unwrap(), Vec::<String>::new());
+        let built = build(fixtures);
+        assert_eq!(built.check_consistency().unwrap(), Vec::<String>::new());
+
+        // `load` stamps the entities with the current time, unlike `build`.
+        let pets = |store: &Petstore| {
+            store
+                .export_pets()
+                .unwrap()
+                .map(|pet| Pet {
+                    created_at: None,
+                    updated_at: None,
+                    ..pet
+                })
+                .collect::<Vec<_>>()
+        };
+        let orders = |store: &Petstore| {
+            store
+                .list_orders()
+                .unwrap()
+                .into_iter()
+                .map(|order| Order {
+                    created_at: None,
+                    updated_at: None,
+                    ..order
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pets(&built), pets(&store));
+        assert_eq!(orders(&built), orders(&store));
+        assert_eq!(built.list_tags().unwrap(), store.list_tags().unwrap());
+        assert_eq!(built.list_categories().unwrap(), store.list_categories().unwrap());
+        assert!(!built.list_tags().unwrap().is_empty());
+    }
+}
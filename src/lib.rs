@@ -1,5 +1,9 @@
 #![feature(conservative_impl_trait)]
 
+extern crate brotli;
+extern crate chrono;
+extern crate clap;
+extern crate csv;
 #[macro_use]
 extern crate derive_more;
 #[macro_use]
@@ -8,15 +12,40 @@ extern crate error_chain;
 extern crate finchers;
 extern crate finchers_json;
 extern crate finchers_urlencoded;
+extern crate flate2;
 extern crate futures;
+extern crate hyper;
+#[macro_use]
+extern crate log;
+extern crate rand;
+extern crate rustls;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
+extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_rustls;
+extern crate toml;
+extern crate zstd;
+
+#[cfg(test)]
+extern crate webpki;
 
 pub mod api;
+pub mod bulk;
+pub mod config;
+pub mod context;
 pub mod error;
+pub mod fixtures;
+pub mod logging;
+pub mod metrics;
+pub mod middleware;
 pub mod petstore;
 pub mod model;
+pub mod patch;
+pub mod server;
+pub mod tls;
 
 pub use petstore::Petstore;
@@ -0,0 +1,131 @@
+//! Structured logging to the standard error, either as logfmt or as JSON lines.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use chrono::{SecondsFormat, Utc};
+use log::{self, Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json::{Map, Value};
+use config::LogFormat;
+
+static JSON: AtomicBool = ATOMIC_BOOL_INIT;
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            write_event(record.level(), record.target(), &record.args().to_string(), &[]);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Install the logger for the `log` macros.
+pub fn init(level: LevelFilter, format: LogFormat) -> Result<(), SetLoggerError> {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+    log::set_boxed_logger(Box::new(Logger)).map(|()| log::set_max_level(level))
+}
+
+/// Log an event carrying structured `fields`, if `level` is enabled.
+pub fn event(level: Level, target: &str, msg: &str, fields: &[(String, Value)]) {
+    if level <= log::max_level() {
+        write_event(level, target, msg, fields);
+    }
+}
+
+fn write_event(level: Level, target: &str, msg: &str, fields: &[(String, Value)]) {
+    let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    let line = format_event(JSON.load(Ordering::Relaxed), &ts, level, target, msg, fields);
+    let stderr = io::stderr();
+    let _ = writeln!(stderr.lock(), "{}", line);
+}
+
+/// Format an event as a single line, of JSON or logfmt.
+fn format_event(json: bool, ts: &str, level: Level, target: &str, msg: &str, fields: &[(String, Value)]) -> String {
+    if json {
+        let mut map = Map::new();
+        map.insert("ts".into(), ts.into());
+        map.insert("level".into(), level.to_string().to_lowercase().into());
+        map.insert("target".into(), target.into());
+        map.insert("msg".into(), msg.into());
+        for &(ref key, ref value) in fields {
+            map.insert(key.clone(), value.clone());
+        }
+        Value::Object(map).to_string()
+    } else {
+        let mut line = format!(
+            "ts={} level={} target={} msg={}",
+            ts,
+            level.to_string().to_lowercase(),
+            target,
+            logfmt_value(&msg.into())
+        );
+        for &(ref key, ref value) in fields {
+            line += &format!(" {}={}", key, logfmt_value(value));
+        }
+        line
+    }
+}
+
+/// Format a logfmt value, quoting strings only if needed.
+fn logfmt_value(value: &Value) -> String {
+    let needs_quotes = |s: &str| {
+        s.is_empty() || s.contains(|c: char| c == ' ' || c == '"' || c == '=' || c.is_control())
+    };
+    match *value {
+        Value::String(ref s) if !needs_quotes(s) => s.clone(),
+        ref value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Vec<(String, Value)> {
+        vec![
+            ("path".to_owned(), "/pet/42".into()),
+            ("query".to_owned(), "a=1 b".into()),
+            ("empty".to_owned(), "".into()),
+            ("status".to_owned(), 200.into()),
+        ]
+    }
+
+    #[test]
+    fn test_logfmt() {
+        let line = format_event(false, "2018-01-01T00:00:00.000Z", Level::Info, "petstore", "a request", &fields());
+        assert_eq!(
+            line,
+            concat!(
+                r#"ts=2018-01-01T00:00:00.000Z level=info target=petstore msg="a request" "#,
+                r#"path=/pet/42 query="a=1 b" empty="" status=200"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let line = format_event(true, "2018-01-01T00:00:00.000Z", Level::Warn, "petstore", "a request", &fields());
+        let value: Value = ::serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "ts": "2018-01-01T00:00:00.000Z",
+                "level": "warn",
+                "target": "petstore",
+                "msg": "a request",
+                "path": "/pet/42",
+                "query": "a=1 b",
+                "empty": "",
+                "status": 200,
+            })
+        );
+    }
+}
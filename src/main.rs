@@ -1,39 +1,114 @@
-extern crate finchers;
 extern crate futures;
-extern crate hyper;
+#[macro_use]
+extern crate log;
 extern crate petstore;
 extern crate tokio_core;
+extern crate tokio_signal;
 
-use finchers::service::FinchersService;
-use finchers::responder::DefaultResponder;
+use std::env;
+use std::io;
+use std::process;
 use futures::{Future, Stream};
-use hyper::server::{Http, NewService};
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Handle};
+use petstore::config::{self, ConfigError, ConfigErrorKind};
+use petstore::server::{self, Server};
 
 fn main() {
-    let petstore = petstore::Petstore::new();
+    let options = match config::load(env::args_os(), env::vars()) {
+        Ok(options) => options,
+        Err(ConfigError(ConfigErrorKind::Args(e), _)) => e.exit(),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
+    };
+    let config = options.config;
+    if options.print_config {
+        print!("{}", config.to_redacted_toml());
+        return;
+    }
 
-    let service = FinchersService::new(
-        petstore::api::endpoint(),
-        petstore,
-        DefaultResponder::default(),
-    );
+    petstore::logging::init(config.log.level_filter(), config.log.format).unwrap();
 
-    run_service(move || Ok(service.clone()));
-}
+    let petstore = match server::open_store(&config.store) {
+        Ok(petstore) => petstore,
+        Err(e) => {
+            error!("failed to open the store: {}", e);
+            process::exit(1);
+        }
+    };
 
-fn run_service<S>(new_service: S)
-where
-    S: NewService<Request = hyper::Request, Response = hyper::Response, Error = hyper::Error> + 'static,
-{
     let mut core = Core::new().unwrap();
-    let mut http = Http::new();
-    http.pipeline(true);
-
-    let addr = "0.0.0.0:4000".parse().unwrap();
-    println!("Serving on listen address {}...", addr);
-    let serves = http.serve_addr_handle(&addr, &core.handle(), new_service)
-        .unwrap()
-        .for_each(|conn| conn.map(|_| ()));
-    core.run(serves).unwrap();
+    let handle = core.handle();
+    let server = match Server::bind(config, petstore, &handle) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("failed to bind the listen addresses: {}", e);
+            process::exit(1);
+        }
+    };
+    for addr in server.local_addrs().unwrap() {
+        info!("Serving on listen address {}...", addr);
+    }
+
+    let shutdown = server.shutdown_handle();
+    handle.spawn(shutdown_signal(&handle).then(move |result| {
+        match result {
+            Ok(()) => shutdown.shutdown(),
+            // The server keeps running, and can still be stopped by killing it.
+            Err(e) => error!("failed to listen for signals, shutdown signals will be ignored: {}", e),
+        }
+        Ok(())
+    }));
+
+    if let Some(acceptor) = server.tls_acceptor() {
+        handle.spawn(reload_signal(&handle).for_each(move |_| {
+            match acceptor.reload() {
+                Ok(()) => info!("Reloaded the TLS certificates"),
+                Err(e) => error!("failed to reload the TLS certificates: {}", e),
+            }
+            Ok(())
+        }).map_err(|e| error!("failed to listen for signals: {}", e)));
+    }
+
+    if let Err(e) = core.run(server.run()) {
+        error!("server error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Resolve on the first SIGINT or SIGTERM.
+#[cfg(unix)]
+fn shutdown_signal(handle: &Handle) -> Box<Future<Item = (), Error = io::Error>> {
+    use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+    let signals = Signal::new(SIGINT, handle)
+        .flatten_stream()
+        .select(Signal::new(SIGTERM, handle).flatten_stream());
+    Box::new(signals.into_future().map(|_| ()).map_err(|(e, _)| e))
+}
+
+/// Resolve on the first Ctrl-C.
+#[cfg(not(unix))]
+fn shutdown_signal(handle: &Handle) -> Box<Future<Item = (), Error = io::Error>> {
+    Box::new(
+        tokio_signal::ctrl_c(handle)
+            .flatten_stream()
+            .into_future()
+            .map(|_| ())
+            .map_err(|(e, _)| e),
+    )
+}
+
+/// Yield on every SIGHUP.
+#[cfg(unix)]
+fn reload_signal(handle: &Handle) -> Box<Stream<Item = (), Error = io::Error>> {
+    use tokio_signal::unix::{Signal, SIGHUP};
+
+    Box::new(Signal::new(SIGHUP, handle).flatten_stream().map(|_| ()))
+}
+
+#[cfg(not(unix))]
+fn reload_signal(_: &Handle) -> Box<Stream<Item = (), Error = io::Error>> {
+    Box::new(::futures::stream::empty())
 }
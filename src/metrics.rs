@@ -0,0 +1,145 @@
+//! Request metrics exported in the Prometheus text format.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::time::Duration;
+use model::{OrderStatus, Status};
+use petstore::{Petstore, PetstoreResult};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: &'static [f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS.len()];
+        }
+        for (count, &bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<(&'static str, String), u64>,
+    latencies: BTreeMap<(&'static str, String), Histogram>,
+    in_flight: u64,
+}
+
+/// The metrics of a server, shared by all its connections.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Rc<RefCell<Registry>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn request_started(&self) {
+        self.0.borrow_mut().in_flight += 1;
+    }
+
+    /// Record a finished request. `status` is the status code, or `error` if no response was sent.
+    pub fn request_finished(&self, route: &'static str, status: String, latency: Duration) {
+        let mut registry = self.0.borrow_mut();
+        registry.in_flight -= 1;
+        *registry.requests.entry((route, status.clone())).or_insert(0) += 1;
+        registry
+            .latencies
+            .entry((route, status))
+            .or_insert_with(Default::default)
+            .observe(latency.as_secs() as f64 + latency.subsec_nanos() as f64 / 1e9);
+    }
+
+    /// Render the request metrics, followed by gauges of the contents of `store`.
+    pub fn render(&self, store: &Petstore) -> PetstoreResult<String> {
+        let registry = self.0.borrow();
+        let mut out = String::new();
+
+        out += "# HELP petstore_http_requests_total Requests answered, by route and status.\n";
+        out += "# TYPE petstore_http_requests_total counter\n";
+        for (&(route, ref status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "petstore_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                route, status, count
+            );
+        }
+
+        out += "# HELP petstore_http_request_duration_seconds Latency of requests, by route and status.\n";
+        out += "# TYPE petstore_http_request_duration_seconds histogram\n";
+        for (&(route, ref status), histogram) in &registry.latencies {
+            let name = "petstore_http_request_duration_seconds";
+            let labels = format!("route=\"{}\",status=\"{}\"", route, status);
+            for (bound, count) in BUCKETS.iter().zip(&histogram.counts) {
+                let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, count);
+            }
+            let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+
+        out += "# HELP petstore_http_requests_in_flight Requests being handled.\n";
+        out += "# TYPE petstore_http_requests_in_flight gauge\n";
+        let _ = writeln!(out, "petstore_http_requests_in_flight {}", registry.in_flight);
+
+        let inventory = store.get_inventory()?;
+        out += "# HELP petstore_pets Pets in the store, by status.\n";
+        out += "# TYPE petstore_pets gauge\n";
+        for &(status, count) in &[
+            (Status::Available, inventory.available),
+            (Status::Pending, inventory.pending),
+            (Status::Adopted, inventory.adopted),
+        ] {
+            let _ = writeln!(out, "petstore_pets{{status=\"{}\"}} {}", status, count);
+        }
+
+        out += "# HELP petstore_orders Orders in the store, by status.\n";
+        out += "# TYPE petstore_orders gauge\n";
+        for &status in &[OrderStatus::Placed, OrderStatus::Approved, OrderStatus::Delivered] {
+            let _ = writeln!(out, "petstore_orders{{status=\"{}\"}} {}", status, store.count_orders(status)?);
+        }
+
+        out += "# HELP petstore_users Registered users.\n";
+        out += "# TYPE petstore_users gauge\n";
+        let _ = writeln!(out, "petstore_users {}", store.count_users()?);
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.request_started();
+        metrics.request_finished("pet::GetPet", "200".into(), Duration::from_millis(20));
+        metrics.request_started();
+
+        let text = metrics.render(&Petstore::new()).unwrap();
+        assert!(text.contains("petstore_http_requests_total{route=\"pet::GetPet\",status=\"200\"} 1\n"));
+        let bucket = "petstore_http_request_duration_seconds_bucket{route=\"pet::GetPet\",status=\"200\"";
+        assert!(text.contains(&format!("{},le=\"0.01\"}} 0\n", bucket)));
+        assert!(text.contains(&format!("{},le=\"0.025\"}} 1\n", bucket)));
+        assert!(text.contains(&format!("{},le=\"+Inf\"}} 1\n", bucket)));
+        assert!(text.contains("petstore_http_requests_in_flight 1\n"));
+        assert!(text.contains("petstore_users 0\n"));
+    }
+}
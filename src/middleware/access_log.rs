@@ -0,0 +1,177 @@
+use std::rc::Rc;
+use std::str;
+use std::time::Instant;
+use futures::{Async, Future, Poll};
+use hyper::{self, Method, Request, Response};
+use hyper::header::ContentLength;
+use hyper::server::Service;
+use log::Level;
+use rand;
+use serde_json::Value;
+use context::{self, RequestContext, WithContext};
+use logging;
+
+pub const REQUEST_ID_HEADER: &'static str = "X-Request-Id";
+
+/// Take the request ID given by the client, or generate a new one.
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get_raw(REQUEST_ID_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|id| str::from_utf8(id).ok())
+        .and_then(|id| {
+            if !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_graphic()) {
+                Some(id.to_owned())
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+/// Log every request once it has been answered, and echo its ID in `X-Request-Id`.
+///
+/// The request is handled within a `RequestContext`, which collects the matched route
+/// and the spans recorded while handling it.
+#[derive(Debug, Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+}
+
+impl<S> AccessLog<S> {
+    pub fn new(inner: S) -> Self {
+        AccessLog { inner }
+    }
+}
+
+impl<S> Service for AccessLog<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Logged<S::Future>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let context = Rc::new(RequestContext::new(request_id(&req)));
+        let (method, path) = (req.method().clone(), req.path().to_owned());
+        let inner = context::scope(&context, || self.inner.call(req));
+        Logged {
+            inner: WithContext::new(inner, context.clone()),
+            context,
+            method,
+            path,
+            start: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Logged<F> {
+    inner: WithContext<F>,
+    context: Rc<RequestContext>,
+    method: Method,
+    path: String,
+    start: Instant,
+}
+
+impl<F> Logged<F> {
+    fn log(&self, status: Value, bytes: Option<u64>) {
+        logging::event(Level::Info, "petstore::access", "request", &self.fields(status, bytes));
+    }
+
+    fn fields(&self, status: Value, bytes: Option<u64>) -> Vec<(String, Value)> {
+        let millis = |d: ::std::time::Duration| d.as_secs() as f64 * 1e3 + d.subsec_nanos() as f64 / 1e6;
+        let mut fields = vec![
+            ("request_id".to_owned(), self.context.id().into()),
+            ("method".to_owned(), self.method.to_string().into()),
+            ("path".to_owned(), self.path.clone().into()),
+            ("route".to_owned(), self.context.route().unwrap_or("-").into()),
+            ("status".to_owned(), status),
+            ("latency_ms".to_owned(), millis(self.start.elapsed()).into()),
+        ];
+        if let Some(bytes) = bytes {
+            fields.push(("bytes".to_owned(), bytes.into()));
+        }
+        for (name, elapsed) in self.context.spans() {
+            fields.push((format!("{}_ms", name), millis(elapsed).into()));
+        }
+        fields
+    }
+}
+
+impl<F> Future for Logged<F>
+where
+    F: Future<Item = Response, Error = hyper::Error>,
+{
+    type Item = Response;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Response, hyper::Error> {
+        match self.inner.poll() {
+            Ok(Async::Ready(mut response)) => {
+                let bytes = response.headers().get::<ContentLength>().map(|len| len.0);
+                self.log(response.status().as_u16().into(), bytes);
+                response
+                    .headers_mut()
+                    .set_raw(REQUEST_ID_HEADER, self.context.id().to_owned());
+                Ok(Async::Ready(response))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.log(e.to_string().into(), None);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use hyper::Uri;
+    use hyper::server::service_fn;
+
+    #[test]
+    fn test_access_log_fields() {
+        let service = AccessLog::new(service_fn(|_| {
+            context::with_current(|context| context.set_route("pet::GetPet"));
+            context::span("store", || ());
+            future::ok::<_, hyper::Error>(Response::new().with_header(ContentLength(2)).with_body("{}"))
+        }));
+        let mut req = Request::new(Method::Get, "/pet/42?x=1".parse::<Uri>().unwrap());
+        req.headers_mut().set_raw(REQUEST_ID_HEADER, "abc-123");
+
+        let mut logged = service.call(req);
+        let response = match logged.poll() {
+            Ok(Async::Ready(response)) => response,
+            _ => panic!("the response is ready"),
+        };
+        assert_eq!(response.headers().get_raw(REQUEST_ID_HEADER).unwrap().one(), Some(&b"abc-123"[..]));
+
+        let fields = logged.fields(200.into(), Some(2));
+        let names: Vec<_> = fields.iter().map(|&(ref name, _)| &name[..]).collect();
+        assert_eq!(
+            names,
+            vec!["request_id", "method", "path", "route", "status", "latency_ms", "bytes", "store_ms"]
+        );
+        let values: Vec<_> = fields.iter().take(5).map(|&(_, ref value)| value.clone()).collect();
+        assert_eq!(values, vec![json!("abc-123"), json!("GET"), json!("/pet/42"), json!("pet::GetPet"), json!(200)]);
+    }
+
+    #[test]
+    fn test_request_id() {
+        let request = |id: Option<&str>| {
+            let mut req = Request::new(Method::Get, "/".parse::<Uri>().unwrap());
+            if let Some(id) = id {
+                req.headers_mut().set_raw(REQUEST_ID_HEADER, id.to_owned());
+            }
+            req
+        };
+        assert_eq!(request_id(&request(Some("abc-123"))), "abc-123");
+        assert_eq!(request_id(&request(None)).len(), 16);
+        assert_eq!(request_id(&request(Some("a b"))).len(), 16);
+    }
+}
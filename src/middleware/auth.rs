@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::str;
+use futures::future::{self, Either, FutureResult};
+use hyper::{self, Request, Response, StatusCode};
+use hyper::server::Service;
+use context;
+use super::plain_response;
+
+/// The header carrying the API key, as in the upstream Swagger Petstore.
+pub const API_KEY_HEADER: &'static str = "api_key";
+
+/// Extract the API key of a request, if any.
+pub fn api_key(req: &Request) -> Option<&str> {
+    req.headers()
+        .get_raw(API_KEY_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|key| str::from_utf8(key).ok())
+}
+
+/// Reject requests without one of the configured API keys with `401 Unauthorized`.
+///
+/// Every request is accepted if no key is configured. The accepted key identifies the client in the
+/// `RequestContext`.
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth<S> {
+    inner: S,
+    keys: Rc<HashSet<String>>,
+}
+
+impl<S> ApiKeyAuth<S> {
+    pub fn new(inner: S, keys: Vec<String>) -> Self {
+        ApiKeyAuth {
+            inner,
+            keys: Rc::new(keys.into_iter().collect()),
+        }
+    }
+}
+
+impl<S> Service for ApiKeyAuth<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Either<FutureResult<Response, hyper::Error>, S::Future>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        if !self.keys.is_empty() {
+            match api_key(&req).into_iter().find(|key| self.keys.contains(*key)) {
+                Some(key) => {
+                    context::with_current(|context| context.set_client(key.to_owned()));
+                }
+                None => {
+                    return Either::A(future::ok(plain_response(
+                        StatusCode::Unauthorized,
+                        "missing or invalid API key",
+                    )))
+                }
+            }
+        }
+        Either::B(self.inner.call(req))
+    }
+}
@@ -0,0 +1,236 @@
+use futures::{future, Future, Stream};
+use futures::future::Loop;
+use hyper::{self, Post, Request, Response, StatusCode};
+use hyper::server::Service;
+use api::common::json_response;
+use bulk::{BulkFormat, RowParser, RowResult};
+use context;
+use petstore::Petstore;
+use super::compression::{content_encoding, DecodeError, DecodedBody};
+use super::plain_response;
+
+pub const BULK_IMPORT_PATH: &'static str = "/pet/bulk";
+
+/// Whether a request is a bulk import, which the other middlewares let stream through.
+pub fn is_bulk_import(req: &Request) -> bool {
+    *req.method() == Post && req.path() == BULK_IMPORT_PATH
+}
+
+/// Import the pets of `POST /pet/bulk` as the rows arrive.
+///
+/// The endpoints only see fully buffered bodies, so this route is served before them.
+/// A compressed body is decoded as it arrives too. As the rows are independent, those
+/// received before the body exceeds the size limit stay imported: the body is not read any
+/// further, and their outcome is reported along with `413 Payload Too Large`.
+///
+/// A single row may not be longer than `max_row_size`, so that an unterminated one is not
+/// buffered up to the size of the whole body.
+#[derive(Debug, Clone)]
+pub struct BulkImport<S> {
+    inner: S,
+    store: Petstore,
+    max_size: u64,
+    max_row_size: u64,
+}
+
+impl<S> BulkImport<S> {
+    pub fn new(inner: S, store: Petstore, max_size: u64, max_row_size: u64) -> Self {
+        BulkImport {
+            inner,
+            store,
+            max_size,
+            max_row_size,
+        }
+    }
+}
+
+fn format_of(req: &Request) -> Option<BulkFormat> {
+    let content_type = req.headers()
+        .get_raw("Content-Type")
+        .and_then(|raw| raw.one())
+        .unwrap_or(b"");
+    [BulkFormat::Ndjson, BulkFormat::Csv]
+        .iter()
+        .cloned()
+        .find(|format| content_type.starts_with(format.content_type().as_bytes()))
+}
+
+/// The body of a bulk import, decoded if need be, which fails with `DecodeError::TooLarge` once
+/// it exceeds `max_size` bytes.
+fn rows_body(req: Request, max_size: u64) -> Result<Box<Stream<Item = Vec<u8>, Error = DecodeError>>, Response> {
+    let encoding = match content_encoding(req.headers()) {
+        None => {
+            let mut size = 0;
+            return Ok(Box::new(req.body().map_err(DecodeError::Body).and_then(move |chunk| {
+                size += chunk.len() as u64;
+                if size > max_size {
+                    Err(DecodeError::TooLarge)
+                } else {
+                    Ok(chunk.to_vec())
+                }
+            })));
+        }
+        Some(Ok(encoding)) => encoding,
+        Some(Err(())) => {
+            return Err(plain_response(
+                StatusCode::UnsupportedMediaType,
+                "unsupported content encoding",
+            ))
+        }
+    };
+    match encoding.decoder(max_size) {
+        Ok(decoder) => Ok(Box::new(DecodedBody::new(req.body(), decoder, max_size))),
+        Err(e) => {
+            error!("failed to create a {} decoder: {}", encoding.name(), e);
+            Err(plain_response(
+                StatusCode::InternalServerError,
+                "request body cannot be decompressed",
+            ))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Import {
+    parser: RowParser,
+    results: Vec<RowResult>,
+}
+
+impl<S> Service for BulkImport<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        if !is_bulk_import(&req) {
+            return Box::new(self.inner.call(req));
+        }
+        context::with_current(|context| context.set_route("pet::ImportPets"));
+        let format = match format_of(&req) {
+            Some(format) => format,
+            None => {
+                return Box::new(future::ok(plain_response(
+                    StatusCode::UnsupportedMediaType,
+                    "expected a body of type application/x-ndjson or text/csv",
+                )))
+            }
+        };
+
+        let body = match rows_body(req, self.max_size) {
+            Ok(body) => body,
+            Err(response) => return Box::new(future::ok(response)),
+        };
+
+        let store = self.store.clone();
+        let import = Import {
+            parser: RowParser::new(format).max_row_size(self.max_row_size as usize),
+            results: vec![],
+        };
+        Box::new(future::loop_fn((body, import), move |(body, mut import)| {
+            let store = store.clone();
+            body.into_future().then(move |next| match next {
+                Ok((Some(chunk), body)) => {
+                    let rows = import.parser.push(&chunk);
+                    import.results.extend(store.import_pets(rows));
+                    Ok(Loop::Continue((body, import)))
+                }
+                Ok((None, _)) => {
+                    import.results.extend(store.import_pets(import.parser.finish()));
+                    Ok(Loop::Break(json_response(&import.results)))
+                }
+                Err((DecodeError::TooLarge, _)) => Ok(Loop::Break(
+                    json_response(&import.results).with_status(StatusCode::PayloadTooLarge),
+                )),
+                Err((DecodeError::Invalid(_), _)) => Ok(Loop::Break(
+                    json_response(&import.results).with_status(StatusCode::BadRequest),
+                )),
+                Err((DecodeError::Body(e), _)) => Err(e),
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+    use futures::Sink;
+    use hyper::{Body, Uri};
+    use hyper::server::service_fn;
+    use serde_json::{self, Value};
+    use middleware::compression::Encoding;
+
+    const ROW: &'static [u8] = b"{\"name\":\"doggie\",\"photo_urls\":[]}\n";
+
+    /// A body whose chunks arrive one at a time, until it is dropped.
+    fn chunked(chunks: Vec<Vec<u8>>) -> Body {
+        let (mut tx, body) = Body::pair();
+        thread::spawn(move || {
+            for chunk in chunks {
+                tx = match tx.send(Ok(chunk.into())).wait() {
+                    Ok(tx) => tx,
+                    Err(_) => return,
+                };
+            }
+        });
+        body
+    }
+
+    fn import(chunks: Vec<Vec<u8>>, encoding: Option<Encoding>, max_size: u64) -> (StatusCode, Value) {
+        let inner = service_fn(|_: Request| future::ok::<_, hyper::Error>(Response::new()));
+        let service = BulkImport::new(inner, Petstore::new(), max_size, 2 * ROW.len() as u64);
+        let mut req = Request::new(Post, BULK_IMPORT_PATH.parse::<Uri>().unwrap());
+        req.headers_mut().set_raw("Content-Type", BulkFormat::Ndjson.content_type());
+        if let Some(encoding) = encoding {
+            req.headers_mut().set_raw("Content-Encoding", encoding.name());
+        }
+        req.set_body(chunked(chunks));
+        let response = service.call(req).wait().unwrap();
+        let status = response.status();
+        let body = response.body().concat2().wait().unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn test_import_stops_at_the_limit() {
+        let rows = vec![ROW.to_vec(); 3];
+        let (status, results) = import(rows.clone(), None, 3 * ROW.len() as u64);
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(results.as_array().unwrap().len(), 3);
+
+        // The rows received before the limit are imported and reported.
+        let (status, results) = import(rows, None, 2 * ROW.len() as u64 + 1);
+        assert_eq!(status, StatusCode::PayloadTooLarge);
+        assert_eq!(results.as_array().unwrap().len(), 2);
+        assert!(results[1]["id"].is_u64());
+    }
+
+    #[test]
+    fn test_import_rejects_long_rows() {
+        let long = format!("{{\"name\":\"{}\",\"photo_urls\":[]}}\n", "x".repeat(100)).into_bytes();
+        let (status, results) = import(vec![ROW.to_vec(), long, ROW.to_vec()], None, 1024);
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(results.as_array().unwrap().len(), 3);
+        assert!(results[1]["error"].is_string());
+        assert!(results[2]["id"].is_u64());
+    }
+
+    #[test]
+    fn test_import_compressed() {
+        let data: Vec<u8> = (0..3).flat_map(|_| ROW.iter().cloned()).collect();
+        let encoded = Encoding::Gzip.encode(&data).unwrap();
+        let chunks: Vec<Vec<u8>> = encoded.chunks(5).map(|chunk| chunk.to_vec()).collect();
+        let (status, results) = import(chunks.clone(), Some(Encoding::Gzip), data.len() as u64);
+        assert_eq!(status, StatusCode::Ok);
+        assert_eq!(results.as_array().unwrap().len(), 3);
+
+        let (status, results) = import(chunks, Some(Encoding::Gzip), 2 * ROW.len() as u64);
+        assert_eq!(status, StatusCode::PayloadTooLarge);
+        assert!(results.as_array().unwrap().len() <= 2);
+    }
+}
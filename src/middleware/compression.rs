@@ -0,0 +1,667 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+use std::str;
+use brotli;
+use flate2;
+use flate2::write::{GzDecoder, GzEncoder};
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::{self, Head, Headers, Request, Response, StatusCode};
+use hyper::header::{ContentLength, ContentType, ETag, EntityTag};
+use hyper::server::Service;
+use zstd;
+use config::CompressionConfig;
+use super::bulk_import::is_bulk_import;
+use super::{plain_response, reassemble};
+
+/// The media types which are already compressed, and thus not worth compressing again.
+const COMPRESSED_MEDIA_TYPES: &'static [&'static str] = &["image/", "video/", "audio/", "application/zip"];
+
+/// The magic number of a Zstandard frame.
+const ZSTD_MAGIC: u64 = 0xFD2F_B528;
+
+/// The magic number of a skippable Zstandard frame, whose last 4 bits are free.
+const ZSTD_SKIPPABLE_MAGIC: u64 = 0x184D_2A50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// The supported encodings, by order of preference.
+    const ALL: &'static [Encoding] = &[Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Encoding> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|encoding| encoding.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            Encoding::Brotli => {
+                let mut out = vec![];
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(out)
+            }
+            Encoding::Zstd => zstd::stream::encode_all(data, 3),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Create a decoder whose output is limited to `max_size` bytes.
+    pub fn decoder(&self, max_size: u64) -> io::Result<Decoder> {
+        let sink = Sink::new(max_size);
+        let writer = match *self {
+            Encoding::Brotli => Writer::Brotli(brotli::DecompressorWriter::new(sink.clone(), 4096)),
+            Encoding::Zstd => Writer::Zstd(zstd::stream::write::Decoder::new(sink.clone())?, ZstdFrames::new()),
+            Encoding::Gzip => Writer::Gzip(GzDecoder::new(sink.clone())),
+        };
+        Ok(Decoder { writer, sink })
+    }
+}
+
+/// The reasons why a body cannot be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    Body(hyper::Error),
+    Invalid(io::Error),
+    TooLarge,
+}
+
+/// The output of a `Decoder`, which refuses to grow past its limit.
+#[derive(Debug, Clone)]
+struct Sink(Rc<RefCell<SinkState>>);
+
+#[derive(Debug)]
+struct SinkState {
+    buf: Vec<u8>,
+    remaining: u64,
+    overflowed: bool,
+}
+
+impl Sink {
+    fn new(max_size: u64) -> Self {
+        Sink(Rc::new(RefCell::new(SinkState {
+            buf: vec![],
+            remaining: max_size,
+            overflowed: false,
+        })))
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.borrow_mut();
+        if data.len() as u64 > state.remaining {
+            state.overflowed = true;
+            return Err(io::Error::new(io::ErrorKind::Other, "decoded body is too large"));
+        }
+        state.remaining -= data.len() as u64;
+        state.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Writer {
+    Brotli(brotli::DecompressorWriter<Sink>),
+    Zstd(zstd::stream::write::Decoder<Sink>, ZstdFrames),
+    Gzip(GzDecoder<Sink>),
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A field of a Zstandard stream, which is read whole.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ZstdField {
+    Magic,
+    SkippableSize,
+    Descriptor,
+    BlockHeader,
+}
+
+impl ZstdField {
+    fn len(&self) -> usize {
+        match *self {
+            ZstdField::Magic | ZstdField::SkippableSize => 4,
+            ZstdField::Descriptor => 1,
+            ZstdField::BlockHeader => 3,
+        }
+    }
+}
+
+/// Follows the frames of a Zstandard stream, as its decoder does not tell whether the last one
+/// was complete. Only the headers are read; the blocks themselves are left to the decoder.
+#[derive(Debug)]
+struct ZstdFrames {
+    field: ZstdField,
+    buf: Vec<u8>,
+    /// The number of bytes to skip before the next field.
+    skip: u64,
+    checksum: bool,
+    frames: usize,
+}
+
+impl ZstdFrames {
+    fn new() -> Self {
+        ZstdFrames {
+            field: ZstdField::Magic,
+            buf: vec![],
+            skip: 0,
+            checksum: false,
+            frames: 0,
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            if self.skip > 0 {
+                let len = cmp::min(self.skip, data.len() as u64) as usize;
+                self.skip -= len as u64;
+                data = &data[len..];
+                continue;
+            }
+            let len = cmp::min(self.field.len() - self.buf.len(), data.len());
+            self.buf.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.buf.len() == self.field.len() {
+                // The fields are little-endian.
+                let value = self.buf
+                    .iter()
+                    .rev()
+                    .fold(0u64, |value, &byte| value << 8 | u64::from(byte));
+                self.buf.clear();
+                self.read(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, value: u64) -> io::Result<()> {
+        self.field = match self.field {
+            ZstdField::Magic if value == ZSTD_MAGIC => {
+                self.frames += 1;
+                ZstdField::Descriptor
+            }
+            ZstdField::Magic if value & !0xF == ZSTD_SKIPPABLE_MAGIC => {
+                self.frames += 1;
+                ZstdField::SkippableSize
+            }
+            ZstdField::Magic => return Err(invalid("not a zstd frame")),
+            ZstdField::SkippableSize => {
+                self.skip = value;
+                ZstdField::Magic
+            }
+            ZstdField::Descriptor => {
+                let single_segment = value & 0x20 != 0;
+                self.checksum = value & 0x04 != 0;
+                let window_size = if single_segment { 0 } else { 1 };
+                let dictionary_id = [0, 1, 2, 4][(value & 0x03) as usize];
+                let content_size = match value >> 6 {
+                    0 if single_segment => 1,
+                    0 => 0,
+                    1 => 2,
+                    2 => 4,
+                    _ => 8,
+                };
+                self.skip = window_size + dictionary_id + content_size;
+                ZstdField::BlockHeader
+            }
+            ZstdField::BlockHeader => {
+                let size = match (value >> 1) & 0x03 {
+                    // A RLE block holds a single byte.
+                    1 => 1,
+                    3 => return Err(invalid("reserved zstd block type")),
+                    _ => value >> 3,
+                };
+                if value & 0x01 != 0 {
+                    self.skip = size + if self.checksum { 4 } else { 0 };
+                    ZstdField::Magic
+                } else {
+                    self.skip = size;
+                    ZstdField::BlockHeader
+                }
+            }
+        };
+        Ok(())
+    }
+
+    /// Whether the stream ends with a complete frame.
+    fn is_complete(&self) -> bool {
+        self.frames > 0 && self.field == ZstdField::Magic && self.buf.is_empty() && self.skip == 0
+    }
+}
+
+/// Decompresses a body chunk by chunk, and stops as soon as its output exceeds the limit, so that
+/// neither the compressed nor the decoded body has to be held in memory past it.
+pub struct Decoder {
+    writer: Writer,
+    sink: Sink,
+}
+
+impl Decoder {
+    /// Decode a chunk of the body, returning the output it produced so far.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let written = match self.writer {
+            Writer::Brotli(ref mut writer) => writer.write_all(data),
+            Writer::Zstd(ref mut writer, ref mut frames) => frames.push(data).and_then(|()| writer.write_all(data)),
+            Writer::Gzip(ref mut writer) => writer.write_all(data),
+        };
+        self.check(written)
+    }
+
+    /// Decode what remains once the whole body was pushed, which fails if the body was truncated.
+    pub fn finish(mut self) -> Result<Vec<u8>, DecodeError> {
+        let finished = match self.writer {
+            // `close` fails unless the decompressor reached the last meta-block.
+            Writer::Brotli(ref mut writer) => writer.close(),
+            Writer::Zstd(ref mut writer, ref frames) => writer.flush().and_then(|()| {
+                if frames.is_complete() {
+                    Ok(())
+                } else {
+                    Err(invalid("the zstd stream is truncated"))
+                }
+            }),
+            Writer::Gzip(ref mut writer) => writer.try_finish(),
+        };
+        self.check(finished)
+    }
+
+    fn check(&mut self, result: io::Result<()>) -> Result<Vec<u8>, DecodeError> {
+        let mut state = self.sink.0.borrow_mut();
+        match result {
+            Ok(()) => Ok(mem::replace(&mut state.buf, vec![])),
+            Err(_) if state.overflowed => Err(DecodeError::TooLarge),
+            Err(e) => Err(DecodeError::Invalid(e)),
+        }
+    }
+}
+
+/// A body decoded as its chunks arrive.
+///
+/// The compressed body is subject to the limit too, as a body which does not expand could
+/// otherwise be streamed forever.
+pub struct DecodedBody<S> {
+    body: S,
+    decoder: Option<Decoder>,
+    remaining: u64,
+}
+
+impl<S> DecodedBody<S> {
+    pub fn new(body: S, decoder: Decoder, max_size: u64) -> Self {
+        DecodedBody {
+            body,
+            decoder: Some(decoder),
+            remaining: max_size,
+        }
+    }
+}
+
+impl<S> Stream for DecodedBody<S>
+where
+    S: Stream<Error = hyper::Error>,
+    S::Item: AsRef<[u8]>,
+{
+    type Item = Vec<u8>;
+    type Error = DecodeError;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, DecodeError> {
+        loop {
+            if self.decoder.is_none() {
+                return Ok(Async::Ready(None));
+            }
+            match self.body.poll().map_err(DecodeError::Body)? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(Some(chunk)) => {
+                    let chunk = chunk.as_ref();
+                    if chunk.len() as u64 > self.remaining {
+                        return Err(DecodeError::TooLarge);
+                    }
+                    self.remaining -= chunk.len() as u64;
+                    let decoded = self.decoder.as_mut().expect("checked above").push(chunk)?;
+                    if !decoded.is_empty() {
+                        return Ok(Async::Ready(Some(decoded)));
+                    }
+                }
+                Async::Ready(None) => {
+                    let decoded = self.decoder.take().expect("checked above").finish()?;
+                    return Ok(Async::Ready(Some(decoded)));
+                }
+            }
+        }
+    }
+}
+
+/// The encoding of a request body, or `Err` if it is not supported.
+pub fn content_encoding(headers: &Headers) -> Option<Result<Encoding, ()>> {
+    let name = match headers.get_raw("Content-Encoding").and_then(|raw| raw.one()) {
+        Some(name) => name,
+        None => return None,
+    };
+    str::from_utf8(name).ok().and_then(|name| {
+        if name.trim().eq_ignore_ascii_case("identity") {
+            None
+        } else {
+            Some(Encoding::from_name(name.trim()).ok_or(()))
+        }
+    })
+}
+
+/// Choose the preferred encoding among those accepted by the client, if any.
+fn negotiate(headers: &Headers) -> Option<Encoding> {
+    let accept = match headers.get_raw("Accept-Encoding") {
+        Some(accept) => accept,
+        None => return None,
+    };
+    let mut explicit = vec![];
+    let mut wildcard = None;
+    for line in accept.iter().filter_map(|line| str::from_utf8(line).ok()) {
+        for item in line.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let name = params.next().unwrap_or("");
+            let quality: f32 = params
+                .filter_map(|param| {
+                    let mut param = param.splitn(2, '=').map(str::trim);
+                    match (param.next(), param.next()) {
+                        (Some(name), Some(value)) if name.eq_ignore_ascii_case("q") => value.parse().ok(),
+                        _ => None,
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            if name == "*" {
+                wildcard = Some(quality);
+            } else if let Some(encoding) = Encoding::from_name(name) {
+                explicit.push((encoding, quality));
+            }
+        }
+    }
+
+    // Ties are broken by our own order of preference.
+    Encoding::ALL
+        .iter()
+        .filter_map(|&encoding| {
+            explicit
+                .iter()
+                .find(|&&(other, _)| other == encoding)
+                .map(|&(_, quality)| quality)
+                .or(wildcard)
+                .map(|quality| (encoding, quality))
+        })
+        .filter(|&(_, quality)| quality > 0.0)
+        .fold(None, |best, (encoding, quality)| match best {
+            Some((_, best_quality)) if best_quality >= quality => best,
+            _ => Some((encoding, quality)),
+        })
+        .map(|(encoding, _)| encoding)
+}
+
+/// Whether a response is worth compressing. Streamed responses, whose size is unknown, are not.
+fn is_compressible(response: &Response, min_size: u64) -> bool {
+    let large_enough = match response.headers().get::<ContentLength>() {
+        Some(&ContentLength(len)) => len >= min_size,
+        None => false,
+    };
+    let compressed = response.headers().get::<ContentType>().map_or(false, |content_type| {
+        COMPRESSED_MEDIA_TYPES
+            .iter()
+            .any(|prefix| content_type.as_ref().starts_with(prefix))
+    });
+    large_enough && !compressed && !response.headers().has_raw("Content-Encoding")
+}
+
+/// The entity tag of a compressed representation, which must differ from the identity one.
+///
+/// A strong tag gets the name of the encoding appended, so that `If-Match` still accepts it.
+fn encoded_etag(etag: &EntityTag, encoding: Encoding) -> EntityTag {
+    if etag.weak {
+        etag.clone()
+    } else {
+        EntityTag::strong(format!("{}-{}", etag.tag(), encoding.name()))
+    }
+}
+
+fn compress(response: Response, encoding: Encoding) -> Box<Future<Item = Response, Error = hyper::Error>> {
+    let (status, mut headers) = (response.status(), response.headers().clone());
+    Box::new(response.body().concat2().map(move |body| {
+        match encoding.encode(&body) {
+            Ok(compressed) => {
+                headers.set(ContentLength(compressed.len() as u64));
+                headers.set_raw("Content-Encoding", encoding.name());
+                if let Some(etag) = headers.get::<ETag>().map(|etag| encoded_etag(etag, encoding)) {
+                    headers.set(ETag(etag));
+                }
+                headers.append_raw("Vary", "Accept-Encoding");
+                Response::new()
+                    .with_status(status)
+                    .with_headers(headers)
+                    .with_body(compressed)
+            }
+            Err(e) => {
+                warn!("failed to compress a response with {}: {}", encoding.name(), e);
+                Response::new()
+                    .with_status(status)
+                    .with_headers(headers)
+                    .with_body(body)
+            }
+        }
+    }))
+}
+
+/// Compress the responses with the encoding preferred by the client, and decompress the
+/// request bodies according to their `Content-Encoding`.
+///
+/// A compressed body is decoded as it arrives, and rejected as soon as it exceeds the same
+/// size limit as a plain one. The bulk import is left to `BulkImport`, which imports the rows
+/// as they are decoded.
+#[derive(Debug, Clone)]
+pub struct Compression<S> {
+    inner: S,
+    config: CompressionConfig,
+    max_body_size: u64,
+}
+
+impl<S> Compression<S> {
+    pub fn new(inner: S, config: &CompressionConfig, max_body_size: u64) -> Self {
+        Compression {
+            inner,
+            config: config.clone(),
+            max_body_size,
+        }
+    }
+}
+
+impl<S> Compression<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error> + Clone + 'static,
+{
+    fn call_decoded(&self, req: Request) -> Box<Future<Item = Response, Error = hyper::Error>> {
+        // The bulk import decodes its body itself, as it streams.
+        if is_bulk_import(&req) {
+            return Box::new(self.inner.call(req));
+        }
+        let encoding = match content_encoding(req.headers()) {
+            None => return Box::new(self.inner.call(req)),
+            Some(Ok(encoding)) => encoding,
+            Some(Err(())) => {
+                return Box::new(future::ok(plain_response(
+                    StatusCode::UnsupportedMediaType,
+                    "unsupported content encoding",
+                )))
+            }
+        };
+
+        if let Some(&ContentLength(len)) = req.headers().get::<ContentLength>() {
+            if len > self.max_body_size {
+                return Box::new(future::ok(plain_response(
+                    StatusCode::PayloadTooLarge,
+                    "request body is too large",
+                )));
+            }
+        }
+        let decoder = match encoding.decoder(self.max_body_size) {
+            Ok(decoder) => decoder,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+        let inner = self.inner.clone();
+        let (method, uri, version, mut headers, body) = req.deconstruct();
+        let decoded = DecodedBody::new(body, decoder, self.max_body_size).concat2();
+        Box::new(decoded.then(move |decoded| -> Box<Future<Item = Response, Error = hyper::Error>> {
+            match decoded {
+                Ok(body) => {
+                    headers.remove_raw("Content-Encoding");
+                    headers.set(ContentLength(body.len() as u64));
+                    Box::new(inner.call(reassemble(method, uri, version, headers, body)))
+                }
+                Err(DecodeError::Body(e)) => Box::new(future::err(e)),
+                Err(DecodeError::TooLarge) => Box::new(future::ok(plain_response(
+                    StatusCode::PayloadTooLarge,
+                    "request body is too large",
+                ))),
+                Err(DecodeError::Invalid(_)) => Box::new(future::ok(plain_response(
+                    StatusCode::BadRequest,
+                    "request body cannot be decompressed",
+                ))),
+            }
+        }))
+    }
+}
+
+impl<S> Service for Compression<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error> + Clone + 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let encoding = if self.config.enabled && *req.method() != Head {
+            negotiate(req.headers())
+        } else {
+            None
+        };
+        let response = self.call_decoded(req);
+        match encoding {
+            Some(encoding) => {
+                let min_size = self.config.min_size;
+                Box::new(response.and_then(move |response| -> Box<Future<Item = Response, Error = hyper::Error>> {
+                    if is_compressible(&response, min_size) {
+                        compress(response, encoding)
+                    } else {
+                        Box::new(future::ok(response))
+                    }
+                }))
+            }
+            None => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    /// Decode `data`, received in small chunks.
+    fn decode(encoding: Encoding, data: &[u8], max_size: u64) -> Result<Vec<u8>, DecodeError> {
+        let chunks: Vec<Vec<u8>> = data.chunks(7).map(|chunk| chunk.to_vec()).collect();
+        let body = stream::iter_ok::<_, hyper::Error>(chunks);
+        DecodedBody::new(body, encoding.decoder(max_size).unwrap(), max_size)
+            .concat2()
+            .wait()
+    }
+
+    fn is_too_large(result: Result<Vec<u8>, DecodeError>) -> bool {
+        match result {
+            Err(DecodeError::TooLarge) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let negotiate_with = |accept: &'static str| {
+            let mut headers = Headers::new();
+            headers.set_raw("Accept-Encoding", accept);
+            negotiate(&headers)
+        };
+        assert_eq!(negotiate_with("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate_with("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_with("*, br;q=0"), Some(Encoding::Zstd));
+        assert_eq!(negotiate_with("br;Q=0, zstd; q = 0.1, gzip;q=0.2"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_with("deflate, identity"), None);
+    }
+
+    #[test]
+    fn test_encoded_etag() {
+        let strong = encoded_etag(&EntityTag::strong("3".to_owned()), Encoding::Brotli);
+        assert_eq!(strong, EntityTag::strong("3-br".to_owned()));
+        let weak = EntityTag::weak("3".to_owned());
+        assert_eq!(encoded_etag(&weak, Encoding::Gzip), weak);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..100)
+            .flat_map(|_| br#"[{"name":"doggie","photo_urls":[]}]"#.iter().cloned())
+            .collect();
+        for &encoding in Encoding::ALL {
+            let encoded = encoding.encode(&data).unwrap();
+            assert!(encoded.len() < data.len());
+            assert_eq!(decode(encoding, &encoded, data.len() as u64).unwrap(), data);
+            assert!(is_too_large(decode(encoding, &encoded, 10)));
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        match decode(Encoding::Gzip, b"not gzip at all", 1024) {
+            Err(DecodeError::Invalid(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        // A truncated body is not a shorter one.
+        let data: Vec<u8> = (0..100u32).flat_map(|i| i.to_string().into_bytes()).collect();
+        for &encoding in Encoding::ALL {
+            let encoded = encoding.encode(&data).unwrap();
+            for &len in &[1, encoded.len() / 2, encoded.len() - 1] {
+                match decode(encoding, &encoded[..len], 1024) {
+                    Err(DecodeError::Invalid(_)) => {}
+                    other => panic!("{} truncated to {} bytes: {:?}", encoding.name(), len, other),
+                }
+            }
+        }
+
+        // A body is too large if it is, even before it is decoded.
+        let encoded = Encoding::Gzip.encode(b"").unwrap();
+        assert!(is_too_large(decode(Encoding::Gzip, &encoded, 4)));
+    }
+}
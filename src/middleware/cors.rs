@@ -0,0 +1,185 @@
+use std::rc::Rc;
+use std::str;
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, FutureResult};
+use hyper::{self, Method, Request, Response, StatusCode};
+use hyper::header::{ContentLength, Headers};
+use hyper::server::Service;
+use api::routes::allowed_methods;
+use config::CorsConfig;
+
+/// The response headers which the scripts of other origins may read.
+const EXPOSED_HEADERS: &'static str =
+    "ETag, Retry-After, X-Request-Id, X-RateLimit-Limit, X-RateLimit-Remaining, X-RateLimit-Reset, X-Total-Count";
+
+fn raw_header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .and_then(|value| str::from_utf8(value).ok())
+}
+
+/// Answer the preflight requests of browsers, and allow the configured origins to read
+/// the responses.
+///
+/// Requests from other origins are passed through without any CORS header, so browsers block them.
+#[derive(Debug, Clone)]
+pub struct Cors<S> {
+    inner: S,
+    config: Rc<CorsConfig>,
+}
+
+impl<S> Cors<S> {
+    pub fn new(inner: S, config: &CorsConfig) -> Self {
+        Cors {
+            inner,
+            config: Rc::new(config.clone()),
+        }
+    }
+
+    fn is_allowed(&self, origin: &str) -> bool {
+        self.config
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    /// The methods allowed on `path`, or `None` if no route matches it.
+    fn methods(&self, path: &str) -> Option<Vec<String>> {
+        let methods: Vec<String> = allowed_methods(path)
+            .into_iter()
+            .map(|method| method.to_string())
+            .filter(|method| {
+                self.config.allowed_methods.is_empty()
+                    || self.config
+                        .allowed_methods
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(method))
+            })
+            .collect();
+        if methods.is_empty() {
+            None
+        } else {
+            Some(methods)
+        }
+    }
+
+    fn preflight(&self, origin: String, methods: Vec<String>) -> Response {
+        let mut response = Response::new()
+            .with_status(StatusCode::NoContent)
+            .with_header(ContentLength(0));
+        set_allow_origin(response.headers_mut(), &self.config, origin);
+        let headers = response.headers_mut();
+        headers.set_raw("Access-Control-Allow-Methods", methods.join(", "));
+        if !self.config.allowed_headers.is_empty() {
+            headers.set_raw("Access-Control-Allow-Headers", self.config.allowed_headers.join(", "));
+        }
+        headers.set_raw("Access-Control-Max-Age", self.config.max_age.to_string());
+        response
+    }
+}
+
+fn set_allow_origin(headers: &mut Headers, config: &CorsConfig, origin: String) {
+    // A wildcard cannot be combined with credentials, so the origin is echoed instead.
+    if config.allowed_origins.iter().any(|o| o == "*") && !config.allow_credentials {
+        headers.set_raw("Access-Control-Allow-Origin", "*");
+    } else {
+        headers.set_raw("Access-Control-Allow-Origin", origin);
+        headers.append_raw("Vary", "Origin");
+    }
+    if config.allow_credentials {
+        headers.set_raw("Access-Control-Allow-Credentials", "true");
+    }
+}
+
+impl<S> Service for Cors<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Either<FutureResult<Response, hyper::Error>, WithCors<S::Future>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let origin = match raw_header(req.headers(), "Origin") {
+            Some(origin) if self.config.is_enabled() && self.is_allowed(origin) => Some(origin.to_owned()),
+            _ => None,
+        };
+        if let Some(origin) = origin.clone() {
+            let is_preflight = *req.method() == Method::Options
+                && raw_header(req.headers(), "Access-Control-Request-Method").is_some();
+            if is_preflight {
+                if let Some(methods) = self.methods(req.path()) {
+                    return Either::A(future::ok(self.preflight(origin, methods)));
+                }
+            }
+        }
+        Either::B(WithCors {
+            inner: self.inner.call(req),
+            origin,
+            config: self.config.clone(),
+        })
+    }
+}
+
+/// A response to an actual cross-origin request, which is given the CORS headers once available.
+#[derive(Debug)]
+pub struct WithCors<F> {
+    inner: F,
+    origin: Option<String>,
+    config: Rc<CorsConfig>,
+}
+
+impl<F> Future for WithCors<F>
+where
+    F: Future<Item = Response, Error = hyper::Error>,
+{
+    type Item = Response;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Response, hyper::Error> {
+        match self.inner.poll()? {
+            Async::Ready(mut response) => {
+                if let Some(origin) = self.origin.take() {
+                    set_allow_origin(response.headers_mut(), &self.config, origin);
+                    response
+                        .headers_mut()
+                        .set_raw("Access-Control-Expose-Headers", EXPOSED_HEADERS);
+                }
+                Ok(Async::Ready(response))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Uri;
+    use hyper::server::service_fn;
+
+    #[test]
+    fn test_preflight() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://editor.swagger.io".into()],
+            ..CorsConfig::default()
+        };
+        let cors = Cors::new(service_fn(|_| -> FutureResult<Response, hyper::Error> { unreachable!() }), &config);
+        let mut req = Request::new(Method::Options, "/pet/42".parse::<Uri>().unwrap());
+        req.headers_mut().set_raw("Origin", "https://editor.swagger.io");
+        req.headers_mut().set_raw("Access-Control-Request-Method", "DELETE");
+
+        let response = cors.call(req).wait().unwrap();
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(
+            raw_header(response.headers(), "Access-Control-Allow-Origin"),
+            Some("https://editor.swagger.io")
+        );
+        assert_eq!(
+            raw_header(response.headers(), "Access-Control-Allow-Methods"),
+            Some("GET, PATCH, DELETE, POST")
+        );
+    }
+}
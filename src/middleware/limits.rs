@@ -0,0 +1,80 @@
+use futures::{future, Future, Stream};
+use hyper::{self, Request, Response, StatusCode};
+use hyper::header::{ContentLength, TransferEncoding};
+use hyper::server::Service;
+use config::LimitsConfig;
+use super::bulk_import::is_bulk_import;
+use super::{plain_response, reassemble};
+
+/// Reject requests whose body exceeds the limit of their route with `413 Payload Too Large`.
+///
+/// The bodies without `Content-Length` are read up to the limit before being passed on,
+/// except for the bulk import, which enforces the limit itself while streaming.
+#[derive(Debug, Clone)]
+pub struct BodyLimit<S> {
+    inner: S,
+    config: LimitsConfig,
+}
+
+impl<S> BodyLimit<S> {
+    pub fn new(inner: S, config: &LimitsConfig) -> Self {
+        BodyLimit {
+            inner,
+            config: config.clone(),
+        }
+    }
+
+    fn max_size(&self, req: &Request) -> u64 {
+        if is_bulk_import(req) {
+            self.config.max_bulk_body_size
+        } else {
+            self.config.max_body_size
+        }
+    }
+}
+
+fn too_large() -> Response {
+    plain_response(StatusCode::PayloadTooLarge, "request body is too large")
+}
+
+impl<S> Service for BodyLimit<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error> + Clone + 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let max_size = self.max_size(&req);
+        match req.headers().get::<ContentLength>() {
+            Some(&ContentLength(len)) if len > max_size => return Box::new(future::ok(too_large())),
+            Some(_) => return Box::new(self.inner.call(req)),
+            None if is_bulk_import(&req) || !req.headers().has::<TransferEncoding>() => {
+                return Box::new(self.inner.call(req))
+            }
+            None => {}
+        }
+
+        let inner = self.inner.clone();
+        let (method, uri, version, headers, body) = req.deconstruct();
+        let body = body.fold(Some(vec![]), move |body, chunk| {
+            let body = body.and_then(|mut body: Vec<u8>| {
+                if (body.len() + chunk.len()) as u64 > max_size {
+                    None
+                } else {
+                    body.extend_from_slice(&chunk);
+                    Some(body)
+                }
+            });
+            Ok::<_, hyper::Error>(body)
+        });
+        Box::new(body.and_then(move |body| -> Box<Future<Item = Response, Error = hyper::Error>> {
+            match body {
+                Some(body) => Box::new(inner.call(reassemble(method, uri, version, headers, body))),
+                None => Box::new(future::ok(too_large())),
+            }
+        }))
+    }
+}
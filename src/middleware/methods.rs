@@ -0,0 +1,131 @@
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, FutureResult};
+use hyper::{self, Body, Method, Request, Response, StatusCode};
+use hyper::header::{Allow, ContentLength};
+use hyper::server::Service;
+use api::routes::allowed_methods;
+use super::plain_response;
+
+/// Answer the methods which no endpoint handles on a known path, using the table of routes.
+///
+/// * `HEAD` is handled as `GET`, without the body,
+/// * `OPTIONS` lists the allowed methods in `Allow`,
+/// * any other method gets `405 Method Not Allowed`.
+///
+/// Unknown paths are passed through.
+#[derive(Debug, Clone)]
+pub struct AllowedMethods<S> {
+    inner: S,
+}
+
+impl<S> AllowedMethods<S> {
+    pub fn new(inner: S) -> Self {
+        AllowedMethods { inner }
+    }
+}
+
+fn allow(methods: &[Method]) -> Allow {
+    let mut methods = methods.to_vec();
+    if methods.contains(&Method::Get) {
+        methods.push(Method::Head);
+    }
+    methods.push(Method::Options);
+    Allow(methods)
+}
+
+impl<S> Service for AllowedMethods<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Either<FutureResult<Response, hyper::Error>, WithoutBody<S::Future>>;
+
+    fn call(&self, mut req: Request) -> Self::Future {
+        let methods = allowed_methods(req.path());
+        if methods.is_empty() || methods.contains(req.method()) {
+            return Either::B(WithoutBody {
+                inner: self.inner.call(req),
+                strip: false,
+            });
+        }
+        match *req.method() {
+            Method::Head if methods.contains(&Method::Get) => {
+                req.set_method(Method::Get);
+                Either::B(WithoutBody {
+                    inner: self.inner.call(req),
+                    strip: true,
+                })
+            }
+            Method::Options => Either::A(future::ok(
+                Response::new()
+                    .with_status(StatusCode::NoContent)
+                    .with_header(allow(&methods))
+                    .with_header(ContentLength(0)),
+            )),
+            _ => Either::A(future::ok(
+                plain_response(StatusCode::MethodNotAllowed, "method not allowed").with_header(allow(&methods)),
+            )),
+        }
+    }
+}
+
+/// A response whose body is dropped if it answers a `HEAD` request. The headers are kept as is.
+#[derive(Debug)]
+pub struct WithoutBody<F> {
+    inner: F,
+    strip: bool,
+}
+
+impl<F> Future for WithoutBody<F>
+where
+    F: Future<Item = Response, Error = hyper::Error>,
+{
+    type Item = Response;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Response, hyper::Error> {
+        match self.inner.poll()? {
+            Async::Ready(mut response) => {
+                if self.strip {
+                    response.set_body(Body::empty());
+                }
+                Ok(Async::Ready(response))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Uri;
+    use hyper::server::service_fn;
+
+    #[test]
+    fn test_allowed_methods() {
+        let service = AllowedMethods::new(service_fn(|req: Request| {
+            assert_eq!(*req.method(), Method::Get);
+            future::ok::<_, hyper::Error>(Response::new().with_header(ContentLength(2)).with_body("{}"))
+        }));
+        let request = |method, path: &str| Request::new(method, path.parse::<Uri>().unwrap());
+
+        let response = service.call(request(Method::Head, "/store/inventory")).wait().unwrap();
+        assert_eq!(response.headers().get(), Some(&ContentLength(2)));
+
+        let response = service.call(request(Method::Put, "/store/inventory")).wait().unwrap();
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(
+            response.headers().get(),
+            Some(&Allow(vec![Method::Get, Method::Head, Method::Options]))
+        );
+
+        let response = service.call(request(Method::Options, "/pet")).wait().unwrap();
+        assert_eq!(
+            response.headers().get(),
+            Some(&Allow(vec![Method::Post, Method::Put, Method::Options]))
+        );
+    }
+}
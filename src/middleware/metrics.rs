@@ -0,0 +1,154 @@
+use std::time::Instant;
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, FutureResult};
+use hyper::{self, Get, Request, Response, StatusCode};
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::Service;
+use context;
+use metrics::Metrics;
+use petstore::Petstore;
+use super::plain_response;
+
+pub const METRICS_PATH: &'static str = "/metrics";
+
+/// Record the metrics of every request, and export them on `GET /metrics`.
+///
+/// It has to be wrapped by `AccessLog`, which provides the route of the requests.
+#[derive(Debug, Clone)]
+pub struct RequestMetrics<S> {
+    inner: S,
+    metrics: Metrics,
+    store: Petstore,
+}
+
+impl<S> RequestMetrics<S> {
+    pub fn new(inner: S, store: Petstore) -> Self {
+        RequestMetrics {
+            inner,
+            metrics: Metrics::new(),
+            store,
+        }
+    }
+
+    fn export(&self) -> Response {
+        match self.metrics.render(&self.store) {
+            Ok(body) => Response::new()
+                .with_header(ContentType("text/plain; version=0.0.4".parse().unwrap()))
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body),
+            Err(e) => {
+                error!("failed to collect the metrics: {}", e);
+                plain_response(StatusCode::InternalServerError, "failed to collect the metrics")
+            }
+        }
+    }
+}
+
+impl<S> Service for RequestMetrics<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Either<FutureResult<Response, hyper::Error>, Measured<S::Future>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        if *req.method() == Get && req.path() == METRICS_PATH {
+            return Either::A(future::ok(self.export()));
+        }
+        self.metrics.request_started();
+        Either::B(Measured {
+            inner: self.inner.call(req),
+            metrics: Some(self.metrics.clone()),
+            route: None,
+            start: Instant::now(),
+        })
+    }
+}
+
+/// A request being handled, which is counted as in flight until it completes or is dropped.
+#[derive(Debug)]
+pub struct Measured<F> {
+    inner: F,
+    metrics: Option<Metrics>,
+    /// The route seen while polled, as a request dropped unfinished is no longer in its context.
+    route: Option<&'static str>,
+    start: Instant,
+}
+
+impl<F> Measured<F> {
+    fn capture_route(&mut self) {
+        if let Some(Some(route)) = context::with_current(|context| context.route()) {
+            self.route = Some(route);
+        }
+    }
+
+    fn finish(&mut self, status: String) {
+        self.capture_route();
+        if let Some(metrics) = self.metrics.take() {
+            metrics.request_finished(self.route.unwrap_or("unmatched"), status, self.start.elapsed());
+        }
+    }
+}
+
+impl<F> Future for Measured<F>
+where
+    F: Future<Item = Response, Error = hyper::Error>,
+{
+    type Item = Response;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Response, hyper::Error> {
+        let polled = self.inner.poll();
+        self.capture_route();
+        match polled {
+            Ok(Async::Ready(response)) => {
+                self.finish(response.status().as_u16().to_string());
+                Ok(Async::Ready(response))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.finish("error".to_owned());
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<F> Drop for Measured<F> {
+    fn drop(&mut self) {
+        self.finish("aborted".to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use context::RequestContext;
+    use super::*;
+
+    #[test]
+    fn test_aborted_request_keeps_its_route() {
+        let metrics = Metrics::new();
+        metrics.request_started();
+        let mut measured = Measured {
+            inner: future::poll_fn(|| -> Poll<Response, hyper::Error> {
+                context::with_current(|context| context.set_route("pet::GetPet"));
+                Ok(Async::NotReady)
+            }),
+            metrics: Some(metrics.clone()),
+            route: None,
+            start: Instant::now(),
+        };
+        let context = Rc::new(RequestContext::new("1".into()));
+        assert!(context::scope(&context, || measured.poll()).unwrap().is_not_ready());
+        // The connection is closed, out of the context of the request.
+        drop(measured);
+
+        let text = metrics.render(&Petstore::new()).unwrap();
+        assert!(text.contains("petstore_http_requests_total{route=\"pet::GetPet\",status=\"aborted\"} 1\n"));
+        let count = "petstore_http_request_duration_seconds_count{route=\"pet::GetPet\",status=\"aborted\"} 1\n";
+        assert!(text.contains(count));
+    }
+}
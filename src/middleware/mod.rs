@@ -0,0 +1,44 @@
+//! Wrappers around a hyper `Service` which apply to every route of the API.
+
+pub mod access_log;
+pub mod auth;
+pub mod bulk_import;
+pub mod compression;
+pub mod cors;
+pub mod limits;
+pub mod methods;
+pub mod metrics;
+pub mod probes;
+pub mod rate_limit;
+
+pub use self::access_log::AccessLog;
+pub use self::auth::ApiKeyAuth;
+pub use self::bulk_import::BulkImport;
+pub use self::compression::Compression;
+pub use self::cors::Cors;
+pub use self::limits::BodyLimit;
+pub use self::methods::AllowedMethods;
+pub use self::metrics::RequestMetrics;
+pub use self::probes::Probes;
+pub use self::rate_limit::RateLimit;
+
+use hyper::{Body, Headers, HttpVersion, Method, Request, Response, StatusCode, Uri};
+use hyper::header::{ContentLength, ContentType};
+
+/// Create a plain-text response, as returned by the middlewares when they reject a request.
+pub fn plain_response(status: StatusCode, body: &'static str) -> Response {
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::plaintext())
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+/// Put back together a request taken apart by `Request::deconstruct`, with the given body.
+pub fn reassemble(method: Method, uri: Uri, version: HttpVersion, headers: Headers, body: Vec<u8>) -> Request {
+    let mut req = Request::new(method, uri);
+    req.set_version(version);
+    *req.headers_mut() = headers;
+    req.set_body(Body::from(body));
+    req
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::Path;
+use futures::future::{self, Either, FutureResult};
+use hyper::{self, Get, Request, Response, StatusCode};
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::Service;
+use serde_json::{self, Map, Value};
+use config::{StoreBackend, StoreConfig};
+use petstore::Petstore;
+
+pub const HEALTH_PATH: &'static str = "/healthz";
+pub const READY_PATH: &'static str = "/readyz";
+pub const VERSION_PATH: &'static str = "/version";
+
+/// Answer the liveness, readiness and build information probes, before any authentication.
+#[derive(Debug, Clone)]
+pub struct Probes<S> {
+    inner: S,
+    store: Petstore,
+    store_config: StoreConfig,
+}
+
+impl<S> Probes<S> {
+    pub fn new(inner: S, store: Petstore, store_config: StoreConfig) -> Self {
+        Probes {
+            inner,
+            store,
+            store_config,
+        }
+    }
+
+    /// Check each component the server depends on. The server is ready if all of them are.
+    fn readiness(&self) -> (bool, Value) {
+        let mut components = vec![("store", self.store.ping().map_err(|e| e.to_string()))];
+        if self.store_config.backend == StoreBackend::Snapshot {
+            let dsn = self.store_config.dsn.as_ref().expect("validated on load");
+            components.push(("snapshot", check_writable(Path::new(dsn))));
+        }
+
+        let ready = components.iter().all(|&(_, ref result)| result.is_ok());
+        let components: Map<String, Value> = components
+            .into_iter()
+            .map(|(name, result)| {
+                let status = match result {
+                    Ok(()) => json!({ "status": "ok" }),
+                    Err(e) => json!({ "status": "error", "error": e }),
+                };
+                (name.to_owned(), status)
+            })
+            .collect();
+        let status = if ready { "ok" } else { "error" };
+        (ready, json!({ "status": status, "components": components }))
+    }
+}
+
+/// Check that a snapshot can be written at `path`.
+fn check_writable(path: &Path) -> Result<(), String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match fs::metadata(dir) {
+        Ok(ref metadata) if !metadata.is_dir() => Err(format!("`{}' is not a directory", dir.display())),
+        Ok(ref metadata) if metadata.permissions().readonly() => {
+            Err(format!("`{}' is not writable", dir.display()))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("`{}': {}", dir.display(), e)),
+    }
+}
+
+fn build_info() -> Value {
+    let features: Vec<&str> = env!("PETSTORE_FEATURES")
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("PETSTORE_GIT_SHA"),
+        "features": features,
+    })
+}
+
+fn json_response(status: StatusCode, content: &Value) -> Response {
+    let body = serde_json::to_vec(content).expect("a JSON value is always serializable");
+    Response::new()
+        .with_status(status)
+        .with_header(ContentType::json())
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+impl<S> Service for Probes<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Either<FutureResult<Response, hyper::Error>, S::Future>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        if *req.method() != Get {
+            return Either::B(self.inner.call(req));
+        }
+        let response = match req.path() {
+            HEALTH_PATH => json_response(StatusCode::Ok, &json!({ "status": "ok" })),
+            READY_PATH => {
+                let (ready, report) = self.readiness();
+                let status = if ready {
+                    StatusCode::Ok
+                } else {
+                    StatusCode::ServiceUnavailable
+                };
+                json_response(status, &report)
+            }
+            VERSION_PATH => json_response(StatusCode::Ok, &build_info()),
+            _ => return Either::B(self.inner.call(req)),
+        };
+        Either::A(future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness() {
+        let store = Petstore::new();
+        let probes = Probes::new((), store.clone(), StoreConfig::default());
+        assert_eq!(probes.readiness().0, true);
+
+        let mut config = StoreConfig::default();
+        config.backend = StoreBackend::Snapshot;
+        config.dsn = Some("/nonexistent/petstore.json".into());
+        let (ready, report) = Probes::new((), store, config).readiness();
+        assert_eq!(ready, false);
+        assert_eq!(report["components"]["store"]["status"], "ok");
+        assert_eq!(report["components"]["snapshot"]["status"], "error");
+    }
+}
@@ -0,0 +1,349 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use futures::{Async, Future, Poll, Stream};
+use futures::future::{self, Either, FutureResult};
+use hyper::{self, Request, Response, StatusCode};
+use hyper::header::{Authorization, Bearer, RetryAfter};
+use hyper::server::Service;
+use tokio_core::reactor::{Handle, Interval};
+use config::RateLimitConfig;
+use context;
+use petstore::Petstore;
+use super::plain_response;
+
+/// The number of buckets above which the fullest one is forgotten to make room for a new client.
+const MAX_BUCKETS: usize = 10_000;
+
+/// The groups of routes which have their own quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Group {
+    Pet,
+    Store,
+    User,
+}
+
+impl Group {
+    fn of(path: &str) -> Option<Group> {
+        match path.trim_left_matches('/').split('/').next() {
+            // The trash lists the deleted pets, before the deleted users.
+            Some("pet") | Some("trash") => Some(Group::Pet),
+            Some("store") => Some(Group::Store),
+            Some("user") => Some(Group::User),
+            _ => None,
+        }
+    }
+}
+
+/// Identify the client of a request, by the user of its session, the API key `ApiKeyAuth` accepted,
+/// or else its address.
+///
+/// Unverified credentials are ignored, as a client could otherwise get a new quota with each request.
+fn client_key(req: &Request, store: &Petstore) -> String {
+    let user = req.headers()
+        .get::<Authorization<Bearer>>()
+        .and_then(|auth| store.session_user(&auth.0.token).ok());
+    if let Some(user) = user {
+        return format!("user:{}", user);
+    }
+    if let Some(Some(key)) = context::with_current(|context| context.client()) {
+        return format!("key:{}", key);
+    }
+    match context::peer_addr() {
+        Some(addr) => format!("ip:{}", addr.ip()),
+        None => "unknown".to_owned(),
+    }
+}
+
+/// A token bucket holding up to `capacity` requests, refilled continuously over `window`.
+#[derive(Debug, Clone)]
+struct Bucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The state of a bucket after a request, as reported in the response headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quota {
+    limit: u32,
+    remaining: u32,
+    /// Seconds until the bucket is full again.
+    reset: u64,
+}
+
+impl Bucket {
+    fn new(capacity: u32, window: Duration, now: Instant) -> Self {
+        Bucket {
+            capacity: capacity as f64,
+            rate: capacity as f64 / seconds(window),
+            tokens: capacity as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = seconds(now.duration_since(self.updated));
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn quota(&self) -> Quota {
+        Quota {
+            limit: self.capacity as u32,
+            remaining: self.tokens as u32,
+            reset: ((self.capacity - self.tokens) / self.rate).ceil() as u64,
+        }
+    }
+
+    /// Take a token, or return the number of seconds until one is available.
+    fn take(&mut self, now: Instant) -> Result<Quota, u64> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(self.quota())
+        } else {
+            Err(((1.0 - self.tokens) / self.rate).ceil() as u64)
+        }
+    }
+}
+
+/// Forget the buckets unused for a whole window, which are full again and thus as good as new ones.
+fn sweep(buckets: &mut HashMap<(Group, String), Bucket>, now: Instant, window: Duration) {
+    buckets.retain(|_, bucket| now.duration_since(bucket.updated) < window);
+}
+
+/// Forget the bucket nearest to full, whose client gains the least from getting a new one.
+///
+/// The clients which barely used their quota, e.g. one spread over many addresses, are thus the ones
+/// to make room for the others, while the exhausted clients stay throttled.
+fn evict(buckets: &mut HashMap<(Group, String), Bucket>, now: Instant) {
+    let fullest = buckets
+        .iter_mut()
+        .map(|(key, bucket)| {
+            bucket.refill(now);
+            (key, bucket.tokens / bucket.capacity)
+        })
+        .fold(None, |fullest, (key, fill)| match fullest {
+            Some((_, max)) if max >= fill => fullest,
+            _ => Some((key, fill)),
+        })
+        .map(|(key, _)| key.clone());
+    if let Some(key) = fullest {
+        buckets.remove(&key);
+    }
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+fn set_quota_headers(response: &mut Response, quota: &Quota) {
+    let headers = response.headers_mut();
+    headers.set_raw("X-RateLimit-Limit", quota.limit.to_string());
+    headers.set_raw("X-RateLimit-Remaining", quota.remaining.to_string());
+    headers.set_raw("X-RateLimit-Reset", quota.reset.to_string());
+}
+
+/// Throttle each client with a token bucket per group of routes.
+///
+/// Exhausted clients get `429 Too Many Requests`, with `Retry-After` telling when to retry.
+/// The idle buckets are swept once per window. The sessions are looked up in `store`.
+#[derive(Debug, Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    config: RateLimitConfig,
+    store: Petstore,
+    buckets: Rc<RefCell<HashMap<(Group, String), Bucket>>>,
+}
+
+impl<S> RateLimit<S> {
+    pub fn new(inner: S, config: &RateLimitConfig, store: Petstore, handle: &Handle) -> io::Result<Self> {
+        let buckets: Rc<RefCell<HashMap<(Group, String), Bucket>>> = Default::default();
+        let window = Duration::from_secs(config.window);
+        // Stop sweeping once the service is gone.
+        let weak = Rc::downgrade(&buckets);
+        let sweeps = Interval::new(window, handle)?
+            .map(move |_| weak.upgrade())
+            .take_while(|buckets| Ok(buckets.is_some()))
+            .for_each(move |buckets| {
+                if let Some(buckets) = buckets {
+                    sweep(&mut buckets.borrow_mut(), Instant::now(), window);
+                }
+                Ok(())
+            });
+        handle.spawn(sweeps.map_err(|e| error!("failed to sweep the rate limits: {}", e)));
+        Ok(RateLimit {
+            inner,
+            config: config.clone(),
+            store,
+            buckets,
+        })
+    }
+
+    fn limit(&self, group: Group) -> u32 {
+        match group {
+            Group::Pet => self.config.pet,
+            Group::Store => self.config.store,
+            Group::User => self.config.user,
+        }
+    }
+
+    fn take(&self, group: Group, client: String, now: Instant) -> Result<Quota, (Quota, u64)> {
+        let limit = self.limit(group);
+        let window = Duration::from_secs(self.config.window);
+        let mut buckets = self.buckets.borrow_mut();
+        let key = (group, client);
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            evict(&mut buckets, now);
+        }
+        let bucket = buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::new(limit, window, now));
+        bucket.take(now).map_err(|retry_after| (bucket.quota(), retry_after))
+    }
+}
+
+impl<S> Service for RateLimit<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Either<FutureResult<Response, hyper::Error>, WithQuota<S::Future>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let group = match Group::of(req.path()) {
+            Some(group) if self.limit(group) > 0 => group,
+            _ => {
+                return Either::B(WithQuota {
+                    inner: self.inner.call(req),
+                    quota: None,
+                })
+            }
+        };
+        let client = client_key(&req, &self.store);
+        match self.take(group, client, Instant::now()) {
+            Ok(quota) => Either::B(WithQuota {
+                inner: self.inner.call(req),
+                quota: Some(quota),
+            }),
+            Err((quota, retry_after)) => {
+                let mut response = plain_response(StatusCode::TooManyRequests, "rate limit exceeded");
+                set_quota_headers(&mut response, &quota);
+                response
+                    .headers_mut()
+                    .set(RetryAfter::Delay(Duration::from_secs(retry_after)));
+                Either::A(future::ok(response))
+            }
+        }
+    }
+}
+
+/// A response which is given the rate limit headers once available.
+#[derive(Debug)]
+pub struct WithQuota<F> {
+    inner: F,
+    quota: Option<Quota>,
+}
+
+impl<F> Future for WithQuota<F>
+where
+    F: Future<Item = Response, Error = hyper::Error>,
+{
+    type Item = Response;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Response, hyper::Error> {
+        match self.inner.poll()? {
+            Async::Ready(mut response) => {
+                if let Some(ref quota) = self.quota {
+                    set_quota_headers(&mut response, quota);
+                }
+                Ok(Async::Ready(response))
+            }
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(2, Duration::from_secs(10), start);
+        assert_eq!(
+            bucket.take(start),
+            Ok(Quota {
+                limit: 2,
+                remaining: 1,
+                reset: 5,
+            })
+        );
+        assert!(bucket.take(start).is_ok());
+        assert_eq!(bucket.take(start), Err(5));
+        assert!(bucket.take(start + Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn test_buckets_are_bounded() {
+        use tokio_core::reactor::Core;
+
+        let core = Core::new().unwrap();
+        let config = RateLimitConfig {
+            pet: 2,
+            ..Default::default()
+        };
+        let limiter = RateLimit::new((), &config, Petstore::new(), &core.handle()).unwrap();
+        let start = Instant::now();
+        assert!(limiter.take(Group::Pet, "ip:greedy".into(), start).is_ok());
+        assert!(limiter.take(Group::Pet, "ip:greedy".into(), start).is_ok());
+        for i in 0..MAX_BUCKETS + 2 {
+            assert!(limiter.take(Group::Pet, format!("ip:{}", i), start).is_ok());
+        }
+        assert_eq!(limiter.buckets.borrow().len(), MAX_BUCKETS);
+        // The new clients made room for each other, and the exhausted one is still throttled.
+        assert!(limiter.take(Group::Pet, "ip:new".into(), start).is_ok());
+        assert!(limiter.take(Group::Pet, "ip:greedy".into(), start).is_err());
+
+        let later = start + Duration::from_secs(config.window / 2);
+        assert!(limiter.take(Group::Pet, "ip:0".into(), later).is_ok());
+        let window = Duration::from_secs(config.window);
+        sweep(&mut limiter.buckets.borrow_mut(), start + window, window);
+        assert_eq!(limiter.buckets.borrow().keys().map(|key| &key.1[..]).collect::<Vec<_>>(), vec!["ip:0"]);
+    }
+
+    #[test]
+    fn test_client_key() {
+        use hyper::Method;
+        use serde_json;
+
+        let store = Petstore::new();
+        let alice = serde_json::from_value(json!({"username": "alice", "password": "secret"})).unwrap();
+        store.add_users(vec![alice]).unwrap();
+        let token = store.login("alice".into(), "secret".into()).unwrap().token;
+        let request = |token: &str| {
+            let mut req = Request::new(Method::Get, "/pet/1".parse().unwrap());
+            req.headers_mut().set(Authorization(Bearer { token: token.to_owned() }));
+            req
+        };
+        assert_eq!(client_key(&request(&token), &store), "user:alice");
+        // Outside of a connection, an unknown session leaves the client unidentified.
+        assert_eq!(client_key(&request("forged"), &store), "unknown");
+    }
+
+    #[test]
+    fn test_group() {
+        assert_eq!(Group::of("/pet/findByStatus"), Some(Group::Pet));
+        assert_eq!(Group::of("/store"), Some(Group::Store));
+        assert_eq!(Group::of("/trash"), Some(Group::Pet));
+        assert_eq!(Group::of("/healthz"), None);
+    }
+}
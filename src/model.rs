@@ -1,6 +1,9 @@
 use std::fmt;
 use std::io;
 use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Category {
@@ -8,21 +11,120 @@ pub struct Category {
     pub name: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Inventory {
     pub available: u32,
     pub pending: u32,
     pub adopted: u32,
 }
 
+impl Inventory {
+    pub fn count(&mut self, status: Status) {
+        match status {
+            Status::Available => self.available += 1,
+            Status::Pending => self.pending += 1,
+            Status::Adopted => self.adopted += 1,
+        }
+    }
+}
+
+/// The entities of a report are the ones dated from `from` (inclusive) until `to` (exclusive).
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Window {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl Window {
+    pub fn is_bounded(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    /// Whether the date is in the window; an unknown date is only in an unbounded one.
+    pub fn contains(&self, date: Option<DateTime<Utc>>) -> bool {
+        match date {
+            Some(date) => self.from.map_or(true, |from| from <= date) && self.to.map_or(true, |to| date < to),
+            None => !self.is_bounded(),
+        }
+    }
+}
+
+/// The criterion by which an inventory is broken down.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InventoryGrouping {
+    Category,
+    Tag,
+}
+
+/// The length of the periods of a time series.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Day,
+    Week,
+    Month,
+}
+
+impl Granularity {
+    /// The first day of the period containing `date`; weeks start on Monday.
+    pub fn period(&self, date: NaiveDate) -> NaiveDate {
+        match *self {
+            Granularity::Day => date,
+            Granularity::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Granularity::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+        }
+    }
+
+    /// The first day of the period following the one starting on `period`.
+    pub fn next(&self, period: NaiveDate) -> NaiveDate {
+        match *self {
+            Granularity::Day => period + Duration::days(1),
+            Granularity::Week => period + Duration::weeks(1),
+            Granularity::Month if period.month() == 12 => NaiveDate::from_ymd(period.year() + 1, 1, 1),
+            Granularity::Month => NaiveDate::from_ymd(period.year(), period.month() + 1, 1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AdoptionCount {
+    /// The first day of the period.
+    pub period: NaiveDate,
+    pub adoptions: u64,
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Order {
     pub id: Option<u64>,
     pub pet_id: Option<u64>,
     pub quantity: Option<u64>,
-    pub ship_date: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_ship_date")]
+    pub ship_date: Option<DateTime<Utc>>,
     pub status: Option<OrderStatus>,
     pub complete: Option<bool>,
+    /// The user who placed the order, set by the store from the session of the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub version: u64,
+}
+
+/// Read an RFC 3339 date-time, naming the field in the error so that clients can tell which one is wrong.
+fn deserialize_ship_date<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(date) => DateTime::parse_from_rfc3339(&date)
+            .map(|date| Some(date.with_timezone(&Utc)))
+            .map_err(|e| D::Error::custom(format!("ship_date: `{}' is not an RFC 3339 date-time: {}", date, e))),
+        None => Ok(None),
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
@@ -62,6 +164,132 @@ impl FromStr for OrderStatus {
     }
 }
 
+/// The order in which orders are listed, by a date then by ID. Orders without the date come
+/// first, or last in descending order.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum OrderSort {
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "-id")]
+    IdDesc,
+    #[serde(rename = "ship_date")]
+    ShipDate,
+    #[serde(rename = "-ship_date")]
+    ShipDateDesc,
+    #[serde(rename = "created_at")]
+    CreatedAt,
+    #[serde(rename = "-created_at")]
+    CreatedAtDesc,
+    #[serde(rename = "updated_at")]
+    UpdatedAt,
+    #[serde(rename = "-updated_at")]
+    UpdatedAtDesc,
+}
+
+impl OrderSort {
+    pub fn is_descending(&self) -> bool {
+        use self::OrderSort::*;
+        match *self {
+            IdDesc | ShipDateDesc | CreatedAtDesc | UpdatedAtDesc => true,
+            Id | ShipDate | CreatedAt | UpdatedAt => false,
+        }
+    }
+
+    /// The date by which `order` is sorted, if any.
+    pub fn date(&self, order: &Order) -> Option<DateTime<Utc>> {
+        use self::OrderSort::*;
+        match *self {
+            Id | IdDesc => None,
+            ShipDate | ShipDateDesc => order.ship_date,
+            CreatedAt | CreatedAtDesc => order.created_at,
+            UpdatedAt | UpdatedAtDesc => order.updated_at,
+        }
+    }
+}
+
+impl Default for OrderSort {
+    fn default() -> Self {
+        OrderSort::Id
+    }
+}
+
+/// The order in which pets or users are listed, by a timestamp then by ID. Entities without
+/// the timestamp come first, or last in descending order.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum TimestampSort {
+    #[serde(rename = "id")]
+    Id,
+    #[serde(rename = "-id")]
+    IdDesc,
+    #[serde(rename = "created_at")]
+    CreatedAt,
+    #[serde(rename = "-created_at")]
+    CreatedAtDesc,
+    #[serde(rename = "updated_at")]
+    UpdatedAt,
+    #[serde(rename = "-updated_at")]
+    UpdatedAtDesc,
+}
+
+impl TimestampSort {
+    pub fn is_descending(&self) -> bool {
+        use self::TimestampSort::*;
+        match *self {
+            IdDesc | CreatedAtDesc | UpdatedAtDesc => true,
+            Id | CreatedAt | UpdatedAt => false,
+        }
+    }
+
+    /// The timestamp by which an entity with these ones is sorted, if any.
+    pub fn date(
+        &self,
+        created_at: Option<DateTime<Utc>>,
+        updated_at: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        use self::TimestampSort::*;
+        match *self {
+            Id | IdDesc => None,
+            CreatedAt | CreatedAtDesc => created_at,
+            UpdatedAt | UpdatedAtDesc => updated_at,
+        }
+    }
+}
+
+impl Default for TimestampSort {
+    fn default() -> Self {
+        TimestampSort::Id
+    }
+}
+
+/// The windows of the timestamps of the pets or users to list, and their order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimestampQuery {
+    pub created_at: Window,
+    pub updated_at: Window,
+    pub sort: TimestampSort,
+}
+
+impl TimestampQuery {
+    pub fn contains(&self, created_at: Option<DateTime<Utc>>, updated_at: Option<DateTime<Utc>>) -> bool {
+        self.created_at.contains(created_at) && self.updated_at.contains(updated_at)
+    }
+}
+
+/// The criteria of the orders to list, and the page of them to return.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OrderQuery {
+    pub status: Option<OrderStatus>,
+    pub pet_id: Option<u64>,
+    pub complete: Option<bool>,
+    pub username: Option<String>,
+    pub ship_date: Window,
+    pub created_at: Window,
+    pub updated_at: Window,
+    pub sort: OrderSort,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Pet {
     pub id: Option<u64>,
@@ -70,6 +298,16 @@ pub struct Pet {
     pub category: Option<Category>,
     pub tags: Option<Vec<Tag>>,
     pub status: Option<Status>,
+    /// Set by the store when the pet is added, and `updated_at` whenever it changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Set by the store when the pet is moved to the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub version: u64,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
@@ -124,6 +362,55 @@ pub struct User {
     pub email: Option<String>,
     pub password: String,
     pub phone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Set by the store when the user is moved to the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub version: u64,
+}
+
+/// A user as listed to other clients, without their password.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PublicUser {
+    pub id: Option<u64>,
+    pub username: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<User> for PublicUser {
+    fn from(user: User) -> Self {
+        PublicUser {
+            id: user.id,
+            username: user.username,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            email: user.email,
+            phone: user.phone,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+/// The deleted entities which have not been purged yet, in the order of their IDs.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Trash {
+    pub pets: Vec<Pet>,
+    pub users: Vec<PublicUser>,
 }
 
 pub use self::OrderStatus::*;
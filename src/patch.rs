@@ -0,0 +1,299 @@
+//! Partial updates via JSON Merge Patch (RFC 7396) and JSON Patch (RFC 6902).
+
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    Merge(Value),
+    Json(Vec<PatchOperation>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchError(String);
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for PatchError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Patch {
+    /// Apply this patch to `target`.
+    ///
+    /// `target` is left untouched if any of the operations fails.
+    pub fn apply(&self, target: &mut Value) -> Result<(), PatchError> {
+        match *self {
+            Patch::Merge(ref patch) => {
+                merge(target, patch);
+                Ok(())
+            }
+            Patch::Json(ref operations) => {
+                let mut patched = target.clone();
+                for operation in operations {
+                    apply_operation(&mut patched, operation)?;
+                }
+                *target = patched;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    let patch = match *patch {
+        Value::Object(ref patch) => patch,
+        ref patch => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    if let Value::Object(ref mut target) = *target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+fn apply_operation(target: &mut Value, operation: &PatchOperation) -> Result<(), PatchError> {
+    use self::PatchOperation::*;
+    match *operation {
+        Add { ref path, ref value } => add(target, path, value.clone()),
+        Remove { ref path } => remove(target, path).map(|_| ()),
+        Replace { ref path, ref value } => {
+            *pointer_mut(target, path)? = value.clone();
+            Ok(())
+        }
+        Move { ref from, ref path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(PatchError(format!("cannot move `{}' into its own child `{}'", from, path)));
+            }
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+        Copy { ref from, ref path } => {
+            let value = pointer(target, from)?.clone();
+            add(target, path, value)
+        }
+        Test { ref path, ref value } => match pointer(target, path) {
+            Ok(current) if current == value => Ok(()),
+            _ => Err(PatchError(format!("test failed for `{}'", path))),
+        },
+    }
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Split a JSON pointer into the pointer to its parent and its unescaped last token.
+fn split_pointer(path: &str) -> Result<(&str, String), PatchError> {
+    match path.rfind('/') {
+        Some(pos) => Ok((&path[..pos], unescape(&path[pos + 1..]))),
+        None => Err(PatchError(format!("`{}' is not a valid JSON pointer", path))),
+    }
+}
+
+/// Split a JSON pointer into its unescaped tokens.
+fn tokens(path: &str) -> Result<Vec<String>, PatchError> {
+    if path.is_empty() {
+        Ok(vec![])
+    } else if path.starts_with('/') {
+        Ok(path[1..].split('/').map(unescape).collect())
+    } else {
+        Err(PatchError(format!("`{}' is not a valid JSON pointer", path)))
+    }
+}
+
+/// Find the value `path` points to.
+///
+/// Unlike `Value::pointer`, which would read `+1` as an index, the array indices are read by
+/// `array_index`, so that every operation accepts the same pointers.
+fn pointer<'a>(target: &'a Value, path: &str) -> Result<&'a Value, PatchError> {
+    let mut current = target;
+    for token in tokens(path)? {
+        current = match *current {
+            Value::Object(ref map) => map.get(&token),
+            Value::Array(ref array) => array_index(&token, array.len())
+                .ok()
+                .and_then(|index| array.get(index)),
+            _ => None,
+        }.ok_or_else(|| PatchError(format!("`{}' does not exist", path)))?;
+    }
+    Ok(current)
+}
+
+fn pointer_mut<'a>(target: &'a mut Value, path: &str) -> Result<&'a mut Value, PatchError> {
+    let mut current = target;
+    for token in tokens(path)? {
+        current = match *{ current } {
+            Value::Object(ref mut map) => map.get_mut(&token),
+            Value::Array(ref mut array) => {
+                let len = array.len();
+                array_index(&token, len).ok().and_then(move |index| array.get_mut(index))
+            }
+            _ => None,
+        }.ok_or_else(|| PatchError(format!("`{}' does not exist", path)))?;
+    }
+    Ok(current)
+}
+
+/// Read an array index, which RFC 6901 restricts to digits without leading zeros.
+fn array_index(token: &str, len: usize) -> Result<usize, PatchError> {
+    let is_digits = !token.is_empty() && token.bytes().all(|b| b >= b'0' && b <= b'9');
+    match token.parse() {
+        Ok(index) if is_digits && index <= len && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(PatchError(format!("`{}' is not a valid array index", token))),
+    }
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let (parent, token) = split_pointer(path)?;
+    match *pointer_mut(target, parent)? {
+        Value::Object(ref mut map) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Value::Array(ref mut array) => {
+            let index = if token == "-" {
+                array.len()
+            } else {
+                array_index(&token, array.len())?
+            };
+            array.insert(index, value);
+            Ok(())
+        }
+        _ => Err(PatchError(format!("`{}' is not a container", parent))),
+    }
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, PatchError> {
+    if path.is_empty() {
+        return Ok(mem::replace(target, Value::Null));
+    }
+    let (parent, token) = split_pointer(path)?;
+    match *pointer_mut(target, parent)? {
+        Value::Object(ref mut map) => map.remove(&token)
+            .ok_or_else(|| PatchError(format!("`{}' does not exist", path))),
+        Value::Array(ref mut array) => {
+            let len = array.len();
+            match array_index(&token, len) {
+                Ok(index) if index < len => Ok(array.remove(index)),
+                _ => Err(PatchError(format!("`{}' does not exist", path))),
+            }
+        }
+        _ => Err(PatchError(format!("`{}' is not a container", parent))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = json!({"title": "Goodbye!", "author": {"givenName": "John", "familyName": "Doe"}});
+        let patch = Patch::Merge(json!({"title": "Hello!", "author": {"familyName": null}}));
+        patch.apply(&mut target).unwrap();
+        assert_eq!(target, json!({"title": "Hello!", "author": {"givenName": "John"}}));
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let mut target = json!({"name": "Alice", "tags": [{"name": "cat"}]});
+        let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+            {"op": "replace", "path": "/name", "value": "Bob"},
+            {"op": "add", "path": "/tags/-", "value": {"name": "cute"}},
+            {"op": "copy", "from": "/name", "path": "/nickname"},
+        ])).unwrap();
+        Patch::Json(operations).apply(&mut target).unwrap();
+        assert_eq!(
+            target,
+            json!({"name": "Bob", "nickname": "Bob", "tags": [{"name": "cat"}, {"name": "cute"}]})
+        );
+    }
+
+    #[test]
+    fn test_array_index() {
+        assert_eq!(array_index("0", 2), Ok(0));
+        assert_eq!(array_index("2", 2), Ok(2));
+        for token in &["+1", "-1", "01", " 1", "", "3"] {
+            assert!(array_index(token, 2).is_err(), "`{}' must be rejected", token);
+        }
+    }
+
+    #[test]
+    fn test_pointers_read_indices_alike() {
+        let target = json!({"tags": [{"name": "cat"}, {"name": "cute"}]});
+        let operations = vec![
+            json!({"op": "replace", "path": "/tags/+1", "value": {"name": "dog"}}),
+            json!({"op": "test", "path": "/tags/01", "value": {"name": "cute"}}),
+            json!({"op": "copy", "from": "/tags/+0", "path": "/tags/-"}),
+            json!({"op": "add", "path": "/tags/+0/id", "value": 1}),
+            json!({"op": "remove", "path": "/tags/-1/name"}),
+            json!({"op": "replace", "path": "tags", "value": []}),
+        ];
+        for operation in operations {
+            let operation: PatchOperation = serde_json::from_value(operation).unwrap();
+            let mut patched = target.clone();
+            assert!(
+                Patch::Json(vec![operation.clone()]).apply(&mut patched).is_err(),
+                "{:?} must be rejected",
+                operation
+            );
+        }
+
+        let mut patched = target.clone();
+        let operation = json!({"op": "replace", "path": "/tags/1/name", "value": "dog"});
+        Patch::Json(vec![serde_json::from_value(operation).unwrap()]).apply(&mut patched).unwrap();
+        assert_eq!(patched, json!({"tags": [{"name": "cat"}, {"name": "dog"}]}));
+    }
+
+    #[test]
+    fn test_json_patch_is_atomic() {
+        let mut target = json!({"name": "Alice"});
+        let patch = Patch::Json(vec![
+            PatchOperation::Replace {
+                path: "/name".into(),
+                value: json!("Bob"),
+            },
+            PatchOperation::Test {
+                path: "/name".into(),
+                value: json!("Alice"),
+            },
+        ]);
+        assert!(patch.apply(&mut target).is_err());
+        assert_eq!(target, json!({"name": "Alice"}));
+    }
+}
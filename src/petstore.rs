@@ -1,7 +1,18 @@
 use std::cell::{self, RefCell};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
 use std::rc::Rc;
+use std::vec;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rand;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
+use serde_json::{self, Value};
+use bulk::RowResult;
 use model::*;
+use patch::Patch;
 use self::PetstoreErrorKind::*;
 
 error_chain! {
@@ -29,14 +40,63 @@ error_chain! {
         RedundantUserName(msg: String) {
             display("redundant username: {}", msg)
         }
+
+        PreconditionFailed(msg: String) {
+            display("precondition failed: {}", msg)
+        }
+
+        PreconditionRequired(msg: String) {
+            display("precondition required: {}", msg)
+        }
+
+        InvalidPatch(msg: String) {
+            display("invalid patch: {}", msg)
+        }
+
+        UnsupportedMediaType(msg: String) {
+            display("unsupported media type: {}", msg)
+        }
+
+        TooManyItems(msg: String) {
+            display("too many items: {}", msg)
+        }
+
+        InvalidCredentials(msg: String) {
+            display("invalid credentials: {}", msg)
+        }
+
+        InvalidSession(msg: String) {
+            display("invalid session: {}", msg)
+        }
+
+        Forbidden(msg: String) {
+            display("forbidden: {}", msg)
+        }
     }
 
     foreign_links {
         Borrow(cell::BorrowError);
         BorrowMutError(cell::BorrowMutError);
+        Io(io::Error);
+        Json(serde_json::Error);
     }
 }
 
+/// The session of a logged in user. Sessions are not persisted in snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub token: String,
+    pub username: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The versions of an entity a client expects to modify, as given by `If-Match`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Precondition {
+    Any,
+    Versions(Vec<u64>),
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Petstore {
     pets: Rc<RefCell<HashMap<u64, Pet>>>,
@@ -45,6 +105,9 @@ pub struct Petstore {
     orders: Rc<RefCell<HashMap<u64, Order>>>,
     photos: Rc<RefCell<HashMap<u64, Vec<u8>>>>,
     users: Rc<RefCell<HashMap<u64, User>>>,
+    sessions: Rc<RefCell<HashMap<String, Session>>>,
+    require_precondition: bool,
+    max_list_length: Option<usize>,
 }
 
 impl Petstore {
@@ -52,11 +115,96 @@ impl Petstore {
         Self::default()
     }
 
+    /// Load a store from a snapshot file written by `save_snapshot`.
+    ///
+    /// A missing file is treated as an empty store.
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> PetstoreResult<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file))?;
+
+        let store = Self::default();
+        *store.pets.borrow_mut() = with_versions(snapshot.pets, |pet| pet.id, |pet, v| pet.version = v);
+        *store.tags.borrow_mut() = snapshot.tags.into_iter().filter_map(|t| t.id.map(|id| (id, t))).collect();
+        *store.categories.borrow_mut() = snapshot
+            .categories
+            .into_iter()
+            .filter_map(|c| c.id.map(|id| (id, c)))
+            .collect();
+        *store.orders.borrow_mut() = with_versions(snapshot.orders, |order| order.id, |order, v| order.version = v);
+        *store.photos.borrow_mut() = snapshot.photos.into_iter().collect();
+        *store.users.borrow_mut() = with_versions(snapshot.users, |user| user.id, |user, v| user.version = v);
+        Ok(store)
+    }
+
+    /// Write the whole store to `path`, replacing the previous snapshot atomically.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> PetstoreResult<()> {
+        let snapshot = Snapshot {
+            pets: self.pets.try_borrow()?.values().map(|p| (p.version, p.clone())).collect(),
+            tags: self.tags.try_borrow()?.values().cloned().collect(),
+            categories: self.categories.try_borrow()?.values().cloned().collect(),
+            orders: self.orders.try_borrow()?.values().map(|o| (o.version, o.clone())).collect(),
+            photos: self.photos.try_borrow()?.clone().into_iter().collect(),
+            users: self.users.try_borrow()?.values().map(|u| (u.version, u.clone())).collect(),
+        };
+
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, &snapshot)?;
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reject modifications which are not conditioned on `If-Match`.
+    pub fn require_precondition(mut self, required: bool) -> Self {
+        self.require_precondition = required;
+        self
+    }
+
+    /// Limit the number of entities listed or created by a single call, and the periods of a report.
+    pub fn max_list_length(mut self, max: usize) -> Self {
+        self.max_list_length = Some(max);
+        self
+    }
+
+    /// Check that every table can be accessed.
+    pub fn ping(&self) -> PetstoreResult<()> {
+        self.pets.try_borrow()?;
+        self.tags.try_borrow()?;
+        self.categories.try_borrow()?;
+        self.orders.try_borrow()?;
+        self.photos.try_borrow()?;
+        self.users.try_borrow()?;
+        Ok(())
+    }
+
+    fn check_precondition(&self, current: Option<u64>, precondition: Option<&Precondition>) -> PetstoreResult<()> {
+        match (precondition, current) {
+            (None, _) if self.require_precondition => {
+                bail!(PreconditionRequired("This request must be conditioned on If-Match".into()))
+            }
+            (None, _) => Ok(()),
+            (Some(&Precondition::Any), Some(_)) => Ok(()),
+            (Some(&Precondition::Versions(ref versions)), Some(current)) if versions.contains(&current) => Ok(()),
+            (Some(_), Some(current)) => bail!(PreconditionFailed(format!(
+                "The current version is {}",
+                current
+            ))),
+            (Some(_), None) => bail!(PreconditionFailed("The entity does not exist".into())),
+        }
+    }
+
     pub fn get_pet(&self, id: u64) -> PetstoreResult<Option<Pet>> {
         self.pets
             .try_borrow()
             .map_err(Into::into)
-            .map(|pets| pets.get(&id).cloned())
+            .map(|pets| pets.get(&id).into_iter().find(|pet| pet.deleted_at.is_none()).cloned())
     }
 
     pub fn add_pet(&self, mut pet: Pet) -> PetstoreResult<u64> {
@@ -72,6 +220,10 @@ impl Petstore {
             pets.keys().map(|id| *id).max().unwrap_or(0) + 1
         };
         pet.id = Some(new_id);
+        pet.version = 1;
+        pet.created_at = Some(Utc::now());
+        pet.updated_at = pet.created_at;
+        pet.deleted_at = None;
         pets.insert(new_id, pet.clone());
 
         if let Some(tags) = pet.tags {
@@ -123,25 +275,31 @@ impl Petstore {
         Ok(category)
     }
 
-    pub fn update_pet(&self, pet: Pet) -> PetstoreResult<Pet> {
+    pub fn update_pet(&self, mut pet: Pet, precondition: Option<Precondition>) -> PetstoreResult<Pet> {
         let id = pet.id
             .ok_or_else(|| MissingIdentifier(format!("Missing id for pet: {:?}", pet)))?;
 
         let mut pets = self.pets.try_borrow_mut()?;
-        if !pets.contains_key(&id) {
-            bail!(MissingPet("Invalid id: doesn't exist".to_string()));
-        }
+        let (version, created_at) = match pets.get(&id) {
+            Some(current) if current.deleted_at.is_none() => (current.version, current.created_at),
+            _ => bail!(MissingPet("Invalid id: doesn't exist".to_string())),
+        };
+        self.check_precondition(Some(version), precondition.as_ref())?;
+        pet.version = version + 1;
+        pet.created_at = created_at;
+        pet.updated_at = Some(Utc::now());
+        pet.deleted_at = None;
         pets.insert(id, pet.clone());
 
         Ok(pet)
     }
 
-    pub fn get_pets_by_status(&self, statuses: Vec<Status>) -> PetstoreResult<Vec<Pet>> {
-        self.find_pets(|p| p.status.map_or(true, |s| statuses.contains(&s)))
+    pub fn get_pets_by_status(&self, statuses: Vec<Status>, query: &TimestampQuery) -> PetstoreResult<Vec<Pet>> {
+        self.find_pets(query, |p| p.status.map_or(true, |s| statuses.contains(&s)))
     }
 
-    pub fn find_pets_by_tag(&self, tags: Vec<String>) -> PetstoreResult<Vec<Pet>> {
-        self.find_pets(|p| {
+    pub fn find_pets_by_tag(&self, tags: Vec<String>, query: &TimestampQuery) -> PetstoreResult<Vec<Pet>> {
+        self.find_pets(query, |p| {
             tags.iter().all(|ftag| {
                 p.tags
                     .as_ref()
@@ -150,44 +308,68 @@ impl Petstore {
         })
     }
 
-    fn find_pets<F>(&self, mut f: F) -> PetstoreResult<Vec<Pet>>
+    fn find_pets<F>(&self, query: &TimestampQuery, mut f: F) -> PetstoreResult<Vec<Pet>>
     where
         F: FnMut(&Pet) -> bool,
     {
         let pets = self.pets.try_borrow()?;
 
-        let mut pets: Vec<_> = pets.values().filter(|&p| f(p)).cloned().collect();
-        pets.sort_by(|l, r| match (l.id, r.id) {
-            (Some(l), Some(r)) => l.partial_cmp(&r).unwrap(),
-            _ => panic!(),
-        });
+        let mut pets: Vec<_> = pets.values()
+            .filter(|&p| p.deleted_at.is_none() && query.contains(p.created_at, p.updated_at) && f(p))
+            .cloned()
+            .collect();
+        pets.sort_by_key(|pet| (query.sort.date(pet.created_at, pet.updated_at), pet.id));
+        if query.sort.is_descending() {
+            pets.reverse();
+        }
 
         Ok(pets)
     }
 
-    pub fn delete_pet(&self, id: u64) -> PetstoreResult<()> {
+    /// Move a pet to the trash, from which it can be restored until it is purged.
+    pub fn delete_pet(&self, id: u64, precondition: Option<Precondition>) -> PetstoreResult<()> {
         let mut pets = self.pets.try_borrow_mut()?;
-        if !pets.contains_key(&id) {
-            bail!(MissingPet(format!(
+        let pet = match pets.get_mut(&id).into_iter().find(|pet| pet.deleted_at.is_none()) {
+            Some(pet) => pet,
+            None => bail!(MissingPet(format!(
                 "Pet with id {} does not exist and cannot be deleted",
                 id
-            )));
-        }
-        pets.remove(&id);
+            ))),
+        };
+        self.check_precondition(Some(pet.version), precondition.as_ref())?;
+        pet.version += 1;
+        pet.deleted_at = Some(Utc::now());
         Ok(())
     }
 
+    /// Take a pet out of the trash.
+    pub fn restore_pet(&self, id: u64) -> PetstoreResult<Pet> {
+        let mut pets = self.pets.try_borrow_mut()?;
+        let pet = match pets.get_mut(&id).into_iter().find(|pet| pet.deleted_at.is_some()) {
+            Some(pet) => pet,
+            None => bail!(MissingPet(format!("Pet with id {} is not in the trash", id))),
+        };
+        pet.version += 1;
+        pet.updated_at = Some(Utc::now());
+        pet.deleted_at = None;
+        Ok(pet.clone())
+    }
+
     pub fn update_pet_name_status(
         &self,
         pet_id: u64,
         name: Option<String>,
         status: Option<Status>,
+        precondition: Option<Precondition>,
     ) -> PetstoreResult<Pet> {
         let mut pets = self.pets.try_borrow_mut()?;
-        if !pets.contains_key(&pet_id) {
-            bail!(MissingPet(format!("Invalid id: doesn't exist")));
-        }
-        let pet = pets.get_mut(&pet_id).unwrap();
+        let pet = match pets.get_mut(&pet_id).into_iter().find(|pet| pet.deleted_at.is_none()) {
+            Some(pet) => pet,
+            None => bail!(MissingPet(format!("Invalid id: doesn't exist"))),
+        };
+        self.check_precondition(Some(pet.version), precondition.as_ref())?;
+        pet.version += 1;
+        pet.updated_at = Some(Utc::now());
         if let Some(s) = status {
             pet.status = Some(s);
         }
@@ -197,30 +379,178 @@ impl Petstore {
         Ok(pet.clone())
     }
 
+    pub fn patch_pet(&self, id: u64, patch: &Patch, precondition: Option<Precondition>) -> PetstoreResult<Option<Pet>> {
+        let mut pets = self.pets.try_borrow_mut()?;
+        let pet = match pets.get_mut(&id).into_iter().find(|pet| pet.deleted_at.is_none()) {
+            Some(pet) => pet,
+            None => return Ok(None),
+        };
+        self.check_precondition(Some(pet.version), precondition.as_ref())?;
+
+        let mut patched: Pet = apply_patch(&*pet, patch)?;
+        if patched.id != pet.id {
+            bail!(InvalidPatch("The ID of a pet cannot be changed".into()));
+        }
+        patched.version = pet.version + 1;
+        patched.created_at = pet.created_at;
+        patched.updated_at = Some(Utc::now());
+        patched.deleted_at = None;
+        self.add_new_tags_and_category(pet, &patched)?;
+        *pet = patched.clone();
+
+        Ok(Some(patched))
+    }
+
+    /// Register the tags and category which `after` gained over `before`, as `add_pet` does for a new pet.
+    fn add_new_tags_and_category(&self, before: &Pet, after: &Pet) -> PetstoreResult<()> {
+        let old_tags = before.tags.as_ref().map_or(&[][..], |tags| &tags[..]);
+        for tag in after.tags.iter().flat_map(|tags| tags) {
+            if tag.id.is_none() && !old_tags.contains(tag) {
+                self.add_tag(tag.clone())?;
+            }
+        }
+        if let Some(ref category) = after.category {
+            if category.id.is_none() && before.category.as_ref() != Some(category) {
+                self.add_category(category.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add every pet yielded by `rows` along with its row number, recording the outcome of each row separately.
+    ///
+    /// The IDs of the rows (and of their tags and category) are ignored, so that an export can be
+    /// imported back: every pet is given a new ID.
+    pub fn import_pets<I>(&self, rows: I) -> Vec<RowResult>
+    where
+        I: IntoIterator<Item = (usize, Result<Pet, String>)>,
+    {
+        rows.into_iter()
+            .map(|(row, pet)| {
+                let result = pet.and_then(|mut pet| {
+                    pet.id = None;
+                    for tag in pet.tags.iter_mut().flat_map(|tags| tags) {
+                        tag.id = None;
+                    }
+                    if let Some(ref mut category) = pet.category {
+                        category.id = None;
+                    }
+                    self.add_pet(pet).map_err(|e| e.to_string())
+                });
+                RowResult {
+                    row,
+                    id: result.as_ref().ok().cloned(),
+                    error: result.err(),
+                }
+            })
+            .collect()
+    }
+
+    /// Iterate over all pets in the order of their IDs, without copying them upfront.
+    pub fn export_pets(&self) -> PetstoreResult<PetExport> {
+        let mut ids: Vec<u64> = self.pets.try_borrow()?.keys().cloned().collect();
+        ids.sort();
+        Ok(PetExport {
+            store: self.clone(),
+            ids: ids.into_iter(),
+        })
+    }
+
     // TODO: add_image
 }
 
 impl Petstore {
     pub fn get_inventory(&self) -> PetstoreResult<Inventory> {
+        self.get_inventory_within(&Window::default())
+    }
+
+    /// Count the pets added within `window` by status.
+    pub fn get_inventory_within(&self, window: &Window) -> PetstoreResult<Inventory> {
         let pets = self.pets.try_borrow()?;
-        let mut inventory = Inventory {
-            available: 0,
-            pending: 0,
-            adopted: 0,
-        };
-        for (_, pet) in &*pets {
-            match pet.status {
-                Some(Available) => inventory.available += 1,
-                Some(Pending) => inventory.pending += 1,
-                Some(Adopted) => inventory.adopted += 1,
-                None => {}
+        let mut inventory = Inventory::default();
+        for pet in pets.values().filter(|pet| pet.deleted_at.is_none() && window.contains(pet.created_at)) {
+            if let Some(status) = pet.status {
+                inventory.count(status);
             }
         }
         Ok(inventory)
     }
 
+    /// Count the pets added within `window` by status, for each category or tag.
+    ///
+    /// Pets without a category (resp. tags) are not counted.
+    pub fn get_inventory_by(
+        &self,
+        grouping: InventoryGrouping,
+        window: &Window,
+    ) -> PetstoreResult<BTreeMap<String, Inventory>> {
+        let pets = self.pets.try_borrow()?;
+        let mut inventories: BTreeMap<String, Inventory> = BTreeMap::new();
+        for pet in pets.values().filter(|pet| pet.deleted_at.is_none() && window.contains(pet.created_at)) {
+            let status = match pet.status {
+                Some(status) => status,
+                None => continue,
+            };
+            let groups: Vec<&String> = match grouping {
+                InventoryGrouping::Category => pet.category.iter().map(|category| &category.name).collect(),
+                InventoryGrouping::Tag => pet.tags.iter().flat_map(|tags| tags).map(|tag| &tag.name).collect(),
+            };
+            for group in groups {
+                inventories.entry(group.clone()).or_insert_with(Default::default).count(status);
+            }
+        }
+        Ok(inventories)
+    }
+
+    /// The number of delivered orders shipped in each period of `window`.
+    ///
+    /// The series runs from the first to the last period of the window, or of the adoptions
+    /// if it is unbounded, including the periods without any adoption.
+    pub fn report_adoptions(&self, window: &Window, granularity: Granularity) -> PetstoreResult<Vec<AdoptionCount>> {
+        let orders = self.orders.try_borrow()?;
+        let mut counts: BTreeMap<NaiveDate, u64> = BTreeMap::new();
+        for order in orders.values().filter(|order| order.status == Some(Delivered)) {
+            match order.ship_date {
+                Some(shipped) if window.contains(Some(shipped)) => {
+                    *counts.entry(granularity.period(shipped.date().naive_utc())).or_insert(0) += 1
+                }
+                _ => {}
+            }
+        }
+
+        let first = window
+            .from
+            .map(|from| from.date().naive_utc())
+            .or_else(|| counts.keys().next().cloned());
+        // `to` is excluded from the window.
+        let last = window
+            .to
+            .map(|to| (to - Duration::nanoseconds(1)).date().naive_utc())
+            .or_else(|| counts.keys().next_back().cloned());
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (granularity.period(first), granularity.period(last)),
+            _ => return Ok(vec![]),
+        };
+
+        let mut series = vec![];
+        let mut period = first;
+        while period <= last {
+            if let Some(max) = self.max_list_length {
+                if series.len() >= max {
+                    bail!(TooManyItems(format!("The report cannot have more than {} periods", max)));
+                }
+            }
+            series.push(AdoptionCount {
+                period,
+                adoptions: counts.get(&period).cloned().unwrap_or(0),
+            });
+            period = granularity.next(period);
+        }
+        Ok(series)
+    }
+
     pub fn add_order(&self, mut order: Order) -> PetstoreResult<u64> {
-        if order.status.is_some() {
+        if order.id.is_some() {
             bail!(InvalidInput("New order should not contain an ID".into()));
         }
         let mut orders = self.orders.try_borrow_mut()?;
@@ -230,13 +560,18 @@ impl Petstore {
             orders.keys().map(|id| *id).max().unwrap_or(0) + 1
         };
         order.id = Some(new_id);
+        order.version = 1;
+        order.created_at = Some(Utc::now());
+        order.updated_at = order.created_at;
         orders.insert(new_id, order.clone());
 
         Ok(new_id)
     }
 
-    pub fn delete_order(&self, id: u64) -> PetstoreResult<bool> {
+    pub fn delete_order(&self, id: u64, precondition: Option<Precondition>) -> PetstoreResult<bool> {
         let mut orders = self.orders.try_borrow_mut()?;
+        let version = orders.get(&id).map(|order| order.version);
+        self.check_precondition(version, precondition.as_ref())?;
         if orders.contains_key(&id) {
             orders.remove(&id);
             Ok(true)
@@ -251,6 +586,63 @@ impl Petstore {
             .map_err(Into::into)
             .map(|orders| orders.get(&id).cloned())
     }
+
+    /// All orders, in the order of their IDs.
+    pub fn list_orders(&self) -> PetstoreResult<Vec<Order>> {
+        let mut orders: Vec<Order> = self.orders.try_borrow()?.values().cloned().collect();
+        orders.sort_by_key(|order| order.id);
+        Ok(orders)
+    }
+
+    /// All tags of the pets, in the order of their IDs.
+    pub fn list_tags(&self) -> PetstoreResult<Vec<Tag>> {
+        let mut tags: Vec<Tag> = self.tags.try_borrow()?.values().cloned().collect();
+        tags.sort_by_key(|tag| tag.id);
+        Ok(tags)
+    }
+
+    /// All categories of the pets, in the order of their IDs.
+    pub fn list_categories(&self) -> PetstoreResult<Vec<Category>> {
+        let mut categories: Vec<Category> = self.categories.try_borrow()?.values().cloned().collect();
+        categories.sort_by_key(|category| category.id);
+        Ok(categories)
+    }
+
+    /// The orders matching `query`, along with their total number before paging.
+    pub fn find_orders(&self, query: &OrderQuery) -> PetstoreResult<(usize, Vec<Order>)> {
+        if let (Some(limit), Some(max)) = (query.limit, self.max_list_length) {
+            if limit > max {
+                bail!(TooManyItems(format!("At most {} orders can be listed at once", max)));
+            }
+        }
+        let orders = self.orders.try_borrow()?;
+        let mut found: Vec<&Order> = orders
+            .values()
+            .filter(|order| {
+                query.status.map_or(true, |status| order.status == Some(status))
+                    && query.pet_id.map_or(true, |pet_id| order.pet_id == Some(pet_id))
+                    && query.complete.map_or(true, |complete| order.complete == Some(complete))
+                    && query.username.as_ref().map_or(true, |name| order.username.as_ref() == Some(name))
+                    && query.ship_date.contains(order.ship_date)
+                    && query.created_at.contains(order.created_at)
+                    && query.updated_at.contains(order.updated_at)
+            })
+            .collect();
+        found.sort_by_key(|order| (query.sort.date(order), order.id));
+        if query.sort.is_descending() {
+            found.reverse();
+        }
+
+        let total = found.len();
+        let limit = query.limit.or(self.max_list_length).unwrap_or(total);
+        let page = found.into_iter().skip(query.offset).take(limit).cloned().collect();
+        Ok((total, page))
+    }
+
+    pub fn count_orders(&self, status: OrderStatus) -> PetstoreResult<u64> {
+        let orders = self.orders.try_borrow()?;
+        Ok(orders.values().filter(|order| order.status == Some(status)).count() as u64)
+    }
 }
 
 // user APIs
@@ -274,42 +666,212 @@ impl Petstore {
             users.keys().map(|id| *id).max().unwrap_or(0) + 1
         };
         new_user.id = Some(new_id);
+        new_user.version = 1;
+        new_user.created_at = Some(Utc::now());
+        new_user.updated_at = new_user.created_at;
+        new_user.deleted_at = None;
         users.insert(new_id, new_user);
 
         Ok(new_username)
     }
 
     pub fn add_users(&self, users: Vec<User>) -> PetstoreResult<Vec<String>> {
+        if let Some(max) = self.max_list_length {
+            if users.len() > max {
+                bail!(TooManyItems(format!("At most {} users can be created at once", max)));
+            }
+        }
         users
             .into_iter()
             .map(move |new_user| self.add_user(new_user))
             .collect()
     }
 
-    pub fn get_user(&self, name: String) -> PetstoreResult<Option<User>> {
+    /// All users, in the order of their IDs.
+    pub fn list_users(&self) -> PetstoreResult<Vec<User>> {
+        let mut users: Vec<User> = self.users
+            .try_borrow()?
+            .values()
+            .filter(|user| user.deleted_at.is_none())
+            .cloned()
+            .collect();
+        users.sort_by_key(|user| user.id);
+        Ok(users)
+    }
+
+    /// List a page of the users matching `query`, along with their total number before paging.
+    pub fn find_users(
+        &self,
+        query: &TimestampQuery,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> PetstoreResult<(usize, Vec<PublicUser>)> {
+        if let (Some(limit), Some(max)) = (limit, self.max_list_length) {
+            if limit > max {
+                bail!(TooManyItems(format!("At most {} users can be listed at once", max)));
+            }
+        }
+        let mut users: Vec<User> = self.list_users()?
+            .into_iter()
+            .filter(|user| query.contains(user.created_at, user.updated_at))
+            .collect();
+        users.sort_by_key(|user| (query.sort.date(user.created_at, user.updated_at), user.id));
+        if query.sort.is_descending() {
+            users.reverse();
+        }
+        let total = users.len();
+        let limit = limit.or(self.max_list_length).unwrap_or(total);
+        let page = users.into_iter().skip(offset).take(limit).map(Into::into).collect();
+        Ok((total, page))
+    }
+
+    pub fn count_users(&self) -> PetstoreResult<u64> {
         let users = self.users.try_borrow()?;
-        Ok(users.values().find(|user| user.username == name).cloned())
+        Ok(users.values().filter(|user| user.deleted_at.is_none()).count() as u64)
     }
 
-    pub fn delete_user(&self, name: String) -> PetstoreResult<()> {
-        let mut users = self.users.try_borrow_mut()?;
-        if let Some(id) = users
+    /// Whether the store contains no pet, order nor user.
+    pub fn is_empty(&self) -> PetstoreResult<bool> {
+        Ok(self.pets.try_borrow()?.is_empty() && self.orders.try_borrow()?.is_empty()
+            && self.users.try_borrow()?.is_empty())
+    }
+
+    /// Open a session for the user, if the password matches.
+    pub fn login(&self, username: String, password: String) -> PetstoreResult<Session> {
+        let users = self.users.try_borrow()?;
+        if !users
             .values()
-            .find(|user| user.username == name)
-            .and_then(|user| user.id)
+            .any(|user| user.username == username && user.password == password && user.deleted_at.is_none())
         {
-            users.remove(&id);
+            bail!(InvalidCredentials("Invalid username/password supplied".into()));
+        }
+        let session = Session {
+            token: format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>()),
+            username,
+            expires_at: Utc::now() + Duration::hours(1),
+        };
+
+        let mut sessions = self.sessions.try_borrow_mut()?;
+        let now = Utc::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(session.token.clone(), session.clone());
+        Ok(session)
+    }
+
+    /// The user of the session opened with `token`.
+    pub fn session_user(&self, token: &str) -> PetstoreResult<String> {
+        let sessions = self.sessions.try_borrow()?;
+        match sessions.get(token) {
+            Some(session) if session.expires_at > Utc::now() => Ok(session.username.clone()),
+            _ => bail!(InvalidSession("The session does not exist or has expired".into())),
+        }
+    }
+
+    /// Check that `token` is a session of `username`, who alone may modify their account and see
+    /// their orders.
+    pub fn authorize(&self, token: Option<&str>, username: &str) -> PetstoreResult<()> {
+        let token = match token {
+            Some(token) => token,
+            None => bail!(InvalidSession("A session of the user is required".into())),
+        };
+        if self.session_user(token)? != username {
+            bail!(Forbidden("The session belongs to another user".into()));
         }
         Ok(())
     }
 
-    pub fn update_user(&self, mut updated_user: User) -> PetstoreResult<User> {
+    /// Close the session opened with `token`, if any.
+    pub fn logout(&self, token: &str) -> PetstoreResult<()> {
+        self.sessions.try_borrow_mut()?.remove(token);
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: String) -> PetstoreResult<Option<User>> {
+        let users = self.users.try_borrow()?;
+        Ok(users
+            .values()
+            .find(|user| user.username == name && user.deleted_at.is_none())
+            .cloned())
+    }
+
+    /// Move a user to the trash and close their sessions.
+    ///
+    /// The username stays taken until the user is purged, so that it can be restored.
+    pub fn delete_user(&self, name: String, precondition: Option<Precondition>) -> PetstoreResult<()> {
+        let mut users = self.users.try_borrow_mut()?;
+        let user = match users
+            .values_mut()
+            .find(|user| user.username == name && user.deleted_at.is_none())
+        {
+            Some(user) => user,
+            None => bail!(MissingUser(format!("User {} does not exist and cannot be deleted", name))),
+        };
+        self.check_precondition(Some(user.version), precondition.as_ref())?;
+        user.version += 1;
+        user.deleted_at = Some(Utc::now());
+        self.sessions.try_borrow_mut()?.retain(|_, session| session.username != name);
+        Ok(())
+    }
+
+    /// Take a user out of the trash.
+    ///
+    /// This is not served by the API: a deleted user has no session left to authorize it.
+    pub fn restore_user(&self, name: String) -> PetstoreResult<User> {
+        let mut users = self.users.try_borrow_mut()?;
+        let user = match users
+            .values_mut()
+            .find(|user| user.username == name && user.deleted_at.is_some())
+        {
+            Some(user) => user,
+            None => bail!(MissingUser(format!("User {} is not in the trash", name))),
+        };
+        user.version += 1;
+        user.updated_at = Some(Utc::now());
+        user.deleted_at = None;
+        Ok(user.clone())
+    }
+
+    pub fn patch_user(
+        &self,
+        name: String,
+        patch: &Patch,
+        precondition: Option<Precondition>,
+    ) -> PetstoreResult<Option<User>> {
+        let mut users = self.users.try_borrow_mut()?;
+        let user = match users
+            .values_mut()
+            .find(|user| user.username == name && user.deleted_at.is_none())
+        {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        self.check_precondition(Some(user.version), precondition.as_ref())?;
+
+        let mut patched: User = apply_patch(&*user, patch)?;
+        if patched.id != user.id || patched.username != user.username {
+            bail!(InvalidPatch("The ID and username of a user cannot be changed".into()));
+        }
+        patched.version = user.version + 1;
+        patched.created_at = user.created_at;
+        patched.updated_at = Some(Utc::now());
+        patched.deleted_at = None;
+        *user = patched.clone();
+
+        Ok(Some(patched))
+    }
+
+    pub fn update_user(&self, mut updated_user: User, precondition: Option<Precondition>) -> PetstoreResult<User> {
         let mut users = self.users.try_borrow_mut()?;
         if let Some(user) = users
             .values_mut()
-            .find(|user| user.username == updated_user.username)
+            .find(|user| user.username == updated_user.username && user.deleted_at.is_none())
         {
+            self.check_precondition(Some(user.version), precondition.as_ref())?;
             updated_user.id = user.id;
+            updated_user.version = user.version + 1;
+            updated_user.created_at = user.created_at;
+            updated_user.updated_at = Some(Utc::now());
+            updated_user.deleted_at = None;
             *user = updated_user.clone();
             Ok(updated_user)
         } else {
@@ -317,3 +879,664 @@ impl Petstore {
         }
     }
 }
+
+// maintenance
+impl Petstore {
+    /// Create a store holding entities which already have their IDs and versions, e.g. generated
+    /// fixtures, without the per-entity checks of the API.
+    ///
+    /// Entities without an ID are skipped. The tags and categories of the pets are registered
+    /// in the order of the pets, as `add_pet` would.
+    pub fn from_entities(pets: Vec<Pet>, orders: Vec<Order>, users: Vec<User>) -> Self {
+        let store = Self::default();
+        {
+            let mut tags = store.tags.borrow_mut();
+            let mut categories = store.categories.borrow_mut();
+            for pet in pets.iter().filter(|pet| pet.id.is_some()) {
+                for tag in pet.tags.iter().flat_map(|tags| tags) {
+                    let id = tags.len() as u64;
+                    tags.insert(id, Tag { id: Some(id), ..tag.clone() });
+                }
+                if let Some(ref category) = pet.category {
+                    let id = categories.len() as u64;
+                    categories.insert(id, Category { id: Some(id), ..category.clone() });
+                }
+            }
+        }
+        *store.pets.borrow_mut() = pets.into_iter().filter_map(|p| p.id.map(|id| (id, p))).collect();
+        *store.orders.borrow_mut() = orders.into_iter().filter_map(|o| o.id.map(|id| (id, o))).collect();
+        *store.users.borrow_mut() = users.into_iter().filter_map(|u| u.id.map(|id| (id, u))).collect();
+        store
+    }
+
+    /// A page of the trash, in which the pets come first and then the users, each in the order
+    /// of their IDs, along with the total number of entities in the trash.
+    pub fn trash(&self, offset: usize, limit: Option<usize>) -> PetstoreResult<(usize, Trash)> {
+        if let (Some(limit), Some(max)) = (limit, self.max_list_length) {
+            if limit > max {
+                bail!(TooManyItems(format!("At most {} entities of the trash can be listed at once", max)));
+            }
+        }
+        let mut pets: Vec<Pet> = self.pets
+            .try_borrow()?
+            .values()
+            .filter(|pet| pet.deleted_at.is_some())
+            .cloned()
+            .collect();
+        pets.sort_by_key(|pet| pet.id);
+        let mut users: Vec<User> = self.users
+            .try_borrow()?
+            .values()
+            .filter(|user| user.deleted_at.is_some())
+            .cloned()
+            .collect();
+        users.sort_by_key(|user| user.id);
+
+        let total = pets.len() + users.len();
+        let limit = limit.or(self.max_list_length).unwrap_or(total);
+        let skipped_users = offset.saturating_sub(pets.len());
+        let pets: Vec<Pet> = pets.into_iter().skip(offset).take(limit).collect();
+        let users = users
+            .into_iter()
+            .skip(skipped_users)
+            .take(limit - pets.len())
+            .map(Into::into)
+            .collect();
+        Ok((total, Trash { pets, users }))
+    }
+
+    /// Remove for good the pets and users deleted before `deadline`, and return how many were
+    /// removed.
+    ///
+    /// The photos and the orders of the pets go with them, as they can no longer be restored.
+    pub fn purge_trash(&self, deadline: DateTime<Utc>) -> PetstoreResult<usize> {
+        let mut pets = self.pets.try_borrow_mut()?;
+        let mut photos = self.photos.try_borrow_mut()?;
+        let mut orders = self.orders.try_borrow_mut()?;
+        let mut users = self.users.try_borrow_mut()?;
+        let expired = |deleted_at: Option<DateTime<Utc>>| deleted_at.map_or(false, |at| at < deadline);
+
+        let purged_pets: HashSet<u64> = pets.iter()
+            .filter(|&(_, pet)| expired(pet.deleted_at))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &purged_pets {
+            pets.remove(id);
+            photos.remove(id);
+        }
+        orders.retain(|_, order| order.pet_id.map_or(true, |pet_id| !purged_pets.contains(&pet_id)));
+        let before = users.len();
+        users.retain(|_, user| !expired(user.deleted_at));
+        Ok(purged_pets.len() + before - users.len())
+    }
+
+    /// Look for the inconsistencies which the API does not prevent, e.g. orders of missing pets.
+    ///
+    /// The orders of the pets in the trash are reported too, as they cannot be fulfilled unless the
+    /// pet is restored, and are purged along with it otherwise.
+    pub fn check_consistency(&self) -> PetstoreResult<Vec<String>> {
+        let pets = self.pets.try_borrow()?;
+        let orders = self.orders.try_borrow()?;
+        let users = self.users.try_borrow()?;
+        let mut problems = vec![];
+
+        for (id, pet) in &*pets {
+            if pet.id != Some(*id) {
+                problems.push(format!("pet {} is stored with the ID {:?}", id, pet.id));
+            }
+        }
+        for (id, order) in &*orders {
+            if order.id != Some(*id) {
+                problems.push(format!("order {} is stored with the ID {:?}", id, order.id));
+            }
+            match order.pet_id.map(|pet_id| (pet_id, pets.get(&pet_id))) {
+                Some((pet_id, None)) => problems.push(format!("order {} refers to the missing pet {}", id, pet_id)),
+                Some((pet_id, Some(pet))) if pet.deleted_at.is_some() => {
+                    problems.push(format!("order {} refers to the deleted pet {}", id, pet_id))
+                }
+                None => problems.push(format!("order {} refers to no pet", id)),
+                _ => {}
+            }
+        }
+        let mut usernames = HashMap::new();
+        for (id, user) in &*users {
+            if user.id != Some(*id) {
+                problems.push(format!("user {} is stored with the ID {:?}", id, user.id));
+            }
+            if let Some(other) = usernames.insert(&user.username, id) {
+                problems.push(format!(
+                    "users {} and {} share the username {}",
+                    other, id, user.username
+                ));
+            }
+        }
+        problems.sort();
+        Ok(problems)
+    }
+}
+
+/// The pets to be exported, each of which is read from the store only when it is reached.
+///
+/// Pets removed in the meantime are skipped.
+#[derive(Debug)]
+pub struct PetExport {
+    store: Petstore,
+    ids: vec::IntoIter<u64>,
+}
+
+impl Iterator for PetExport {
+    type Item = Pet;
+
+    fn next(&mut self) -> Option<Pet> {
+        while let Some(id) = self.ids.next() {
+            if let Ok(Some(pet)) = self.store.get_pet(id) {
+                return Some(pet);
+            }
+        }
+        None
+    }
+}
+
+/// The persistent form of a store, in which entities are paired with their versions.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    pets: Vec<(u64, Pet)>,
+    tags: Vec<Tag>,
+    categories: Vec<Category>,
+    #[serde(deserialize_with = "deserialize_orders")]
+    orders: Vec<(u64, Order)>,
+    photos: Vec<(u64, Vec<u8>)>,
+    users: Vec<(u64, User)>,
+}
+
+/// Read the orders of a snapshot, dropping the shipping dates which are not RFC 3339, as they
+/// were accepted as is before.
+fn deserialize_orders<'de, D>(deserializer: D) -> Result<Vec<(u64, Order)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let orders: Vec<(u64, Value)> = Deserialize::deserialize(deserializer)?;
+    orders
+        .into_iter()
+        .map(|(version, mut order)| {
+            if let Err(e) = serde_json::from_value::<Order>(order.clone()) {
+                warn!("dropping the shipping date of an order in the snapshot: {}", e);
+                order["ship_date"] = Value::Null;
+            }
+            serde_json::from_value(order)
+                .map(|order| (version, order))
+                .map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+fn with_versions<T, F, G>(entities: Vec<(u64, T)>, id: F, mut set_version: G) -> HashMap<u64, T>
+where
+    F: Fn(&T) -> Option<u64>,
+    G: FnMut(&mut T, u64),
+{
+    entities
+        .into_iter()
+        .filter_map(|(version, mut entity)| {
+            set_version(&mut entity, version);
+            id(&entity).map(|id| (id, entity))
+        })
+        .collect()
+}
+
+/// Apply `patch` to the JSON representation of `entity` and read the result back.
+fn apply_patch<T>(entity: &T, patch: &Patch) -> PetstoreResult<T>
+where
+    T: ::serde::Serialize + ::serde::de::DeserializeOwned,
+{
+    let mut value = serde_json::to_value(entity).chain_err(|| "failed to serialize the entity")?;
+    patch
+        .apply(&mut value)
+        .map_err(|e| InvalidPatch(e.to_string()))?;
+    serde_json::from_value(value).map_err(|e| InvalidPatch(e.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pet(name: &str) -> Pet {
+        Pet {
+            id: None,
+            name: name.into(),
+            photo_urls: vec![],
+            category: None,
+            tags: None,
+            status: Some(Status::Available),
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        }
+    }
+
+    fn user(name: &str) -> User {
+        User {
+            id: None,
+            username: name.into(),
+            first_name: None,
+            last_name: None,
+            email: None,
+            password: "secret".into(),
+            phone: None,
+            created_at: None,
+            updated_at: None,
+            deleted_at: None,
+            version: 0,
+        }
+    }
+
+    fn order(pet_id: u64, status: Option<OrderStatus>) -> Order {
+        Order {
+            id: None,
+            pet_id: Some(pet_id),
+            quantity: Some(1),
+            ship_date: None,
+            status,
+            complete: Some(false),
+            username: None,
+            created_at: None,
+            updated_at: None,
+            version: 0,
+        }
+    }
+
+    fn at(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        use chrono::TimeZone;
+        Utc.ymd(year, month, day).and_hms(0, 0, 0)
+    }
+
+    fn series(report: Vec<AdoptionCount>) -> Vec<(String, u64)> {
+        report
+            .into_iter()
+            .map(|count| (count.period.to_string(), count.adoptions))
+            .collect()
+    }
+
+    #[test]
+    fn test_report_adoptions() {
+        let store = Petstore::new();
+        let shipped = vec![
+            (at(2017, 12, 30), Delivered),
+            (at(2018, 1, 2), Delivered),
+            (at(2018, 1, 2) + Duration::hours(23), Delivered),
+            (at(2018, 1, 3), OrderStatus::Placed),
+            (at(2018, 1, 15), Delivered),
+            (at(2018, 2, 1), Delivered),
+        ];
+        for (ship_date, status) in shipped {
+            store
+                .add_order(Order {
+                    ship_date: Some(ship_date),
+                    ..order(0, Some(status))
+                })
+                .unwrap();
+        }
+        let window = |from, to| Window {
+            from: Some(from),
+            to: Some(to),
+        };
+
+        // The days without adoptions are filled in, and `to` is excluded.
+        let days = store
+            .report_adoptions(&window(at(2017, 12, 30), at(2018, 1, 3)), Granularity::Day)
+            .unwrap();
+        let expected = vec![("2017-12-30", 1), ("2017-12-31", 0), ("2018-01-01", 0), ("2018-01-02", 2)];
+        assert_eq!(series(days), expected.into_iter().map(|(d, n)| (d.to_owned(), n)).collect::<Vec<_>>());
+
+        // Weeks start on Monday, across the end of the year.
+        let weeks = store
+            .report_adoptions(&window(at(2017, 12, 25), at(2018, 2, 1)), Granularity::Week)
+            .unwrap();
+        let expected = vec![
+            ("2017-12-25", 1),
+            ("2018-01-01", 2),
+            ("2018-01-08", 0),
+            ("2018-01-15", 1),
+            ("2018-01-22", 0),
+            ("2018-01-29", 0),
+        ];
+        assert_eq!(series(weeks), expected.into_iter().map(|(d, n)| (d.to_owned(), n)).collect::<Vec<_>>());
+
+        // An unbounded window runs from the first to the last adoption.
+        let months = store.report_adoptions(&Window::default(), Granularity::Month).unwrap();
+        let expected = vec![("2017-12-01", 1), ("2018-01-01", 3), ("2018-02-01", 1)];
+        assert_eq!(series(months), expected.into_iter().map(|(d, n)| (d.to_owned(), n)).collect::<Vec<_>>());
+
+        let empty = store
+            .report_adoptions(&window(at(2017, 1, 1), at(2017, 1, 1)), Granularity::Day)
+            .unwrap();
+        assert!(empty.is_empty());
+        let store = store.max_list_length(3);
+        assert!(store.report_adoptions(&window(at(2018, 1, 1), at(2018, 1, 5)), Granularity::Day).is_err());
+    }
+
+    #[test]
+    fn test_get_inventory_by() {
+        let tags = |names: Vec<&str>| {
+            let tags: Vec<Tag> = names.into_iter().map(|name| Tag { id: None, name: name.into() }).collect();
+            Some(tags)
+        };
+        let category = |name: &str| Some(Category { id: None, name: name.into() });
+        let pets = vec![
+            (category("dogs"), tags(vec!["cute", "small"]), Status::Available, at(2018, 1, 1), None),
+            (category("dogs"), tags(vec!["cute"]), Status::Pending, at(2018, 1, 2), None),
+            (None, None, Status::Adopted, at(2018, 1, 1), None),
+            (category("cats"), tags(vec!["cute"]), Status::Available, at(2018, 1, 3), None),
+            (category("dogs"), tags(vec!["cute"]), Status::Adopted, at(2018, 1, 1), Some(at(2018, 1, 2))),
+        ];
+        let pets = pets.into_iter()
+            .zip(0..)
+            .map(|((category, tags, status, created_at, deleted_at), id)| Pet {
+                id: Some(id),
+                category,
+                tags,
+                status: Some(status),
+                created_at: Some(created_at),
+                deleted_at,
+                ..pet("pet")
+            })
+            .collect();
+        let store = Petstore::from_entities(pets, vec![], vec![]);
+        let inventory = |available, pending, adopted| Inventory {
+            available,
+            pending,
+            adopted,
+        };
+
+        let by_category = store
+            .get_inventory_by(InventoryGrouping::Category, &Window::default())
+            .unwrap();
+        let expected = vec![("cats", inventory(1, 0, 0)), ("dogs", inventory(1, 1, 0))];
+        assert_eq!(
+            by_category.into_iter().collect::<Vec<_>>(),
+            expected.into_iter().map(|(g, i)| (g.to_owned(), i)).collect::<Vec<_>>()
+        );
+
+        let window = Window {
+            from: Some(at(2018, 1, 1)),
+            to: Some(at(2018, 1, 3)),
+        };
+        let by_tag = store.get_inventory_by(InventoryGrouping::Tag, &window).unwrap();
+        let expected = vec![("cute", inventory(1, 1, 0)), ("small", inventory(1, 0, 0))];
+        assert_eq!(
+            by_tag.into_iter().collect::<Vec<_>>(),
+            expected.into_iter().map(|(g, i)| (g.to_owned(), i)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_add_order() {
+        let store = Petstore::new();
+        // The status is chosen by the client, only the ID is assigned by the store.
+        let id = store.add_order(order(0, Some(OrderStatus::Placed))).unwrap();
+        let added = store.find_order(id).unwrap().unwrap();
+        assert_eq!((added.id, added.status), (Some(id), Some(OrderStatus::Placed)));
+
+        let mut with_id = order(0, None);
+        with_id.id = Some(42);
+        assert!(store.add_order(with_id).is_err());
+        assert_eq!(store.find_order(42).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_orders() {
+        use model::OrderSort::*;
+
+        let store = Petstore::new();
+        let orders = vec![
+            (1, Some(at(2018, 1, 3)), OrderStatus::Placed, false),
+            (2, Some(at(2018, 1, 1)), Delivered, true),
+            (1, None, OrderStatus::Approved, false),
+            (3, Some(at(2018, 1, 2)), Delivered, true),
+        ];
+        for (pet_id, ship_date, status, complete) in orders {
+            store
+                .add_order(Order {
+                    ship_date,
+                    complete: Some(complete),
+                    ..order(pet_id, Some(status))
+                })
+                .unwrap();
+        }
+        let find = |query: OrderQuery| {
+            let (total, orders) = store.find_orders(&query).unwrap();
+            (total, orders.into_iter().map(|order| order.id.unwrap()).collect::<Vec<_>>())
+        };
+
+        let by_pet = OrderQuery {
+            pet_id: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(find(by_pet), (2, vec![0, 2]));
+        let complete = OrderQuery {
+            complete: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(find(complete), (2, vec![1, 3]));
+        // Orders without a shipping date are not in a bounded window, whose end is excluded.
+        let shipped = OrderQuery {
+            ship_date: Window {
+                from: Some(at(2018, 1, 1)),
+                to: Some(at(2018, 1, 3)),
+            },
+            ..Default::default()
+        };
+        assert_eq!(find(shipped), (2, vec![1, 3]));
+
+        let sorted = |sort| OrderQuery {
+            sort,
+            ..Default::default()
+        };
+        assert_eq!(find(sorted(ShipDate)), (4, vec![2, 1, 3, 0]));
+        assert_eq!(find(sorted(ShipDateDesc)), (4, vec![0, 3, 1, 2]));
+        assert_eq!(find(sorted(IdDesc)), (4, vec![3, 2, 1, 0]));
+        let page = OrderQuery {
+            offset: 1,
+            limit: Some(2),
+            ..sorted(IdDesc)
+        };
+        assert_eq!(find(page), (4, vec![2, 1]));
+
+        let limited = store.clone().max_list_length(3);
+        let (total, orders) = limited.find_orders(&OrderQuery::default()).unwrap();
+        assert_eq!((total, orders.len()), (4, 3));
+        let too_many = OrderQuery {
+            limit: Some(4),
+            ..Default::default()
+        };
+        assert!(limited.find_orders(&too_many).is_err());
+    }
+
+    #[test]
+    fn test_find_by_timestamps() {
+        let dated = |name: &str, id: u64, created_at: Option<DateTime<Utc>>| Pet {
+            id: Some(id),
+            created_at,
+            updated_at: created_at,
+            ..pet(name)
+        };
+        let pets = vec![
+            dated("a", 0, Some(at(2018, 1, 2))),
+            dated("b", 1, None),
+            dated("c", 2, Some(at(2018, 1, 1))),
+            dated("d", 3, Some(at(2018, 1, 3))),
+        ];
+        let users = vec![
+            User {
+                id: Some(0),
+                created_at: Some(at(2018, 1, 2)),
+                ..user("alice")
+            },
+            User {
+                id: Some(1),
+                created_at: Some(at(2018, 1, 1)),
+                ..user("bob")
+            },
+        ];
+        let store = Petstore::from_entities(pets, vec![], users);
+        let names = |pets: Vec<Pet>| pets.into_iter().map(|pet| pet.name).collect::<Vec<_>>();
+        let sorted = |sort| TimestampQuery {
+            sort,
+            ..Default::default()
+        };
+
+        let pets = store
+            .get_pets_by_status(vec![Status::Available], &sorted(TimestampSort::CreatedAt))
+            .unwrap();
+        assert_eq!(names(pets), vec!["b", "c", "a", "d"]);
+        let pets = store
+            .find_pets_by_tag(vec![], &sorted(TimestampSort::UpdatedAtDesc))
+            .unwrap();
+        assert_eq!(names(pets), vec!["d", "a", "c", "b"]);
+        let window = TimestampQuery {
+            created_at: Window {
+                from: Some(at(2018, 1, 2)),
+                to: Some(at(2018, 1, 3)),
+            },
+            ..sorted(TimestampSort::IdDesc)
+        };
+        assert_eq!(names(store.get_pets_by_status(vec![Status::Available], &window).unwrap()), vec!["a"]);
+
+        let (total, users) = store.find_users(&sorted(TimestampSort::CreatedAt), 0, None).unwrap();
+        let usernames: Vec<_> = users.into_iter().map(|user| user.username).collect();
+        assert_eq!((total, usernames), (2, vec!["bob".to_owned(), "alice".to_owned()]));
+        let (total, users) = store.find_users(&window, 0, None).unwrap();
+        assert_eq!((total, users[0].username.as_str()), (1, "alice"));
+    }
+
+    #[test]
+    fn test_deserialize_orders() {
+        let orders = json!([
+            [1, {"id": 0, "ship_date": "2018-01-01T00:00:00Z"}],
+            [2, {"id": 1, "ship_date": "next tuesday", "status": "placed"}],
+        ]);
+        let orders = deserialize_orders(orders).unwrap();
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[0].1.ship_date, Some(at(2018, 1, 1)));
+        // An invalid shipping date is dropped rather than the whole order.
+        assert_eq!((orders[1].0, orders[1].1.id), (2, Some(1)));
+        assert_eq!(orders[1].1.ship_date, None);
+        assert_eq!(orders[1].1.status, Some(OrderStatus::Placed));
+
+        let broken = json!([[1, {"id": "zero"}]]);
+        assert!(deserialize_orders(broken).is_err());
+    }
+
+    #[test]
+    fn test_trash() {
+        let store = Petstore::new();
+        store.add_users(vec![user("alice"), user("bob")]).unwrap();
+        store.add_pet(pet("doggie")).unwrap();
+        store.delete_user("alice".into(), None).unwrap();
+        store.delete_user("bob".into(), None).unwrap();
+        store.delete_pet(0, None).unwrap();
+
+        let usernames = |trash: &Trash| -> Vec<String> { trash.users.iter().map(|u| u.username.clone()).collect() };
+        let limited = store.clone().max_list_length(1);
+        let (total, trash) = limited.trash(0, None).unwrap();
+        assert_eq!((total, trash.pets.len(), usernames(&trash)), (3, 1, vec![]));
+        let json = serde_json::to_value(&limited.trash(1, None).unwrap().1).unwrap();
+        assert!(json["users"][0].get("password").is_none());
+
+        // The pages run from the pets to the users.
+        let store = store.max_list_length(2);
+        let (total, trash) = store.trash(0, None).unwrap();
+        assert_eq!((total, trash.pets.len(), usernames(&trash)), (3, 1, vec!["alice".to_owned()]));
+        let (_, trash) = store.trash(1, Some(2)).unwrap();
+        assert_eq!((trash.pets.len(), usernames(&trash)), (0, vec!["alice".to_owned(), "bob".to_owned()]));
+        let (_, trash) = store.trash(2, Some(2)).unwrap();
+        assert_eq!((trash.pets.len(), usernames(&trash)), (0, vec!["bob".to_owned()]));
+        assert!(store.trash(0, Some(3)).is_err());
+    }
+
+    #[test]
+    fn test_purge_trash_with_orders() {
+        let store = Petstore::new();
+        store.add_pet(pet("doggie")).unwrap();
+        store.add_pet(pet("kitty")).unwrap();
+        store.add_order(order(0, None)).unwrap();
+        store.add_order(order(1, None)).unwrap();
+        store.delete_pet(0, None).unwrap();
+        assert_eq!(
+            store.check_consistency().unwrap(),
+            vec!["order 0 refers to the deleted pet 0".to_owned()]
+        );
+
+        // The orders are purged along with their pet.
+        assert_eq!(store.purge_trash(Utc::now() + Duration::seconds(1)).unwrap(), 1);
+        let orders: Vec<_> = store.list_orders().unwrap().into_iter().map(|order| order.id).collect();
+        assert_eq!(orders, vec![Some(1)]);
+        assert_eq!(store.check_consistency().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_authorize() {
+        let store = Petstore::new();
+        store.add_users(vec![user("alice"), user("bob")]).unwrap();
+        let session = store.login("alice".into(), "secret".into()).unwrap();
+        let kind = |result: PetstoreResult<()>| result.unwrap_err().kind().to_string();
+
+        assert!(store.authorize(Some(&session.token[..]), "alice").is_ok());
+        assert!(kind(store.authorize(Some(&session.token[..]), "bob")).starts_with("forbidden"));
+        assert!(kind(store.authorize(None, "alice")).starts_with("invalid session"));
+        store.logout(&session.token).unwrap();
+        assert!(kind(store.authorize(Some(&session.token[..]), "alice")).starts_with("invalid session"));
+    }
+
+    #[test]
+    fn test_patch_pet_adds_tags_and_category() {
+        let store = Petstore::new();
+        let id = store.add_pet(pet("doggie")).unwrap();
+        let patch = Patch::Merge(json!({"tags": [{"name": "cute"}], "category": {"name": "dogs"}}));
+        store.patch_pet(id, &patch, None).unwrap();
+
+        let tags: Vec<_> = store.tags.borrow().values().map(|tag| tag.name.clone()).collect();
+        assert_eq!(tags, vec!["cute"]);
+        let categories: Vec<_> = store.categories.borrow().values().map(|c| c.name.clone()).collect();
+        assert_eq!(categories, vec!["dogs"]);
+
+        // Tags the pet already had are not registered again.
+        store.patch_pet(id, &Patch::Merge(json!({"name": "Rex"})), None).unwrap();
+        assert_eq!(store.tags.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        use bulk::{self, BulkFormat, RowParser};
+
+        let store = Petstore::new();
+        let mut doggie = pet("doggie");
+        doggie.tags = Some(vec![Tag { id: None, name: "cute".into() }]);
+        doggie.category = Some(Category { id: None, name: "dogs".into() });
+        store.add_pet(doggie).unwrap();
+        store.add_pet(pet("kitty")).unwrap();
+
+        for &format in &[BulkFormat::Ndjson, BulkFormat::Csv] {
+            let mut data = bulk::header(format).unwrap_or_default();
+            for pet in store.export_pets().unwrap() {
+                data.extend(bulk::encode_row(format, &pet));
+            }
+            let mut parser = RowParser::new(format);
+            let mut rows = parser.push(&data);
+            rows.extend(parser.finish());
+
+            let imported = Petstore::new();
+            let results = imported.import_pets(rows);
+            assert!(results.iter().all(|r| r.error.is_none()), "{:?}: {:?}", format, results);
+            let names = |store: &Petstore| -> Vec<(String, Vec<String>)> {
+                store
+                    .export_pets()
+                    .unwrap()
+                    .map(|pet| (pet.name, pet.tags.unwrap_or_default().into_iter().map(|t| t.name).collect()))
+                    .collect()
+            };
+            assert_eq!(names(&imported), names(&store));
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Assembly and lifecycle of the HTTP server.
+
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{self, Utc};
+use finchers::responder::DefaultResponder;
+use finchers::service::FinchersService;
+use futures::{future, Async, Future, Poll, Stream};
+use futures::future::Shared;
+use futures::sync::oneshot;
+use futures::task::{self, Task};
+use hyper::{self, Request, Response};
+use hyper::server::{Connection, Http, Service};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use api;
+use context;
+use fixtures;
+use config::{Config, StoreBackend, StoreConfig};
+use middleware::{AccessLog, AllowedMethods, ApiKeyAuth, BodyLimit, BulkImport, Compression, Cors, Probes, RateLimit,
+                 RequestMetrics};
+use petstore::{Petstore, PetstoreResult};
+use tls::TlsAcceptor;
+
+/// Seconds between two purges of the expired entries of the trash.
+const PURGE_INTERVAL: u64 = 60;
+
+/// Open the store selected by the configuration.
+pub fn open_store(config: &StoreConfig) -> PetstoreResult<Petstore> {
+    let mut store = match config.backend {
+        StoreBackend::Memory => Petstore::new(),
+        StoreBackend::Snapshot => Petstore::load_snapshot(config.dsn.as_ref().expect("validated on load"))?,
+    };
+    if let Some(seed) = config.seed {
+        if store.is_empty()? {
+            info!("seeding the store with {} pets (seed = {})", config.seed_count, seed);
+            store = fixtures::build(fixtures::generate(seed, config.seed_count));
+        }
+    }
+    Ok(store.require_precondition(config.require_if_match))
+}
+
+/// Write everything which is not persisted yet, if the store is backed by a file.
+pub fn flush_store(config: &StoreConfig, store: &Petstore) -> PetstoreResult<()> {
+    match config.backend {
+        StoreBackend::Memory => Ok(()),
+        StoreBackend::Snapshot => store.save_snapshot(config.dsn.as_ref().expect("validated on load")),
+    }
+}
+
+/// Create the service which answers the requests to `store`.
+pub fn service(
+    config: &Config,
+    store: Petstore,
+    handle: &Handle,
+) -> io::Result<impl Service<Request = Request, Response = Response, Error = hyper::Error> + Clone + 'static> {
+    let handler = store.clone().max_list_length(config.limits.max_list_length);
+    let service = FinchersService::new(api::endpoint(), handler, DefaultResponder::default());
+    let service = BulkImport::new(
+        service,
+        store.clone(),
+        config.limits.max_bulk_body_size,
+        config.limits.max_body_size,
+    );
+    let service = BodyLimit::new(service, &config.limits);
+    let service = AllowedMethods::new(service);
+    let service = RateLimit::new(service, &config.rate_limit, store.clone(), handle)?;
+    let service = ApiKeyAuth::new(service, config.auth.api_keys.clone());
+    let service = Probes::new(service, store.clone(), config.store.clone());
+    let service = Cors::new(service, &config.cors);
+    let service = Compression::new(service, &config.compression, config.limits.max_body_size);
+    let service = RequestMetrics::new(service, store);
+    Ok(AccessLog::new(service))
+}
+
+/// Requests a running `Server` to shut down. It can be sent to and used from any thread.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle(Arc<Mutex<Option<oneshot::Sender<()>>>>);
+
+impl ShutdownHandle {
+    /// Stop accepting connections and let the server drain the in-flight requests.
+    ///
+    /// Calling this more than once has no further effect.
+    pub fn shutdown(&self) {
+        if let Some(tx) = self.0.lock().unwrap().take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// A server whose listeners are bound, but which does not accept connections yet.
+pub struct Server {
+    config: Config,
+    store: Petstore,
+    handle: Handle,
+    listeners: Vec<TcpListener>,
+    tls: Option<TlsAcceptor>,
+    shutdown_handle: ShutdownHandle,
+    shutdown: Shared<oneshot::Receiver<()>>,
+}
+
+impl Server {
+    pub fn bind(config: Config, store: Petstore, handle: &Handle) -> io::Result<Server> {
+        let listeners = config
+            .server
+            .listen
+            .iter()
+            .map(|addr| TcpListener::bind(addr, handle))
+            .collect::<io::Result<_>>()?;
+        let tls = if config.tls.is_enabled() {
+            Some(TlsAcceptor::new(&config.tls)?)
+        } else {
+            None
+        };
+        let (tx, rx) = oneshot::channel();
+        api::common::set_handle(handle);
+
+        Ok(Server {
+            config,
+            store,
+            handle: handle.clone(),
+            listeners,
+            tls,
+            shutdown_handle: ShutdownHandle(Arc::new(Mutex::new(Some(tx)))),
+            shutdown: rx.shared(),
+        })
+    }
+
+    /// The addresses actually bound, which differ from the configured ones for port 0.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(|l| l.local_addr()).collect()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+
+    /// The acceptor of TLS connections, through which the certificates are reloaded.
+    pub fn tls_acceptor(&self) -> Option<TlsAcceptor> {
+        self.tls.clone()
+    }
+
+    /// Serve until a shutdown is requested.
+    ///
+    /// The returned future resolves once the in-flight requests are drained (or the
+    /// shutdown timeout elapsed) and the store is flushed.
+    pub fn run(self) -> Box<Future<Item = (), Error = io::Error>> {
+        let Server {
+            config,
+            store,
+            handle,
+            listeners,
+            tls,
+            shutdown_handle,
+            shutdown,
+        } = self;
+        let tracker = Rc::new(Tracker::default());
+        // Fired once the shutdown timeout elapses, to close the connections still open.
+        let (abort_tx, abort_rx) = oneshot::channel();
+        let abort = abort_rx.shared();
+        let service = match service(&config, store.clone(), &handle) {
+            Ok(service) => service,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let mut http = Http::new();
+        http.pipeline(config.server.pipeline);
+
+        let accepting = listeners
+            .into_iter()
+            .map(|listener| {
+                let (http, service, handle) = (http.clone(), service.clone(), handle.clone());
+                let (tls, tracker, shutdown, abort) = (tls.clone(), tracker.clone(), shutdown.clone(), abort.clone());
+                listener.incoming().for_each(move |(socket, addr)| {
+                    let guard = Guard::new(&tracker);
+                    let (http, service) = (http.clone(), service.clone());
+                    let (shutdown, abort) = (shutdown.clone(), abort.clone());
+                    match tls {
+                        Some(ref tls) => handle.spawn(
+                            tls.accept(socket)
+                                .map_err(move |e| debug!("TLS handshake with {} failed: {}", addr, e))
+                                .and_then(move |socket| {
+                                    Draining {
+                                        conn: http.serve_connection(socket, service),
+                                        addr,
+                                        shutdown: Some(shutdown),
+                                        abort,
+                                        _guard: guard,
+                                    }.map_err(|e| debug!("connection error: {}", e))
+                                }),
+                        ),
+                        None => handle.spawn(
+                            Draining {
+                                conn: http.serve_connection(socket, service),
+                                addr,
+                                shutdown: Some(shutdown),
+                                abort,
+                                _guard: guard,
+                            }.map_err(|e| debug!("connection error: {}", e)),
+                        ),
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut serving: Box<Future<Item = (), Error = io::Error>> =
+            Box::new(future::join_all(accepting).map(|_| ()));
+
+        if config.store.backend == StoreBackend::Snapshot {
+            let (store_config, store) = (config.store.clone(), store.clone());
+            let snapshots = match Interval::new(Duration::from_secs(config.store.snapshot_interval), &handle) {
+                Ok(interval) => interval,
+                Err(e) => return Box::new(future::err(e)),
+            };
+            let snapshots = snapshots.for_each(move |_| {
+                if let Err(e) = flush_store(&store_config, &store) {
+                    error!("failed to save a snapshot: {}", e);
+                }
+                Ok(())
+            });
+            serving = Box::new(serving.join(snapshots).map(|_| ()));
+        }
+
+        {
+            let (retention, store) = (Duration::from_secs(config.store.trash_retention), store.clone());
+            let purges = match Interval::new(Duration::from_secs(PURGE_INTERVAL), &handle) {
+                Ok(interval) => interval,
+                Err(e) => return Box::new(future::err(e)),
+            };
+            let purges = purges.for_each(move |_| {
+                // Nothing can have been deleted before a deadline which is out of range.
+                let deadline = chrono::Duration::from_std(retention)
+                    .ok()
+                    .and_then(|retention| Utc::now().checked_sub_signed(retention));
+                match deadline.map(|deadline| store.purge_trash(deadline)) {
+                    Some(Ok(0)) | None => {}
+                    Some(Ok(purged)) => info!("purged {} entities from the trash", purged),
+                    Some(Err(e)) => error!("failed to purge the trash: {}", e),
+                }
+                Ok(())
+            });
+            serving = Box::new(serving.join(purges).map(|_| ()));
+        }
+
+        let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout);
+        Box::new(
+            serving
+                // The future keeps a handle, so that the shutdown is only ever requested through one
+                // rather than by dropping the last of them.
+                .select(shutdown.then(move |_| {
+                    drop(shutdown_handle);
+                    Ok(())
+                }))
+                .map_err(|(e, _)| e)
+                .and_then(move |_| {
+                    info!("Shutting down, draining {} connection(s)...", tracker.active.get());
+                    let timeout = Timeout::new(shutdown_timeout, &handle)?.map(move |_| {
+                        warn!("shutdown timeout elapsed, dropping the remaining connections");
+                        let _ = abort_tx.send(());
+                    });
+                    Ok(WaitIdle(tracker).select(timeout).map_err(|(e, _)| e))
+                })
+                .flatten()
+                .and_then(move |_| {
+                    flush_store(&config.store, &store).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+                }),
+        )
+    }
+}
+
+/// Counts the open connections.
+#[derive(Debug, Default)]
+struct Tracker {
+    active: Cell<usize>,
+    waiter: RefCell<Option<Task>>,
+}
+
+/// Marks a connection as open until dropped.
+#[derive(Debug)]
+struct Guard(Rc<Tracker>);
+
+impl Guard {
+    fn new(tracker: &Rc<Tracker>) -> Self {
+        tracker.active.set(tracker.active.get() + 1);
+        Guard(tracker.clone())
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.active.set(self.0.active.get() - 1);
+        if let Some(task) = self.0.waiter.borrow_mut().take() {
+            task.notify();
+        }
+    }
+}
+
+/// Resolves once all connections are closed.
+#[derive(Debug)]
+struct WaitIdle(Rc<Tracker>);
+
+impl Future for WaitIdle {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        if self.0.active.get() == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            *self.0.waiter.borrow_mut() = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// A connection which stops keeping itself alive once the server shuts down,
+/// so that it is closed after its in-flight request, and which is dropped outright
+/// once the shutdown timeout elapses.
+///
+/// The address of the client is made available to the service while it is polled.
+struct Draining<I, S> {
+    conn: Connection<I, S>,
+    addr: SocketAddr,
+    shutdown: Option<Shared<oneshot::Receiver<()>>>,
+    abort: Shared<oneshot::Receiver<()>>,
+    _guard: Guard,
+}
+
+impl<I, S> Future for Draining<I, S>
+where
+    I: AsyncRead + AsyncWrite + 'static,
+    S: Service<Request = Request, Response = Response, Error = hyper::Error> + 'static,
+{
+    type Item = ();
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<(), hyper::Error> {
+        // The sender is only dropped without firing once the server is gone.
+        match self.abort.poll() {
+            Ok(Async::NotReady) => {}
+            _ => return Ok(Async::Ready(())),
+        }
+        let shutting_down = match self.shutdown {
+            Some(ref mut shutdown) => match shutdown.poll() {
+                Ok(Async::NotReady) => false,
+                _ => true,
+            },
+            None => false,
+        };
+        if shutting_down {
+            self.shutdown = None;
+            self.conn.disable_keep_alive();
+        }
+        let conn = &mut self.conn;
+        context::peer_scope(self.addr, || conn.poll())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Client, StatusCode};
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn test_graceful_shutdown() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mut config = Config::default();
+        config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+
+        let server = Server::bind(config, Petstore::new(), &handle).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        let shutdown = server.shutdown_handle();
+        let (done_tx, done_rx) = oneshot::channel();
+        handle.spawn(server.run().then(|result| done_tx.send(result.is_ok()).map_err(|_| ())));
+
+        let uri = format!("http://{}/store/inventory", addr).parse().unwrap();
+        let response = core.run(Client::new(&handle).get(uri)).unwrap();
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        shutdown.shutdown();
+        assert_eq!(core.run(done_rx), Ok(true));
+    }
+
+    #[test]
+    fn test_serves_without_shutdown_handle() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mut config = Config::default();
+        config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+
+        let server = Server::bind(config, Petstore::new(), &handle).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        handle.spawn(server.run().map_err(|e| panic!("{}", e)));
+
+        let client = Client::new(&handle);
+        for _ in 0..3 {
+            let uri = format!("http://{}/store/inventory", addr).parse().unwrap();
+            let response = core.run(client.get(uri)).unwrap();
+            assert_eq!(response.status(), StatusCode::Ok);
+        }
+    }
+
+    #[test]
+    fn test_shutdown_timeout_drops_connections() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mut config = Config::default();
+        config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+        config.server.shutdown_timeout = 0;
+
+        let server = Server::bind(config, Petstore::new(), &handle).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        let shutdown = server.shutdown_handle();
+        let (done_tx, done_rx) = oneshot::channel();
+        handle.spawn(server.run().then(|result| done_tx.send(result.is_ok()).map_err(|_| ())));
+
+        // The request is never completed, so the connection cannot be drained.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /store/inventory HTTP/1.1\r\nHost: localhost\r\n").unwrap();
+        for _ in 0..5 {
+            core.turn(Some(Duration::from_millis(20)));
+        }
+
+        shutdown.shutdown();
+        assert_eq!(core.run(done_rx), Ok(true));
+        for _ in 0..5 {
+            core.turn(Some(Duration::from_millis(20)));
+        }
+        stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,463 @@
+//! Runs the Petstore operations over HTTP against a server on an ephemeral port, and checks the
+//! status codes, headers and bodies of the responses against `spec/swagger.json`.
+
+extern crate futures;
+extern crate hyper;
+extern crate petstore;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio_core;
+
+use futures::{Future, Stream};
+use hyper::{Client, Headers, Method, Request, StatusCode};
+use hyper::client::HttpConnector;
+use hyper::header::ContentType;
+use serde_json::Value;
+use tokio_core::reactor::Core;
+use petstore::Petstore;
+use petstore::api::routes;
+use petstore::config::Config;
+use petstore::server::Server;
+
+const SPEC: &'static str = include_str!("../spec/swagger.json");
+
+struct Harness {
+    core: Core,
+    client: Client<HttpConnector>,
+    base: String,
+    spec: Value,
+}
+
+struct Exchange {
+    status: StatusCode,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl Exchange {
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+impl Harness {
+    fn start() -> Harness {
+        let core = Core::new().unwrap();
+        let mut config = Config::default();
+        config.server.listen = vec!["127.0.0.1:0".parse().unwrap()];
+        let server = Server::bind(config, Petstore::new(), &core.handle()).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        core.handle().spawn(server.run().map_err(|e| panic!("{}", e)));
+        Harness {
+            client: Client::new(&core.handle()),
+            base: format!("http://{}", addr),
+            spec: serde_json::from_str(SPEC).unwrap(),
+            core,
+        }
+    }
+
+    /// Send a request to `path`, an instance of the spec path `template`, and check the response
+    /// against the operation described by the spec.
+    fn call(&mut self, method: Method, template: &str, path: &str, body: Option<(&str, String)>) -> Exchange {
+        self.call_with(method, template, path, Headers::new(), body)
+    }
+
+    fn call_with(
+        &mut self,
+        method: Method,
+        template: &str,
+        path: &str,
+        headers: Headers,
+        body: Option<(&str, String)>,
+    ) -> Exchange {
+        let operation = self.spec["paths"][template][method.as_ref().to_lowercase()].clone();
+        assert!(operation.is_object(), "{} {} is not in the spec", method, template);
+
+        let mut request = Request::new(method.clone(), format!("{}{}", self.base, path).parse().unwrap());
+        request.headers_mut().extend(headers.iter());
+        if let Some((content_type, body)) = body {
+            request.headers_mut().set(ContentType(content_type.parse().unwrap()));
+            request.set_body(body);
+        }
+        let exchange = self.core
+            .run(self.client.request(request).and_then(|response| {
+                let status = response.status();
+                let headers = response.headers().clone();
+                response.body().concat2().map(move |body| Exchange {
+                    status,
+                    headers,
+                    body: body.to_vec(),
+                })
+            }))
+            .unwrap();
+
+        let context = format!("{} {} -> {}", method, path, exchange.status);
+        let response = &operation["responses"][exchange.status.as_u16().to_string()];
+        assert!(response.is_object(), "{}: the status is not in the spec", context);
+        if let Some(headers) = response["headers"].as_object() {
+            for name in headers.keys() {
+                assert!(exchange.headers.get_raw(name).is_some(), "{}: missing {}", context, name);
+            }
+        }
+        if response["schema"].is_object() {
+            let value: Value = serde_json::from_slice(&exchange.body)
+                .unwrap_or_else(|e| panic!("{}: the body is not JSON: {}", context, e));
+            let errors = validate(&self.spec, &response["schema"], &value, "$");
+            assert!(errors.is_empty(), "{}: {:?}", context, errors);
+        }
+        exchange
+    }
+}
+
+/// The mismatches of `value` with `schema`, a subset of the JSON schema of Swagger 2.0.
+///
+/// `null` is accepted in place of any optional property, as the models are serialized this way.
+fn validate(spec: &Value, schema: &Value, value: &Value, at: &str) -> Vec<String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_left_matches("#/definitions/");
+        return validate(spec, &spec["definitions"][name], value, at);
+    }
+    let mut errors = vec![];
+    let valid = match schema["type"].as_str() {
+        Some("object") => value.is_object(),
+        Some("array") => value.is_array(),
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_u64() || value.is_i64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        _ => true,
+    };
+    if !valid {
+        errors.push(format!("{}: expected {}, found {}", at, schema["type"], value));
+        return errors;
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        if !values.contains(value) {
+            errors.push(format!("{}: {} is not one of {:?}", at, value, values));
+        }
+    }
+    if let Some(items) = value.as_array() {
+        for (i, item) in items.iter().enumerate() {
+            errors.extend(validate(spec, &schema["items"], item, &format!("{}[{}]", at, i)));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        let required = schema["required"].as_array().cloned().unwrap_or_default();
+        for name in &required {
+            let name = name.as_str().unwrap();
+            if object.get(name).map_or(true, Value::is_null) {
+                errors.push(format!("{}: missing the property {}", at, name));
+            }
+        }
+        if let Some(properties) = schema["properties"].as_object() {
+            for (name, value) in object {
+                match properties.get(name) {
+                    Some(_) if value.is_null() => {}
+                    Some(property) => errors.extend(validate(spec, property, value, &format!("{}.{}", at, name))),
+                    None => errors.push(format!("{}: unknown property {}", at, name)),
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// The path with its parameters blanked out, to compare the route table with the spec.
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .map(|segment| if segment.starts_with('{') { "{}" } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[test]
+fn test_spec_covers_routes() {
+    let spec: Value = serde_json::from_str(SPEC).unwrap();
+    let paths = spec["paths"].as_object().unwrap();
+    for route in routes::routes() {
+        let operation = paths
+            .iter()
+            .find(|&(path, _)| normalize(path) == normalize(route.path))
+            .and_then(|(_, operations)| operations.get(route.method.as_ref().to_lowercase()));
+        assert!(operation.is_some(), "{} {} is not in the spec", route.method, route.path);
+    }
+}
+
+/// The operations of the published Swagger Petstore (v2), which are all served with the same
+/// method and path, except those which are documented deviations.
+const UPSTREAM_OPERATIONS: &[(&str, &str, &str)] = &[
+    ("addPet", "post", "/pet"),
+    ("updatePet", "put", "/pet"),
+    ("findPetsByStatus", "get", "/pet/findByStatus"),
+    ("findPetsByTags", "get", "/pet/findByTags"),
+    ("getPetById", "get", "/pet/{petId}"),
+    ("updatePetWithForm", "post", "/pet/{petId}"),
+    ("deletePet", "delete", "/pet/{petId}"),
+    ("uploadFile", "post", "/pet/{petId}/uploadImage"),
+    ("getInventory", "get", "/store/inventory"),
+    ("placeOrder", "post", "/store/order"),
+    ("getOrderById", "get", "/store/order/{orderId}"),
+    ("deleteOrder", "delete", "/store/order/{orderId}"),
+    ("createUser", "post", "/user"),
+    ("createUsersWithArrayInput", "post", "/user/createWithArray"),
+    ("createUsersWithListInput", "post", "/user/createWithList"),
+    ("loginUser", "get", "/user/login"),
+    ("logoutUser", "get", "/user/logout"),
+    ("getUserByName", "get", "/user/{username}"),
+    ("updateUser", "put", "/user/{username}"),
+    ("deleteUser", "delete", "/user/{username}"),
+];
+
+/// The properties of the published models, in camel case.
+const UPSTREAM_PROPERTIES: &[(&str, &[&str])] = &[
+    ("Pet", &["id", "category", "name", "photoUrls", "tags", "status"]),
+    ("Category", &["id", "name"]),
+    ("Tag", &["id", "name"]),
+    ("Order", &["id", "petId", "quantity", "shipDate", "status", "complete"]),
+    ("User", &["id", "username", "firstName", "lastName", "email", "password", "phone", "userStatus"]),
+];
+
+/// The deviations listed in the description of the spec.
+const DEVIATIONS: &[&str] = &["uploadFile", "userStatus"];
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for c in name.chars() {
+        if c.is_uppercase() {
+            snake.push('_');
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[test]
+fn test_spec_follows_upstream() {
+    let spec: Value = serde_json::from_str(SPEC).unwrap();
+    let description = spec["info"]["description"].as_str().unwrap();
+    for &(id, method, path) in UPSTREAM_OPERATIONS {
+        if DEVIATIONS.contains(&id) {
+            assert!(description.contains(path), "{} is not documented as a deviation", id);
+            assert!(spec["paths"][path][method].is_null(), "{} is served but documented as a deviation", id);
+            continue;
+        }
+        assert_eq!(spec["paths"][path][method]["operationId"], json!(id), "{} {}", method, path);
+    }
+    for &(model, properties) in UPSTREAM_PROPERTIES {
+        for property in properties {
+            let served = !spec["definitions"][model]["properties"][snake_case(property)].is_null();
+            if DEVIATIONS.contains(property) {
+                assert!(description.contains(property), "{}.{} is not documented as a deviation", model, property);
+                assert!(!served, "{}.{} is served but documented as a deviation", model, property);
+            } else {
+                assert!(served, "{}.{} is missing", model, property);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pets() {
+    use hyper::Method::*;
+    let mut h = Harness::start();
+    let doggie = json!({"name": "doggie", "photo_urls": [], "tags": [{"name": "cute"}], "status": "available"});
+
+    let created = h.call(Post, "/pet", "/pet", Some(("application/json", doggie.to_string())));
+    assert_eq!(created.status, StatusCode::Created);
+    let id = created.json().as_u64().unwrap();
+
+    let pet = h.call(Get, "/pet/{petId}", &format!("/pet/{}", id), None);
+    assert_eq!(pet.status, StatusCode::Ok);
+    assert_eq!(pet.json()["name"], "doggie");
+
+    let mut updated = pet.json();
+    updated["status"] = json!("pending");
+    let updated = h.call(Put, "/pet", "/pet", Some(("application/json", updated.to_string())));
+    assert_eq!(updated.status, StatusCode::Ok);
+
+    let form = h.call(
+        Post,
+        "/pet/{petId}",
+        &format!("/pet/{}", id),
+        Some(("application/x-www-form-urlencoded", "name=Rex".into())),
+    );
+    assert_eq!(form.json()["name"], "Rex");
+
+    let patched = h.call(
+        Patch,
+        "/pet/{petId}",
+        &format!("/pet/{}", id),
+        Some(("application/merge-patch+json", r#"{"status":"adopted"}"#.into())),
+    );
+    assert_eq!(patched.json()["status"], "adopted");
+    let unsupported = h.call(
+        Patch,
+        "/pet/{petId}",
+        &format!("/pet/{}", id),
+        Some(("application/json", r#"{"status":"pending"}"#.into())),
+    );
+    assert_eq!(unsupported.status, StatusCode::UnsupportedMediaType);
+
+    let found = h.call(Get, "/pet/findByStatus", "/pet/findByStatus?status=adopted", None);
+    assert_eq!(found.json().as_array().unwrap().len(), 1);
+    h.call(Get, "/pet/findByTags", "/pet/findByTags?tags=cute", None);
+    let uri = "/pet/findByTags?tags=cute&created_at_to=2000-01-01T00:00:00Z&sort=-created_at";
+    let old = h.call(Get, "/pet/findByTags", uri, None);
+    assert_eq!(old.json(), json!([]));
+
+    let imported = h.call(
+        Post,
+        "/pet/bulk",
+        "/pet/bulk",
+        Some(("application/x-ndjson", format!("{}\n{{}}\n", doggie))),
+    );
+    let rows = imported.json();
+    assert!(rows[0]["id"].is_u64() && rows[1]["error"].is_string());
+
+    let exported = h.call(Get, "/pet/export", "/pet/export?format=ndjson", None);
+    assert_eq!(exported.status, StatusCode::Ok);
+    assert_eq!(exported.body.split(|&b| b == b'\n').filter(|l| !l.is_empty()).count(), 2);
+
+    let deleted = h.call(Delete, "/pet/{petId}", &format!("/pet/{}", id), None);
+    assert_eq!(deleted.status, StatusCode::NoContent);
+    let missing = h.call(Get, "/pet/{petId}", &format!("/pet/{}", id), None);
+    assert_eq!(missing.status, StatusCode::NotFound);
+
+    let trash = h.call(Get, "/trash", "/trash", None);
+    assert_eq!(trash.json()["pets"][0]["id"], json!(id));
+    assert!(trash.json()["pets"][0]["deleted_at"].is_string());
+    let restore = format!("/pet/{}/restore", id);
+    let restored = h.call(Post, "/pet/{petId}/restore", &restore, None);
+    assert_eq!(restored.json()["name"], "Rex");
+    let pet = h.call(Get, "/pet/{petId}", &format!("/pet/{}", id), None);
+    assert_eq!(pet.status, StatusCode::Ok);
+    let not_deleted = h.call(Post, "/pet/{petId}/restore", &restore, None);
+    assert_eq!(not_deleted.status, StatusCode::NotFound);
+}
+
+#[test]
+fn test_store() {
+    use hyper::Method::*;
+    let mut h = Harness::start();
+
+    let order = json!({"pet_id": 0, "quantity": 1, "status": "placed", "complete": false});
+    let created = h.call(Post, "/store/order", "/store/order", Some(("application/json", order.to_string())));
+    assert_eq!(created.status, StatusCode::Created);
+    let id = created.json().as_u64().unwrap();
+
+    let order = h.call(Get, "/store/order/{orderId}", &format!("/store/order/{}", id), None);
+    assert_eq!(order.json()["status"], "placed");
+    assert_eq!(order.json()["created_at"], order.json()["updated_at"]);
+    let invalid = json!({"pet_id": 0, "ship_date": "next tuesday"});
+    let rejected = h.call(Post, "/store/order", "/store/order", Some(("application/json", invalid.to_string())));
+    assert_eq!(rejected.status, StatusCode::BadRequest);
+    let orders = h.call(Get, "/store/order", "/store/order?status=placed&limit=10", None);
+    assert_eq!(orders.json().as_array().unwrap().len(), 1);
+    h.call(Get, "/store/inventory", "/store/inventory", None);
+    h.call(Get, "/store/inventory", "/store/inventory?by=category", None);
+
+    let delivered = json!({"pet_id": 0, "status": "delivered", "ship_date": "2018-03-02T10:00:00Z"});
+    h.call(Post, "/store/order", "/store/order", Some(("application/json", delivered.to_string())));
+    let report = h.call(
+        Get,
+        "/store/reports/adoptions",
+        "/store/reports/adoptions?granularity=month&from=2018-01-01T00:00:00Z&to=2018-04-01T00:00:00Z",
+        None,
+    );
+    let adoptions: Vec<_> = report.json().as_array().unwrap().iter().map(|c| c["adoptions"].clone()).collect();
+    assert_eq!(adoptions, vec![json!(0), json!(0), json!(1)]);
+
+    let check = h.call(Get, "/store/check", "/store/check", None);
+    assert_eq!(check.json()[0], format!("order {} refers to the missing pet 0", id));
+
+    let deleted = h.call(Delete, "/store/order/{orderId}", &format!("/store/order/{}", id), None);
+    assert_eq!(deleted.json(), json!(true));
+    let missing = h.call(Get, "/store/order/{orderId}", &format!("/store/order/{}", id), None);
+    assert_eq!(missing.status, StatusCode::NotFound);
+}
+
+#[test]
+fn test_users() {
+    use hyper::Method::*;
+    let mut h = Harness::start();
+    let user = |name: &str| json!({"username": name, "password": "secret", "email": format!("{}@example.com", name)});
+
+    let created = h.call(Post, "/user", "/user", Some(("application/json", user("alice").to_string())));
+    assert_eq!(created.json(), json!("alice"));
+    let many = json!([user("bob"), user("carol")]).to_string();
+    h.call(Post, "/user/createWithList", "/user/createWithList", Some(("application/json", many)));
+    let many = json!([user("dave")]).to_string();
+    h.call(Post, "/user/createWithArray", "/user/createWithArray", Some(("application/json", many)));
+
+    let alice = h.call(Get, "/user/{username}", "/user/alice", None);
+    assert_eq!(alice.json()["email"], "alice@example.com");
+    let users = h.call(Get, "/user", "/user?offset=1&limit=2", None);
+    assert_eq!(users.headers.get_raw("X-Total-Count").unwrap().one(), Some(&b"4"[..]));
+    assert_eq!(users.json()[0]["username"], "bob");
+    assert!(users.json()[0].get("password").is_none());
+
+    let login = h.call(Get, "/user/login", "/user/login?username=alice&password=secret", None);
+    assert_eq!(login.status, StatusCode::Ok);
+    let rejected = h.call(Get, "/user/login", "/user/login?username=alice&password=wrong", None);
+    assert_eq!(rejected.status, StatusCode::BadRequest);
+    let mut session = Headers::new();
+    session.set_raw("Authorization", format!("Bearer {}", login.json().as_str().unwrap()));
+
+    let mut updated = user("alice");
+    updated["phone"] = json!("+1-555-0100");
+    let anonymous = h.call(Put, "/user", "/user", Some(("application/json", updated.to_string())));
+    assert_eq!(anonymous.status, StatusCode::Unauthorized);
+    h.call_with(Put, "/user", "/user", session.clone(), Some(("application/json", updated.to_string())));
+    updated["first_name"] = json!("Al");
+    let replaced = h.call_with(
+        Put,
+        "/user/{username}",
+        "/user/alice",
+        session.clone(),
+        Some(("application/json", updated.to_string())),
+    );
+    assert_eq!(replaced.json()["first_name"], "Al");
+    h.call_with(
+        Patch,
+        "/user/{username}",
+        "/user/alice",
+        session.clone(),
+        Some(("application/merge-patch+json", r#"{"first_name":"Alice"}"#.into())),
+    );
+    let forbidden = h.call_with(Delete, "/user/{username}", "/user/bob", session.clone(), None);
+    assert_eq!(forbidden.status, StatusCode::Forbidden);
+
+    let order = json!({"pet_id": 0}).to_string();
+    let placed = h.call_with(Post, "/store/order", "/store/order", session.clone(), Some(("application/json", order)));
+    assert_eq!(placed.status, StatusCode::Created);
+    let anonymous = h.call(Get, "/user/{username}/orders", "/user/alice/orders", None);
+    assert_eq!(anonymous.status, StatusCode::Unauthorized);
+    let orders = h.call_with(Get, "/user/{username}/orders", "/user/alice/orders", session.clone(), None);
+    assert_eq!(orders.json()[0]["username"], "alice");
+    // Anyone can list the orders, but not see who placed them.
+    let public = h.call(Get, "/store/order", "/store/order", None);
+    assert!(public.json()[0].get("username").is_none());
+    let id = orders.json()[0]["id"].as_u64().unwrap();
+    let public = h.call(Get, "/store/order/{orderId}", &format!("/store/order/{}", id), None);
+    assert!(public.json().get("username").is_none());
+
+    let deleted = h.call_with(Delete, "/user/{username}", "/user/alice", session.clone(), None);
+    assert_eq!(deleted.status, StatusCode::NoContent);
+    let missing = h.call(Get, "/user/{username}", "/user/alice", None);
+    assert_eq!(missing.status, StatusCode::NotFound);
+    // Deleting a user closes their sessions.
+    let closed = h.call_with(Delete, "/user/{username}", "/user/alice", session.clone(), None);
+    assert_eq!(closed.status, StatusCode::Unauthorized);
+
+    let logout = h.call_with(Get, "/user/logout", "/user/logout", session, None);
+    assert_eq!(logout.status, StatusCode::NoContent);
+
+    let trash = h.call(Get, "/trash", "/trash", None);
+    assert_eq!(trash.json()["users"][0]["username"], "alice");
+    assert!(trash.json()["users"][0].get("password").is_none());
+    let empty = h.call(Get, "/trash", "/trash?offset=0&limit=0", None);
+    assert_eq!(empty.json(), json!({"pets": [], "users": []}));
+    assert_eq!(empty.headers.get_raw("X-Total-Count"), trash.headers.get_raw("X-Total-Count"));
+}
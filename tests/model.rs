@@ -0,0 +1,334 @@
+//! Random sequences of operations on a `Petstore`, checked against a simple model of it.
+
+extern crate chrono;
+#[macro_use]
+extern crate quickcheck;
+extern crate petstore;
+
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::{Duration, Utc};
+use quickcheck::{Arbitrary, Gen, TestResult};
+use petstore::model::{Order, Pet, Status, Tag, User};
+use petstore::petstore::Petstore;
+
+// Small pools, so that operations often refer to existing entities.
+const NAMES: &'static [&'static str] = &["alice", "bob", "carol", "dave", "erin"];
+const TAGS: &'static [&'static str] = &["friendly", "small", "trained"];
+const MAX_ID: u64 = 8;
+
+#[derive(Debug, Clone)]
+enum Op {
+    AddPet(Option<Status>, Option<&'static str>),
+    UpdatePet(u64, Option<Status>),
+    UpdatePetNameStatus(u64, Option<Status>),
+    DeletePet(u64),
+    RestorePet(u64),
+    FindPetsByStatus(Vec<Status>),
+    FindPetsByTag(&'static str),
+    AddOrder(u64),
+    DeleteOrder(u64),
+    AddUser(&'static str),
+    UpdateUser(&'static str),
+    DeleteUser(&'static str),
+    RestoreUser(&'static str),
+    Login(&'static str),
+    PurgeTrash,
+}
+
+fn status<G: Gen>(g: &mut G) -> Option<Status> {
+    match g.gen_range(0, 4) {
+        0 => None,
+        1 => Some(Status::Available),
+        2 => Some(Status::Pending),
+        _ => Some(Status::Adopted),
+    }
+}
+
+impl Arbitrary for Op {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let id = g.gen_range(0, MAX_ID);
+        let name = *g.choose(NAMES).unwrap();
+        match g.gen_range(0, 15) {
+            0 => {
+                let tag = if g.gen() { g.choose(TAGS).cloned() } else { None };
+                Op::AddPet(status(g), tag)
+            }
+            1 => Op::UpdatePet(id, status(g)),
+            2 => Op::UpdatePetNameStatus(id, status(g)),
+            3 => Op::DeletePet(id),
+            4 => Op::FindPetsByStatus((0..g.gen_range(0, 3)).filter_map(|_| status(g)).collect()),
+            5 => Op::FindPetsByTag(*g.choose(TAGS).unwrap()),
+            6 => Op::AddOrder(id),
+            7 => Op::DeleteOrder(id),
+            8 => Op::AddUser(name),
+            9 => Op::UpdateUser(name),
+            10 => Op::DeleteUser(name),
+            11 => Op::RestorePet(id),
+            12 => Op::RestoreUser(name),
+            13 => Op::PurgeTrash,
+            _ => Op::Login(name),
+        }
+    }
+}
+
+/// What the store should contain.
+#[derive(Debug, Default)]
+struct Model {
+    pets: BTreeMap<u64, (Option<Status>, Option<&'static str>)>,
+    /// The pet of each order.
+    orders: BTreeMap<u64, u64>,
+    users: BTreeSet<&'static str>,
+    deleted_pets: BTreeMap<u64, (Option<Status>, Option<&'static str>)>,
+    deleted_users: BTreeSet<&'static str>,
+}
+
+fn next_id<'a, I: Iterator<Item = &'a u64>>(ids: I) -> u64 {
+    ids.max().map_or(0, |id| id + 1)
+}
+
+fn pet(status: Option<Status>, tag: Option<&str>) -> Pet {
+    Pet {
+        id: None,
+        name: "doggie".into(),
+        photo_urls: vec![],
+        category: None,
+        tags: tag.map(|name| {
+            vec![Tag {
+                id: None,
+                name: name.into(),
+            }]
+        }),
+        status,
+        created_at: None,
+        updated_at: None,
+        deleted_at: None,
+        version: 0,
+    }
+}
+
+fn user(name: &str) -> User {
+    User {
+        id: None,
+        username: name.into(),
+        first_name: None,
+        last_name: None,
+        email: None,
+        password: "secret".into(),
+        phone: None,
+        created_at: None,
+        updated_at: None,
+        deleted_at: None,
+        version: 0,
+    }
+}
+
+fn ensure(cond: bool, msg: String) -> Result<(), String> {
+    if cond {
+        Ok(())
+    } else {
+        Err(msg)
+    }
+}
+
+/// Apply `op` to both the store and the model, and compare the outcome.
+fn step(store: &Petstore, model: &mut Model, op: &Op) -> Result<(), String> {
+    match *op {
+        Op::AddPet(status, tag) => {
+            let expected = next_id(model.pets.keys().chain(model.deleted_pets.keys()));
+            let id = store.add_pet(pet(status, tag)).map_err(|e| e.to_string())?;
+            ensure(id == expected, format!("pet added with the ID {}, not {}", id, expected))?;
+            model.pets.insert(id, (status, tag));
+        }
+        Op::UpdatePet(id, status) => {
+            let mut new = pet(status, None);
+            new.id = Some(id);
+            let result = store.update_pet(new, None);
+            ensure(result.is_ok() == model.pets.contains_key(&id), format!("{:?}", result))?;
+            if let Some(entry) = model.pets.get_mut(&id) {
+                *entry = (status, None);
+            }
+        }
+        Op::UpdatePetNameStatus(id, status) => {
+            let result = store.update_pet_name_status(id, Some("renamed".into()), status, None);
+            ensure(result.is_ok() == model.pets.contains_key(&id), format!("{:?}", result))?;
+            if let (Some(entry), Some(status)) = (model.pets.get_mut(&id), status) {
+                entry.0 = Some(status);
+            }
+        }
+        Op::DeletePet(id) => {
+            let result = store.delete_pet(id, None);
+            let deleted = model.pets.remove(&id);
+            ensure(result.is_ok() == deleted.is_some(), format!("{:?}", result))?;
+            if let Some(entry) = deleted {
+                model.deleted_pets.insert(id, entry);
+            }
+        }
+        Op::RestorePet(id) => {
+            let result = store.restore_pet(id);
+            let restored = model.deleted_pets.remove(&id);
+            ensure(result.is_ok() == restored.is_some(), format!("{:?}", result))?;
+            if let Some(entry) = restored {
+                model.pets.insert(id, entry);
+            }
+        }
+        Op::FindPetsByStatus(ref statuses) => {
+            let found: Vec<_> = store
+                .get_pets_by_status(statuses.clone(), &Default::default())
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|pet| pet.id)
+                .collect();
+            let expected: Vec<_> = model
+                .pets
+                .iter()
+                .filter(|&(_, &(status, _))| status.map_or(true, |s| statuses.contains(&s)))
+                .map(|(id, _)| Some(*id))
+                .collect();
+            ensure(found == expected, format!("found {:?}, expected {:?}", found, expected))?;
+        }
+        Op::FindPetsByTag(tag) => {
+            let found: Vec<_> = store
+                .find_pets_by_tag(vec![tag.into()], &Default::default())
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|pet| pet.id)
+                .collect();
+            let expected: Vec<_> = model
+                .pets
+                .iter()
+                .filter(|&(_, &(_, t))| t == Some(tag))
+                .map(|(id, _)| Some(*id))
+                .collect();
+            ensure(found == expected, format!("found {:?}, expected {:?}", found, expected))?;
+        }
+        Op::AddOrder(pet_id) => {
+            let expected = next_id(model.orders.keys());
+            let order = Order {
+                id: None,
+                pet_id: Some(pet_id),
+                quantity: Some(1),
+                ship_date: None,
+                status: None,
+                complete: None,
+                username: None,
+                created_at: None,
+                updated_at: None,
+                version: 0,
+            };
+            let id = store.add_order(order).map_err(|e| e.to_string())?;
+            ensure(id == expected, format!("order added with the ID {}, not {}", id, expected))?;
+            model.orders.insert(id, pet_id);
+        }
+        Op::DeleteOrder(id) => {
+            let deleted = store.delete_order(id, None).map_err(|e| e.to_string())?;
+            let expected = model.orders.remove(&id).is_some();
+            ensure(deleted == expected, format!("order {} deleted: {}", id, deleted))?;
+        }
+        Op::AddUser(name) => {
+            // The names of deleted users stay taken until they are purged.
+            let result = store.add_user(user(name));
+            let added = !model.deleted_users.contains(name) && model.users.insert(name);
+            ensure(result.is_ok() == added, format!("{:?}", result))?;
+        }
+        Op::UpdateUser(name) => {
+            let result = store.update_user(user(name), None);
+            ensure(result.is_ok() == model.users.contains(name), format!("{:?}", result))?;
+        }
+        Op::DeleteUser(name) => {
+            let result = store.delete_user(name.into(), None);
+            ensure(result.is_ok() == model.users.remove(name), format!("{:?}", result))?;
+            if result.is_ok() {
+                model.deleted_users.insert(name);
+            }
+        }
+        Op::RestoreUser(name) => {
+            let result = store.restore_user(name.into());
+            ensure(result.is_ok() == model.deleted_users.remove(name), format!("{:?}", result))?;
+            if result.is_ok() {
+                model.users.insert(name);
+            }
+        }
+        Op::Login(name) => {
+            let result = store.login(name.into(), "secret".into());
+            ensure(result.is_ok() == model.users.contains(name), format!("{:?}", result))?;
+        }
+        Op::PurgeTrash => {
+            let purged = store
+                .purge_trash(Utc::now() + Duration::seconds(1))
+                .map_err(|e| e.to_string())?;
+            let expected = model.deleted_pets.len() + model.deleted_users.len();
+            ensure(purged == expected, format!("purged {} entities, not {}", purged, expected))?;
+            // The orders of the purged pets go with them.
+            let orphans: Vec<u64> = model
+                .orders
+                .iter()
+                .filter(|&(_, pet_id)| model.deleted_pets.contains_key(pet_id))
+                .map(|(id, _)| *id)
+                .collect();
+            for id in orphans {
+                model.orders.remove(&id);
+            }
+            model.deleted_pets.clear();
+            model.deleted_users.clear();
+        }
+    }
+    Ok(())
+}
+
+/// Check the invariants which hold after any operation.
+fn check(store: &Petstore, model: &Model) -> Result<(), String> {
+    // Orders may refer to missing or deleted pets, which the API allows.
+    let problems: Vec<_> = store
+        .check_consistency()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|problem| !problem.contains("refers to the missing pet"))
+        .filter(|problem| !problem.contains("refers to the deleted pet"))
+        .collect();
+    ensure(problems.is_empty(), format!("{:?}", problems))?;
+
+    let inventory = store.get_inventory().map_err(|e| e.to_string())?;
+    let count = |status| model.pets.values().filter(|&&(s, _)| s == Some(status)).count() as u32;
+    ensure(
+        (inventory.available, inventory.pending, inventory.adopted)
+            == (count(Status::Available), count(Status::Pending), count(Status::Adopted)),
+        format!("inventory {:?}", inventory),
+    )?;
+
+    let orders: BTreeSet<_> = store
+        .list_orders()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter_map(|order| order.id)
+        .collect();
+    ensure(orders.iter().eq(model.orders.keys()), format!("orders {:?}", orders))?;
+
+    let users = store.list_users().map_err(|e| e.to_string())?;
+    let usernames: BTreeSet<_> = users.iter().map(|user| &*user.username).collect();
+    ensure(
+        usernames.len() == users.len() && usernames == model.users,
+        format!("users {:?}", usernames),
+    )?;
+
+    let (_, trash) = store.trash(0, None).map_err(|e| e.to_string())?;
+    let pets: Vec<_> = trash.pets.iter().filter_map(|pet| pet.id).collect();
+    let users: BTreeSet<_> = trash.users.iter().map(|user| &*user.username).collect();
+    ensure(
+        pets.iter().eq(model.deleted_pets.keys()) && users == model.deleted_users,
+        format!("trash {:?} {:?}", pets, users),
+    )?;
+    Ok(())
+}
+
+quickcheck! {
+    fn prop_store_matches_model(ops: Vec<Op>) -> TestResult {
+        let store = Petstore::new();
+        let mut model = Model::default();
+        for op in &ops {
+            if let Err(e) = step(&store, &mut model, op).and_then(|_| check(&store, &model)) {
+                return TestResult::error(format!("after {:?}: {}", op, e));
+            }
+        }
+        TestResult::passed()
+    }
+}